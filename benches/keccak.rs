@@ -0,0 +1,50 @@
+//! Keccak-256 backends hashing a full slice of headers, as the headers stage does for every
+//! response. Run with `--features tiny-keccak` to include that backend.
+
+use akula::{crypto::*, models::*};
+use criterion::*;
+use ethereum_types::{Bloom, H160};
+
+fn header_slice() -> Vec<Vec<u8>> {
+    let mut parent_hash = H256::zero();
+    (1..=1024_u64)
+        .map(|number| {
+            let header = BlockHeader {
+                parent_hash,
+                beneficiary: H160::from_low_u64_be(number),
+                logs_bloom: Bloom::repeat_byte(number as u8),
+                number: BlockNumber(number),
+                gas_limit: 30_000_000,
+                gas_used: 15_000_000,
+                timestamp: number * 12,
+                extra_data: vec![0xab; 32].into(),
+                ..Default::default()
+            };
+            parent_hash = header.hash();
+
+            let mut out = Vec::new();
+            fastrlp::Encodable::encode(&header, &mut out);
+            out
+        })
+        .collect()
+}
+
+fn hash_slice<K: KeccakBackend>(slice: &[Vec<u8>]) -> Vec<H256> {
+    slice.iter().map(|header| K::keccak256(header)).collect()
+}
+
+pub fn benchmark_keccak(c: &mut Criterion) {
+    let slice = header_slice();
+
+    let mut group = c.benchmark_group("keccak-header-slice");
+    group.throughput(Throughput::Elements(slice.len() as u64));
+    group.bench_function("sha3", |b| b.iter(|| hash_slice::<Sha3Keccak>(&slice)));
+    #[cfg(feature = "tiny-keccak")]
+    group.bench_function("tiny-keccak", |b| {
+        b.iter(|| hash_slice::<TinyKeccak>(&slice))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, benchmark_keccak);
+criterion_main!(benches);
@@ -48,7 +48,14 @@ async fn main() -> anyhow::Result<()> {
         ChainSpec::load_builtin(&opts.chain)?.p2p
     };
 
-    let swarm = akula::sentry::run(opts.sentry_opts, opts.data_dir, network_params).await?;
+    let swarm = akula::sentry::run(
+        opts.sentry_opts,
+        opts.data_dir,
+        network_params,
+        Default::default(),
+        Default::default(),
+    )
+    .await?;
 
     loop {
         info!(
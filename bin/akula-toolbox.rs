@@ -111,6 +111,38 @@ pub enum OptCommand {
             default_value = "http://localhost:8000"
         )]
         uri: tonic::transport::Uri,
+
+        /// Instead of syncing, download the saved headers from this block on again and verify
+        /// them, without saving anything
+        #[clap(long, requires = "dry_run_to")]
+        dry_run_from: Option<BlockNumber>,
+        /// Last block of the dry run
+        #[clap(long, requires = "dry_run_from")]
+        dry_run_to: Option<BlockNumber>,
+    },
+
+    /// Re-verify headers saved in the database
+    VerifyHeaders {
+        #[clap(long)]
+        from: BlockNumber,
+        #[clap(long)]
+        to: BlockNumber,
+    },
+
+    /// Export saved canonical headers to a file
+    ExportHeaders {
+        #[clap(long)]
+        from: BlockNumber,
+        #[clap(long)]
+        to: BlockNumber,
+        /// Output format: rlp, readable by akula --headers-file, or json, one header per line
+        #[clap(long, default_value = "rlp")]
+        format: HeaderExportFormat,
+        /// Leave out blocks without a saved header instead of failing
+        #[clap(long)]
+        skip_gaps: bool,
+        #[clap(long)]
+        output: ExpandedPathBuf,
     },
 
     ReadBlock {
@@ -160,6 +192,7 @@ async fn download_headers(
     data_dir: AkulaDataDir,
     chain: String,
     uri: tonic::transport::Uri,
+    dry_run: Option<BlockNumberRange>,
 ) -> anyhow::Result<()> {
     let chain_config = ChainConfig::new(chain.as_ref())?;
 
@@ -195,16 +228,16 @@ async fn download_headers(
         }
     });
 
+    let stage = HeaderDownload::new(node, consensus, Default::default())?;
+
+    if let Some(range) = dry_run {
+        let report = stage.dry_run(&env.begin()?, range).await?;
+        ensure!(report.is_clean(), "{report}");
+        return Ok(());
+    }
+
     let mut staged_sync = stagedsync::StagedSync::new();
-    staged_sync.push(
-        HeaderDownload {
-            node,
-            consensus,
-            max_block: u64::MAX.into(),
-            increment: None,
-        },
-        false,
-    );
+    staged_sync.push(stage, false);
     staged_sync.run(&env).await?;
 
     Ok(())
@@ -235,6 +268,69 @@ async fn blockhashes(data_dir: AkulaDataDir) -> anyhow::Result<()> {
     staged_sync.run(&env).await?;
     Ok(())
 }
+
+fn verify_headers(
+    data_dir: AkulaDataDir,
+    from: BlockNumber,
+    to: BlockNumber,
+) -> anyhow::Result<()> {
+    let env = open_db(data_dir)?;
+    let tx = env.begin()?;
+
+    let chainspec = tx
+        .get(tables::Config, ())?
+        .ok_or_else(|| format_err!("no chainspec in database"))?;
+    let config = ChainConfig::from(chainspec.clone());
+    let replayer = HeaderReplayer {
+        consensus: engine_factory(None, chainspec, None)?.into(),
+        size_limits: HeaderSizeLimits::for_chain(&config),
+        config,
+    };
+
+    let (mut valid, mut invalid, mut missing) = (0_usize, 0_usize, 0_usize);
+    for result in replayer.replay(&tx, BlockNumberRange::new(from, to)) {
+        match result? {
+            ReplayedHeader::Valid { .. } => valid += 1,
+            ReplayedHeader::Invalid {
+                number,
+                hash,
+                error,
+            } => {
+                invalid += 1;
+                println!("#{number}/{hash:?}: {error:?}");
+            }
+            ReplayedHeader::Missing(number) => {
+                missing += 1;
+                warn!("No saved header for block #{number}");
+            }
+        }
+    }
+
+    println!("Verified headers #{from}-#{to}: {valid} valid, {invalid} invalid, {missing} missing");
+
+    Ok(())
+}
+
+fn export_headers(
+    data_dir: AkulaDataDir,
+    range: BlockNumberRange,
+    format: HeaderExportFormat,
+    skip_gaps: bool,
+    output: &std::path::Path,
+) -> anyhow::Result<()> {
+    let env = open_db(data_dir)?;
+    let tx = env.begin()?;
+
+    let writer = std::io::BufWriter::new(
+        std::fs::File::create(output)
+            .with_context(|| format!("failed to create {}", output.display()))?,
+    );
+    let exported = akula::stages::export_headers(&tx, range, format, skip_gaps, writer)?;
+    println!("Exported {exported} headers to {}", output.display());
+
+    Ok(())
+}
+
 fn open_db(
     data_dir: AkulaDataDir,
 ) -> anyhow::Result<akula::kv::mdbx::MdbxEnvironment<mdbx::NoWriteMap>> {
@@ -728,8 +824,16 @@ async fn main() -> anyhow::Result<()> {
     match opt.command {
         OptCommand::DbStats { csv } => table_sizes(opt.data_dir, csv)?,
         OptCommand::Blockhashes => blockhashes(opt.data_dir).await?,
-        OptCommand::HeaderDownload { chain, uri } => {
-            download_headers(opt.data_dir, chain, uri).await?
+        OptCommand::HeaderDownload {
+            chain,
+            uri,
+            dry_run_from,
+            dry_run_to,
+        } => {
+            let dry_run = dry_run_from
+                .zip(dry_run_to)
+                .map(|(from, to)| BlockNumberRange::new(from, to));
+            download_headers(opt.data_dir, chain, uri, dry_run).await?
         }
         OptCommand::DbQuery { table, key } => db_query(opt.data_dir, table, key)?,
         OptCommand::DbWalk {
@@ -741,6 +845,20 @@ async fn main() -> anyhow::Result<()> {
         OptCommand::DbUnset { table, key } => db_set(opt.data_dir, table, key, None)?,
         OptCommand::DbDrop { table } => db_drop(opt.data_dir, table)?,
         OptCommand::CheckEqual { db1, db2, table } => check_table_eq(db1, db2, table)?,
+        OptCommand::VerifyHeaders { from, to } => verify_headers(opt.data_dir, from, to)?,
+        OptCommand::ExportHeaders {
+            from,
+            to,
+            format,
+            skip_gaps,
+            output,
+        } => export_headers(
+            opt.data_dir,
+            BlockNumberRange::new(from, to),
+            format,
+            skip_gaps,
+            &output.0,
+        )?,
         OptCommand::ReadBlock { block_number } => read_block(opt.data_dir, block_number)?,
         OptCommand::ReadAccount {
             address,
@@ -2,9 +2,9 @@ use akula::{
     akula_tracing::{self, Component},
     binutil::AkulaDataDir,
     consensus::{engine_factory, Consensus, ForkChoiceMode},
-    kv::tables::CHAINDATA_TABLES,
+    kv::{tables::CHAINDATA_TABLES, MdbxGeometry},
     models::*,
-    p2p::node::NodeBuilder,
+    p2p::node::{NodeBuilder, RequestRateLimiter},
     rpc::{
         debug::DebugApiServerImpl, erigon::ErigonApiServerImpl, eth::EthApiServerImpl,
         net::NetApiServerImpl, otterscan::OtterscanApiServerImpl, parity::ParityApiServerImpl,
@@ -30,6 +30,7 @@ use std::{
     sync::Arc, time::Duration,
 };
 use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
 use tracing::*;
 use tracing_subscriber::prelude::*;
 
@@ -40,6 +41,22 @@ pub struct Opt {
     #[clap(long, help = "Database directory path", default_value_t)]
     pub datadir: AkulaDataDir,
 
+    /// Size of the database when it is created, e.g. `1TiB`.
+    #[clap(long, default_value = "0B")]
+    pub db_initial_size: bytesize::ByteSize,
+
+    /// Size the database grows by whenever it is full.
+    #[clap(long, default_value = "4GiB")]
+    pub db_growth_step: bytesize::ByteSize,
+
+    /// Size the database may never grow past.
+    #[clap(long, default_value = "4TiB")]
+    pub db_max_size: bytesize::ByteSize,
+
+    /// Database page size, only used when the database is created.
+    #[clap(long)]
+    pub db_page_size: Option<bytesize::ByteSize>,
+
     /// Name of the network to join
     #[clap(long)]
     pub chain: Option<String>,
@@ -48,6 +65,14 @@ pub struct Opt {
     #[clap(long)]
     pub chain_spec_file: Option<ExpandedPathBuf>,
 
+    /// Genesis file in geth format to use
+    #[clap(long)]
+    pub genesis_file: Option<ExpandedPathBuf>,
+
+    /// Skip seal verification of downloaded headers. Refused on public networks.
+    #[clap(long)]
+    pub no_seal_verification: bool,
+
     /// Sentry GRPC service URL
     #[clap(long, help = "Sentry GRPC service URLs as 'http://host:port'")]
     pub sentry_api_addr: Option<String>,
@@ -71,6 +96,127 @@ pub struct Opt {
     #[clap(long)]
     pub increment: Option<BlockNumber>,
 
+    /// Maximum number of headers downloaded per headers stage run.
+    #[clap(long, default_value = "90000", value_parser = clap::value_parser!(u64).range(1..))]
+    pub headers_batch_size: u64,
+
+    /// Maximum number of header requests in flight at once.
+    #[clap(long, default_value = "128", value_parser = clap::value_parser!(u64).range(1..))]
+    pub headers_max_in_flight_requests: u64,
+
+    /// Maximum number of peers asked for headers at once. Uses all peers if unset.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub headers_max_peers: Option<u64>,
+
+    /// Number of blocks to unwind when downloaded headers do not attach to the stored chain.
+    #[clap(long, default_value = "1", value_parser = clap::value_parser!(u64).range(1..))]
+    pub headers_reorg_rewind_depth: u64,
+
+    /// Stop with an error instead of unwinding when downloaded headers do not attach to the
+    /// stored chain.
+    #[clap(long)]
+    pub headers_fail_on_fork: bool,
+
+    /// Timeout for header requests (seconds).
+    #[clap(long, default_value = "5", value_parser = clap::value_parser!(u64).range(1..))]
+    pub headers_request_timeout: u64,
+
+    /// Minimum number of connected peers before header download starts.
+    #[clap(long, default_value = "1")]
+    pub headers_min_peers: usize,
+
+    /// Maximum rate of outgoing header and body requests (requests/second). Unlimited if unset.
+    #[clap(long, value_parser = parse_request_rate)]
+    pub request_rate: Option<f64>,
+
+    /// Number of requests that may be sent at once before `--request-rate` applies.
+    #[clap(long, default_value = "64", value_parser = clap::value_parser!(u32).range(1..))]
+    pub request_burst: u32,
+
+    /// Number of threads verifying header seals. Defaults to the number of cores.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub headers_verification_threads: Option<u64>,
+
+    /// Trusted `<block number>:<block hash>` checkpoint. Headers up to the highest one are not
+    /// checked against consensus rules. Only use checkpoints from sources you trust.
+    #[clap(long, value_parser = parse_checkpoint)]
+    pub trusted_checkpoint: Vec<(BlockNumber, H256)>,
+
+    /// Time limit for a single headers stage run (seconds). Unlimited if unset.
+    #[clap(long)]
+    pub headers_max_duration: Option<u64>,
+
+    /// Fail the headers stage if downloading makes no progress for this long (seconds).
+    /// Waits forever if unset.
+    #[clap(long)]
+    pub headers_stall_timeout: Option<u64>,
+
+    /// Keep up to this many megabytes of downloaded but not yet saved headers on disk, so that
+    /// they are not downloaded again after a restart. Disabled if unset.
+    #[clap(long)]
+    pub headers_spill_size: Option<u64>,
+
+    /// Take headers from this file of concatenated RLP-encoded headers, in ascending block
+    /// order, before asking peers. Headers from the file are verified as usual.
+    #[clap(long)]
+    pub headers_file: Option<ExpandedPathBuf>,
+
+    /// Maximum size of a header response (kilobytes). Larger responses are rejected and the
+    /// peer is penalized.
+    #[clap(long, default_value = "2048")]
+    pub headers_max_response_size: usize,
+
+    /// Maximum size of a single header in a response (bytes).
+    #[clap(long, default_value = "8192")]
+    pub headers_max_header_size: usize,
+
+    /// Keep re-requesting the last headers of a download if peers answer with fewer than
+    /// asked for, instead of taking the chain to end early.
+    #[clap(long)]
+    pub headers_strict_final_slice: bool,
+
+    /// Re-read and re-hash every header after saving it, failing the stage on mismatch.
+    #[clap(long)]
+    pub headers_verify_after_write: bool,
+
+    /// Keep a history of this many header slice status transitions, logged under the
+    /// `akula::stages::headers::audit` target.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub headers_slice_audit: Option<u64>,
+
+    /// Fetch the last header of every slice from a single peer before downloading the slices,
+    /// and reject slices that do not match it.
+    #[clap(long)]
+    pub headers_skeleton: bool,
+
+    /// Save downloaded headers this many slices of 1024 at a time, letting staged sync commit
+    /// in between, instead of a whole batch at once.
+    #[clap(long)]
+    pub headers_commit_every: Option<usize>,
+
+    /// Save downloaded headers this many megabytes at a time. Ignored if
+    /// `--headers-commit-every` is set.
+    #[clap(long)]
+    pub headers_commit_every_size: Option<u64>,
+
+    /// Remember seal verification outcomes of this many slices of headers, so that slices
+    /// received again unchanged are not verified again. 0 to disable.
+    #[clap(long, default_value = "64")]
+    pub headers_seal_cache: usize,
+
+    /// Buffer this many headers per table while saving, writing each table in one sorted pass.
+    #[clap(long, default_value = "1024", value_parser = clap::value_parser!(u64).range(1..))]
+    pub headers_save_batch: u64,
+
+    /// Rebuild the headers stage and restart the cycle up to this many times when saving hits
+    /// a transient database error, such as the database being busy.
+    #[clap(long, default_value = "3")]
+    pub headers_max_restarts: usize,
+
+    /// Append header download progress as newline-delimited JSON to this file.
+    #[clap(long)]
+    pub headers_progress_json: Option<ExpandedPathBuf>,
+
     /// Sender recovery batch size (blocks)
     #[clap(long, default_value = "500000")]
     pub sender_recovery_batch_size: u64,
@@ -119,6 +265,10 @@ pub struct Opt {
     #[clap(long, default_value = "127.0.0.1:7545")]
     pub grpc_listen_address: SocketAddr,
 
+    /// Serve stage metrics for Prometheus at this IP address and port.
+    #[clap(long)]
+    pub metrics_listen_address: Option<SocketAddr>,
+
     /// Enable CL engine RPC at this IP address and port.
     #[clap(long, default_value = "127.0.0.1:8551")]
     pub engine_listen_address: SocketAddr,
@@ -128,6 +278,26 @@ pub struct Opt {
     pub jwt_secret_path: Option<ExpandedPathBuf>,
 }
 
+fn parse_checkpoint(s: &str) -> Result<(BlockNumber, H256), String> {
+    let (number, hash) = s
+        .split_once(':')
+        .ok_or_else(|| "expected <block number>:<block hash>".to_string())?;
+    Ok((
+        number
+            .parse()
+            .map_err(|e| format!("bad block number: {e}"))?,
+        hash.parse().map_err(|e| format!("bad block hash: {e}"))?,
+    ))
+}
+
+fn parse_request_rate(s: &str) -> Result<f64, String> {
+    let rate = s.parse::<f64>().map_err(|e| e.to_string())?;
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err("must be a positive number".to_string());
+    }
+    Ok(rate)
+}
+
 #[allow(unreachable_code)]
 fn main() -> anyhow::Result<()> {
     let opt: Opt = Opt::parse();
@@ -152,6 +322,8 @@ fn main() -> anyhow::Result<()> {
                     Some(ChainSpec::load_builtin(&chain)?)
                 } else if let Some(path) = opt.chain_spec_file {
                     Some(ChainSpec::load_from_file(path)?)
+                } else if let Some(path) = opt.genesis_file {
+                    Some(ChainSpec::from_geth_genesis(std::fs::File::open(path)?)?)
                 } else {
                     None
                 };
@@ -165,9 +337,15 @@ fn main() -> anyhow::Result<()> {
                     tempfile::tempdir_in(&etl_temp_path)
                         .context("failed to create ETL temp dir")?,
                 );
-                let db = Arc::new(akula::kv::new_database(
+                let db = Arc::new(akula::kv::open_database(
                     &CHAINDATA_TABLES,
                     &akula_chain_data_dir,
+                    MdbxGeometry {
+                        initial_size: opt.db_initial_size.as_u64(),
+                        growth_step: Some(opt.db_growth_step.as_u64()),
+                        max_size: opt.db_max_size.as_u64(),
+                        page_size: opt.db_page_size.map(|size| size.as_u64() as usize),
+                    },
                 )?);
 
                 akula::database_version::migrate_database(&db)?;
@@ -220,7 +398,11 @@ fn main() -> anyhow::Result<()> {
 
                 let network_id = chainspec.params.network_id;
 
-                let chain_config = ChainConfig::from(chainspec);
+                let mut chain_config = ChainConfig::from(chainspec);
+                if opt.no_seal_verification {
+                    chain_config = chain_config.without_seal_verification()?;
+                    warn!("Seal verification of headers is disabled");
+                }
 
                 if !opt.no_rpc {
                     tokio::spawn({
@@ -356,6 +538,42 @@ fn main() -> anyhow::Result<()> {
                 staged_sync.start_with_unwind(opt.start_with_unwind);
                 staged_sync.set_exit_after_sync(opt.exit_after_sync);
 
+                if let Some(metrics_listen_address) = opt.metrics_listen_address {
+                    let metrics = Arc::new(stagedsync::metrics::StageMetrics::default());
+                    staged_sync.set_metrics_observer(metrics.clone());
+                    tokio::spawn(async move {
+                        info!("Serving metrics on {metrics_listen_address}");
+                        if let Err(e) = stagedsync::metrics::serve_prometheus(
+                            metrics,
+                            metrics_listen_address,
+                        )
+                        .await
+                        {
+                            error!("Metrics server failed: {e}");
+                        }
+                    });
+                }
+
+                let shutdown_token = CancellationToken::new();
+                tokio::spawn({
+                    let shutdown_token = shutdown_token.clone();
+                    async move {
+                        if tokio::signal::ctrl_c().await.is_ok() {
+                            info!(
+                                "Received interrupt signal, shutting down staged sync. Interrupt again to exit immediately"
+                            );
+                            shutdown_token.cancel();
+
+                            // The handler stays installed, so exiting is up to us.
+                            if tokio::signal::ctrl_c().await.is_ok() {
+                                warn!("Received second interrupt signal, exiting");
+                                std::process::exit(130);
+                            }
+                        }
+                    }
+                });
+                staged_sync.set_shutdown_token(Some(shutdown_token.clone()));
+
                 if opt.delay_after_sync > 0 {
                     staged_sync
                         .set_delay_after_sync(Some(Duration::from_millis(opt.delay_after_sync)));
@@ -373,6 +591,8 @@ fn main() -> anyhow::Result<()> {
                         opt.sentry_opts,
                         opt.datadir,
                         chain_config.chain_spec.p2p.clone(),
+                        Default::default(),
+                        Default::default(),
                     )
                     .await?;
 
@@ -402,6 +622,12 @@ fn main() -> anyhow::Result<()> {
                 for sentry_api_addr in sentries {
                     builder = builder.add_sentry(sentry_api_addr);
                 }
+                if let Some(rate) = opt.request_rate {
+                    builder = builder.set_request_rate_limiter(Arc::new(RequestRateLimiter::new(
+                        rate,
+                        opt.request_burst,
+                    )));
+                }
 
                 let node = Arc::new(builder.build()?);
                 let tip_discovery =
@@ -414,15 +640,115 @@ fn main() -> anyhow::Result<()> {
                     }
                 });
 
-                staged_sync.push(
-                    HeaderDownload {
-                        node: node.clone(),
-                        consensus: consensus.clone(),
-                        max_block: opt.max_block.unwrap_or_else(|| u64::MAX.into()),
-                        increment,
+                let progress_sender = if let Some(path) = &opt.headers_progress_json {
+                    let file = OpenOptions::new().create(true).append(true).open(&path.0)?;
+                    let (sender, receiver) = tokio::sync::broadcast::channel(1024);
+                    std::thread::spawn(move || {
+                        if let Err(e) = JsonProgressReporter::new(file).run(receiver) {
+                            warn!("Failed to write header download progress: {e}");
+                        }
+                    });
+                    Some(sender)
+                } else {
+                    None
+                };
+
+                let trusted_checkpoints = opt
+                    .trusted_checkpoint
+                    .iter()
+                    .copied()
+                    .collect::<std::collections::BTreeMap<_, _>>();
+                if let Some((number, hash)) = trusted_checkpoints.iter().next_back() {
+                    warn!(
+                        "Trusting checkpoint #{number}/{hash:?}, headers up to it will not be verified"
+                    );
+                }
+
+                let verification_pool = opt
+                    .headers_verification_threads
+                    .map(|threads| {
+                        rayon::ThreadPoolBuilder::new()
+                            .num_threads(threads as usize)
+                            .thread_name(|i| format!("header-verification-{i}"))
+                            .build()
+                            .map(Arc::new)
+                    })
+                    .transpose()?;
+
+                let spill = opt.headers_spill_size.map(|size| {
+                    Arc::new(HeaderSpill::new(
+                        opt.datadir.headers_spill(),
+                        size * 1024 * 1024,
+                    ))
+                });
+
+                let header_file = opt
+                    .headers_file
+                    .as_ref()
+                    .map(|path| HeaderFile::open(&path.0).map(Arc::new))
+                    .transpose()?;
+
+                let header_download_options = HeaderDownloadOptions {
+                    max_block: opt.max_block.unwrap_or_else(|| u64::MAX.into()),
+                    increment,
+                    upper_bound: BlockNumber(opt.headers_batch_size),
+                    progress_sender,
+                    max_in_flight_requests: opt.headers_max_in_flight_requests as usize,
+                    max_peers: opt.headers_max_peers.map(|max_peers| max_peers as usize),
+                    fork_policy: if opt.headers_fail_on_fork {
+                        ForkPolicy::FailFast
+                    } else {
+                        ForkPolicy::ReAnchor {
+                            max_rewind: opt.headers_reorg_rewind_depth,
+                        }
+                    },
+                    request_timeout: Duration::from_secs(opt.headers_request_timeout),
+                    min_peers: opt.headers_min_peers,
+                    max_duration: opt.headers_max_duration.map(Duration::from_secs),
+                    stall_timeout: opt.headers_stall_timeout.map(Duration::from_secs),
+                    verification_pool,
+                    trusted_checkpoints,
+                    spill,
+                    header_file,
+                    size_limits: Some(HeaderSizeLimits {
+                        max_header_bytes: opt.headers_max_header_size,
+                        max_response_bytes: opt.headers_max_response_size * 1024,
+                        ..HeaderSizeLimits::for_chain(&node.config)
+                    }),
+                    partial_final_slice: !opt.headers_strict_final_slice,
+                    verify_after_write: opt.headers_verify_after_write,
+                    slice_audit: opt
+                        .headers_slice_audit
+                        .map(|capacity| Arc::new(SliceAuditLog::new(capacity as usize))),
+                    skeleton: opt.headers_skeleton,
+                    commit_every: opt
+                        .headers_commit_every
+                        .map(CommitCadence::Slices)
+                        .or_else(|| {
+                            opt.headers_commit_every_size
+                                .map(|size| CommitCadence::Bytes(size * 1024 * 1024))
+                        }),
+                    seal_cache: (opt.headers_seal_cache > 0).then(|| {
+                        Arc::new(SealVerificationCache::new(opt.headers_seal_cache))
+                    }),
+                    save_batch: opt.headers_save_batch as usize,
+                    ..Default::default()
+                };
+                staged_sync.push_restartable(
+                    {
+                        let node = node.clone();
+                        let consensus = consensus.clone();
+                        move || {
+                            HeaderDownload::new(
+                                node.clone(),
+                                consensus.clone(),
+                                header_download_options.clone(),
+                            )
+                        }
                     },
                     false,
-                );
+                    opt.headers_max_restarts,
+                )?;
                 staged_sync.push(TotalGasIndex, false);
                 staged_sync.push(
                     BlockHashes {
@@ -491,7 +817,7 @@ fn main() -> anyhow::Result<()> {
                 info!("Running staged sync");
                 staged_sync.run(&db).await?;
 
-                if opt.exit_after_sync {
+                if opt.exit_after_sync || shutdown_token.is_cancelled() {
                     Ok(())
                 } else {
                     pending().await
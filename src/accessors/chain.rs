@@ -1,7 +1,8 @@
 use crate::{
-    kv::{mdbx::*, tables},
+    kv::{mdbx::*, tables, traits::*},
     models::*,
 };
+use anyhow::format_err;
 use tracing::*;
 
 pub mod canonical_hash {
@@ -56,6 +57,66 @@ pub mod header {
 
         tx.get(tables::Header, (number, hash))
     }
+
+    /// Whether the canonical header at `number` commits to a non-empty list of ommers, which
+    /// can only be checked once its body is downloaded. `None` if there is no such header.
+    pub fn has_ommers<K: TransactionKind, E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, K, E>,
+        number: impl Into<BlockNumber>,
+    ) -> anyhow::Result<Option<bool>> {
+        let number = number.into();
+        if let Some(hash) = super::canonical_hash::read(tx, number)? {
+            return Ok(read(tx, hash, number)?.map(|header| header.ommers_hash != EMPTY_LIST_HASH));
+        }
+
+        Ok(None)
+    }
+
+    /// Walks canonical headers from `from` to `to` inclusive. Block numbers without a
+    /// canonical header are an error, unless `skip_gaps` is set.
+    pub fn walk_range<'db, 'tx, K: TransactionKind, E: EnvironmentKind>(
+        tx: &'tx MdbxTransaction<'db, K, E>,
+        from: BlockNumber,
+        to: BlockNumber,
+        skip_gaps: bool,
+    ) -> impl Iterator<Item = anyhow::Result<(BlockNumber, BlockHeader)>> + 'tx
+    where
+        'db: 'tx,
+    {
+        TryGenIter::from(move || {
+            let mut canonical = tx.cursor(tables::CanonicalHeader)?;
+            let mut headers = tx.cursor(tables::Header)?;
+
+            let mut expected = from;
+            let mut entry = canonical.seek(from)?;
+            while let Some((number, hash)) = entry {
+                if number > to {
+                    break;
+                }
+                if number != expected && !skip_gaps {
+                    return Err(format_err!("no canonical header for block #{expected}"));
+                }
+
+                let (_, header) = headers
+                    .seek_exact((number, hash))?
+                    .ok_or_else(|| format_err!("no header for block #{number}/{hash:?}"))?;
+                yield (number, header);
+
+                // Nothing can follow the last possible block.
+                let Some(next) = number.0.checked_add(1) else {
+                    return Ok(());
+                };
+                expected = BlockNumber(next);
+                entry = canonical.next()?;
+            }
+
+            if expected <= to && !skip_gaps {
+                return Err(format_err!("no canonical header for block #{expected}"));
+            }
+
+            Ok(())
+        })
+    }
 }
 
 pub mod tx {
@@ -270,6 +331,19 @@ pub mod td {
 
         tx.get(tables::HeadersTotalDifficulty, (number, hash))
     }
+
+    /// Total difficulty of the canonical block at `number`.
+    pub fn read_canonical<K: TransactionKind, E: EnvironmentKind>(
+        tx: &MdbxTransaction<'_, K, E>,
+        number: impl Into<BlockNumber>,
+    ) -> anyhow::Result<Option<U256>> {
+        let number = number.into();
+        if let Some(hash) = super::canonical_hash::read(tx, number)? {
+            return read(tx, hash, number);
+        }
+
+        Ok(None)
+    }
 }
 
 pub mod tl {
@@ -309,6 +383,61 @@ mod tests {
     use crate::kv::new_mem_chaindata;
     use bytes::Bytes;
 
+    #[test]
+    fn walk_header_range() {
+        let db = new_mem_chaindata().unwrap();
+        let tx = db.begin_mutable().unwrap();
+
+        for number in [1, 2, 3, 5, u64::MAX] {
+            let header = BlockHeader {
+                number: BlockNumber(number),
+                ommers_hash: if number == 2 {
+                    H256::repeat_byte(2)
+                } else {
+                    EMPTY_LIST_HASH
+                },
+                ..Default::default()
+            };
+            let hash = header.hash();
+            tx.set(tables::Header, (header.number, hash), header)
+                .unwrap();
+            tx.set(tables::CanonicalHeader, BlockNumber(number), hash)
+                .unwrap();
+        }
+
+        let walk = |from, to, skip_gaps| {
+            let mut numbers = vec![];
+            for res in header::walk_range(&tx, BlockNumber(from), BlockNumber(to), skip_gaps) {
+                match res {
+                    Ok((number, header)) => {
+                        assert_eq!(number, header.number);
+                        numbers.push(number.0);
+                    }
+                    Err(_) => return (numbers, true),
+                }
+            }
+            (numbers, false)
+        };
+
+        assert_eq!(walk(2, 3, false), (vec![2, 3], false));
+        assert_eq!(walk(0, 6, true), (vec![1, 2, 3, 5], false));
+
+        // Gap at #4 ends the walk with an error, as does running past the last header.
+        assert_eq!(walk(1, 5, false), (vec![1, 2, 3], true));
+        assert_eq!(walk(5, 6, false), (vec![5], true));
+
+        // Walking up to the last possible block ends there.
+        assert_eq!(walk(u64::MAX, u64::MAX, false), (vec![u64::MAX], false));
+        assert_eq!(walk(6, u64::MAX, true), (vec![u64::MAX], false));
+
+        for (number, has_ommers) in [(1, Some(false)), (2, Some(true)), (4, None)] {
+            assert_eq!(
+                header::has_ommers(&tx, BlockNumber(number)).unwrap(),
+                has_ommers
+            );
+        }
+    }
+
     #[test]
     fn accessors() {
         let tx1 = MessageWithSignature {
@@ -8,6 +8,7 @@ use std::{
     fs::File,
     path::{Path, PathBuf},
 };
+use tracing::warn;
 
 #[derive(Clone, Debug, Deref, DerefMut, FromStr)]
 
@@ -22,6 +23,10 @@ impl AkulaDataDir {
         self.0.join("etl-temp")
     }
 
+    pub fn headers_spill(&self) -> PathBuf {
+        self.0.join("headers-spill")
+    }
+
     pub fn sentry_db(&self) -> PathBuf {
         self.0.join("sentrydb")
     }
@@ -52,9 +57,14 @@ impl ChainSpec {
         Ok(ron::de::from_reader(File::open(path)?)?)
     }
 
+    /// Loads a bundled preset. Deprecated testnets still load, with a warning.
     pub fn load_builtin(name: impl AsRef<str>) -> anyhow::Result<Self> {
         let name = name.as_ref();
-        Ok(match name.to_lowercase().as_str() {
+        let name_lowercase = name.to_lowercase();
+        if matches!(name_lowercase.as_str(), "ropsten" | "rinkeby") {
+            warn!("Network {name} is deprecated, support for it will be removed");
+        }
+        Ok(match name_lowercase.as_str() {
             "mainnet" | "ethereum" => chainspec::MAINNET.clone(),
             "ropsten" => chainspec::ROPSTEN.clone(),
             "rinkeby" => chainspec::RINKEBY.clone(),
@@ -63,4 +73,18 @@ impl ChainSpec {
             _ => return Err(format_err!("Network {name} is unknown")),
         })
     }
+
+    /// Like [`ChainSpec::load_builtin`], but only for the live networks: mainnet, sepolia
+    /// and goerli. Deprecated testnets are refused.
+    pub fn load_live(name: impl AsRef<str>) -> anyhow::Result<Self> {
+        let name = name.as_ref();
+        match name.to_lowercase().as_str() {
+            "mainnet" | "ethereum" | "sepolia" | "goerli" => Self::load_builtin(name),
+            "ropsten" | "rinkeby" => Err(format_err!("Network {name} is deprecated")),
+            "holesky" => Err(format_err!(
+                "Network {name} is not supported, its upgrades are scheduled by timestamp"
+            )),
+            _ => Err(format_err!("Network {name} is unknown")),
+        }
+    }
 }
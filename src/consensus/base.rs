@@ -1,6 +1,9 @@
 use super::*;
 use crate::{chain::protocol_param::param, models::*, state::*, trie::root_hash};
-use std::{collections::BTreeMap, time::SystemTime};
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime},
+};
 
 pub const MIN_GAS_LIMIT: u64 = 5000;
 
@@ -9,9 +12,13 @@ pub struct ConsensusEngineBase {
     chain_id: ChainId,
     eip1559_block: Option<BlockNumber>,
     max_extra_data_length: Option<(Option<BlockNumber>, usize)>,
+    max_future_drift: Duration,
 }
 
 impl ConsensusEngineBase {
+    /// How far ahead of the local clock a header timestamp may be, same as geth.
+    pub const DEFAULT_MAX_FUTURE_DRIFT: Duration = Duration::from_secs(15);
+
     pub fn new(
         chain_id: ChainId,
         eip1559_block: Option<BlockNumber>,
@@ -21,9 +28,15 @@ impl ConsensusEngineBase {
             chain_id,
             eip1559_block,
             max_extra_data_length,
+            max_future_drift: Self::DEFAULT_MAX_FUTURE_DRIFT,
         }
     }
 
+    pub fn with_max_future_drift(mut self, max_future_drift: Duration) -> Self {
+        self.max_future_drift = max_future_drift;
+        self
+    }
+
     pub fn validate_block_header(
         &self,
         header: &BlockHeader,
@@ -35,8 +48,9 @@ impl ConsensusEngineBase {
                 .duration_since(SystemTime::UNIX_EPOCH)
                 .unwrap()
                 .as_secs();
-            if header.timestamp > now {
+            if header.timestamp > now.saturating_add(self.max_future_drift.as_secs()) {
                 return Err(ValidationError::FutureBlock {
+                    number: header.number,
                     now,
                     got: header.timestamp,
                 }
@@ -68,9 +82,11 @@ impl ConsensusEngineBase {
             }
         }
 
-        if header.timestamp < parent.timestamp {
+        if header.timestamp <= parent.timestamp {
             return Err(ValidationError::InvalidTimestamp {
+                parent_number: parent.number,
                 parent: parent.timestamp,
+                number: header.number,
                 current: header.timestamp,
             }
             .into());
@@ -89,7 +105,13 @@ impl ConsensusEngineBase {
             parent_gas_limit - header.gas_limit
         };
         if gas_delta >= parent_gas_limit / 1024 {
-            return Err(ValidationError::InvalidGasLimit.into());
+            return Err(ValidationError::GasLimitOutOfBounds {
+                parent_number: parent.number,
+                parent_gas_limit,
+                number: header.number,
+                gas_limit: header.gas_limit,
+            }
+            .into());
         }
 
         let expected_base_fee_per_gas = self.expected_base_fee_per_gas(header, parent)?;
@@ -306,6 +328,187 @@ mod tests {
         }
     }
 
+    #[test]
+    fn validate_base_fee() {
+        const LONDON: BlockNumber = BlockNumber(10);
+
+        let engine = ConsensusEngineBase::new(ChainId(1), Some(LONDON), None);
+
+        let header = |number: u64, gas_limit, gas_used, base_fee_per_gas: Option<u64>| {
+            BlockHeader {
+                number: BlockNumber(number),
+                gas_limit,
+                gas_used,
+                timestamp: number,
+                base_fee_per_gas: base_fee_per_gas.map(U256::from),
+                ..Default::default()
+            }
+        };
+
+        let check = |header: &BlockHeader, parent: &BlockHeader, expected: Option<u64>| {
+            match engine.validate_block_header(header, parent, false) {
+                Ok(()) => assert_eq!(header.base_fee_per_gas, expected.map(U256::from)),
+                Err(DuoError::Validation(ValidationError::WrongBaseFee { expected: e, .. })) => {
+                    assert_eq!(e, expected.map(U256::from));
+                    assert_ne!(header.base_fee_per_gas, e);
+                }
+                Err(e) => panic!("unexpected error {e:?}"),
+            }
+        };
+
+        // Before the fork there is no base fee.
+        let pre_fork = header(8, 15_000_000, 0, None);
+        let parent = header(9, 15_000_000, 7_500_000, None);
+        check(&parent, &pre_fork, None);
+        check(&header(9, 15_000_000, 0, Some(1)), &pre_fork, None);
+
+        // Fork block uses the initial base fee and doubles the gas limit.
+        let fork = header(10, 30_000_000, 30_000_000, Some(param::INITIAL_BASE_FEE));
+        check(&fork, &parent, Some(param::INITIAL_BASE_FEE));
+        check(
+            &header(10, 30_000_000, 0, Some(param::INITIAL_BASE_FEE + 1)),
+            &parent,
+            Some(param::INITIAL_BASE_FEE),
+        );
+        check(
+            &header(10, 30_000_000, 0, None),
+            &parent,
+            Some(param::INITIAL_BASE_FEE),
+        );
+
+        // Full parent raises the base fee by 1/8.
+        let full = header(11, 30_000_000, 0, Some(1_125_000_000));
+        check(&full, &fork, Some(1_125_000_000));
+        check(
+            &header(11, 30_000_000, 0, Some(param::INITIAL_BASE_FEE)),
+            &fork,
+            Some(1_125_000_000),
+        );
+
+        // Empty parent lowers the base fee by 1/8.
+        check(
+            &header(12, 30_000_000, 15_000_000, Some(984_375_000)),
+            &full,
+            Some(984_375_000),
+        );
+
+        // Parent at gas target keeps the base fee.
+        check(
+            &header(14, 30_000_000, 0, Some(984_375_000)),
+            &header(13, 30_000_000, 15_000_000, Some(984_375_000)),
+            Some(984_375_000),
+        );
+
+        // Post-fork parent without base fee is rejected.
+        assert!(matches!(
+            engine.validate_block_header(
+                &header(14, 30_000_000, 0, Some(984_375_000)),
+                &header(13, 30_000_000, 0, None),
+                false
+            ),
+            Err(DuoError::Validation(ValidationError::MissingBaseFee))
+        ));
+    }
+
+    #[test]
+    fn validate_gas_limit() {
+        let engine = ConsensusEngineBase::new(ChainId(1), None, None);
+
+        let header = |number: u64, gas_limit| BlockHeader {
+            number: BlockNumber(number),
+            gas_limit,
+            timestamp: number,
+            ..Default::default()
+        };
+
+        let parent = header(1, 10_240_000);
+
+        // Just inside the 1/1024 bound in either direction.
+        for gas_limit in [10_249_999, 10_230_001] {
+            assert!(engine
+                .validate_block_header(&header(2, gas_limit), &parent, false)
+                .is_ok());
+        }
+
+        // Out-of-bounds jump names both headers and their limits.
+        for gas_limit in [10_250_000, 10_230_000, 20_000_000] {
+            assert!(matches!(
+                engine.validate_block_header(&header(2, gas_limit), &parent, false),
+                Err(DuoError::Validation(ValidationError::GasLimitOutOfBounds {
+                    parent_number: BlockNumber(1),
+                    parent_gas_limit: 10_240_000,
+                    number: BlockNumber(2),
+                    gas_limit: got,
+                })) if got == gas_limit
+            ));
+        }
+
+        // Below the floor.
+        assert!(matches!(
+            engine.validate_block_header(
+                &header(2, MIN_GAS_LIMIT - 1),
+                &header(1, MIN_GAS_LIMIT),
+                false
+            ),
+            Err(DuoError::Validation(ValidationError::InvalidGasLimit))
+        ));
+    }
+
+    #[test]
+    fn validate_timestamp() {
+        let engine = ConsensusEngineBase::new(ChainId(1), None, None)
+            .with_max_future_drift(Duration::from_secs(60));
+
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let header = |number: u64, timestamp| BlockHeader {
+            number: BlockNumber(number),
+            gas_limit: 10_000_000,
+            timestamp,
+            ..Default::default()
+        };
+
+        let parent = header(1, now - 100);
+        assert!(engine
+            .validate_block_header(&header(2, now - 99), &parent, true)
+            .is_ok());
+
+        // Equal and decreasing timestamps.
+        for timestamp in [now - 100, now - 101] {
+            assert!(matches!(
+                engine.validate_block_header(&header(2, timestamp), &parent, false),
+                Err(DuoError::Validation(ValidationError::InvalidTimestamp {
+                    parent_number: BlockNumber(1),
+                    number: BlockNumber(2),
+                    current,
+                    ..
+                })) if current == timestamp
+            ));
+        }
+
+        // Within the drift allowance.
+        assert!(engine
+            .validate_block_header(&header(2, now + 30), &parent, true)
+            .is_ok());
+
+        // Implausibly far in the future.
+        let far_future = header(2, now + 3600);
+        assert!(matches!(
+            engine.validate_block_header(&far_future, &parent, true),
+            Err(DuoError::Validation(ValidationError::FutureBlock {
+                number: BlockNumber(2),
+                got,
+                ..
+            })) if got == now + 3600
+        ));
+        assert!(engine
+            .validate_block_header(&far_future, &parent, false)
+            .is_ok());
+    }
+
     #[test]
     fn block_reward() {
         let schedule = BlockSchedule(
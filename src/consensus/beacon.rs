@@ -17,7 +17,7 @@ use jsonrpsee::{
     types::{error::CallError, ErrorObject, Params},
 };
 use serde::{Deserialize, Serialize};
-use std::{future::pending, net::SocketAddr};
+use std::{future::pending, net::SocketAddr, time::Duration};
 use tracing::*;
 
 #[derive(Debug)]
@@ -110,6 +110,7 @@ impl BeaconConsensus {
         chain_id: ChainId,
         network_id: NetworkId,
         eip1559_block: Option<BlockNumber>,
+        max_future_drift: Duration,
         block_reward: BlockRewardSchedule,
         beneficiary_schedule: BeneficiarySchedule,
         terminal_total_difficulty: Option<U256>,
@@ -122,7 +123,8 @@ impl BeaconConsensus {
             finalized_block: H256::zero(),
         });
         Self {
-            base: ConsensusEngineBase::new(chain_id, eip1559_block, Some((since, 32))),
+            base: ConsensusEngineBase::new(chain_id, eip1559_block, Some((since, 32)))
+                .with_max_future_drift(max_future_drift),
             block_reward,
             beneficiary_schedule,
             since,
@@ -69,6 +69,27 @@ fn parse_checkpoint(extra_data: &[u8]) -> Result<Vec<Address>, DuoError> {
     Ok(addresses)
 }
 
+/// Checks that extra data is vanity, signer list and seal, with signers only in checkpoint
+/// blocks. Either shape is accepted if checkpoint blocks are not known.
+pub(crate) fn validate_extra_data(
+    header: &BlockHeader,
+    epoch: Option<u64>,
+) -> Result<(), DuoError> {
+    let signers = parse_checkpoint(&header.extra_data)?;
+
+    if let Some(epoch) = epoch {
+        let is_checkpoint = header.number.0 % epoch == 0;
+        if is_checkpoint && signers.is_empty() {
+            return Err(CliqueError::InvalidCheckpoint.into());
+        }
+        if !is_checkpoint && !signers.is_empty() {
+            return Err(CliqueError::CheckpointInNonEpochBlock.into());
+        }
+    }
+
+    Ok(())
+}
+
 fn get_header<K: TransactionKind>(
     cursor: &mut MdbxCursor<'_, K, tables::Header>,
     height: BlockNumber,
@@ -159,6 +180,7 @@ impl Clique {
     pub(crate) fn new(
         chain_id: ChainId,
         eip1559_block: Option<BlockNumber>,
+        max_future_drift: Duration,
         period: Duration,
         epoch: u64,
         initial_signers: Vec<Address>,
@@ -166,7 +188,8 @@ impl Clique {
         let mut state = CliqueState::new(epoch);
         state.set_signers(initial_signers);
         Self {
-            base: ConsensusEngineBase::new(chain_id, eip1559_block, None),
+            base: ConsensusEngineBase::new(chain_id, eip1559_block, None)
+                .with_max_future_drift(max_future_drift),
             state: Mutex::new(state),
             period: period.as_secs(),
             fork_choice_graph: Arc::new(Mutex::new(Default::default())),
@@ -204,7 +227,9 @@ impl Consensus for Clique {
 
         if header.timestamp - parent.timestamp < self.period {
             return Err(ValidationError::InvalidTimestamp {
+                parent_number: parent.number,
                 parent: parent.timestamp,
+                number: header.number,
                 current: header.timestamp,
             }
             .into());
@@ -213,6 +238,16 @@ impl Consensus for Clique {
         Ok(())
     }
 
+    fn validate_block_header_without_seal(
+        &self,
+        header: &BlockHeader,
+        parent: &BlockHeader,
+        with_future_timestamp_check: bool,
+    ) -> Result<(), DuoError> {
+        self.base
+            .validate_block_header(header, parent, with_future_timestamp_check)
+    }
+
     fn finalize(
         &self,
         block: &BlockHeader,
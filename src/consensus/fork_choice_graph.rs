@@ -3,7 +3,7 @@ use crate::models::{BlockHeader, BlockNumber, H256};
 use ethnum::U256;
 use hashbrown::HashSet;
 use hashlink::LruCache;
-use std::borrow::Borrow;
+use std::{borrow::Borrow, mem::size_of};
 
 #[derive(Debug, PartialEq, Eq, Clone, Default, Hash)]
 pub struct Link {
@@ -61,6 +61,23 @@ impl ForkChoiceGraph {
         self.q.clear();
     }
 
+    /// Drops all headers below the given block number, e.g. once they are saved.
+    pub fn prune_below(&mut self, number: BlockNumber) {
+        let stale = self
+            .raw
+            .iter()
+            .filter(|(_, header)| header.number < number)
+            .map(|(hash, _)| *hash)
+            .collect::<Vec<_>>();
+
+        for hash in stale {
+            self.raw.remove(&hash);
+            self.skip_list.remove(&hash);
+            self.chains.remove(&hash);
+            self.q.remove(&hash);
+        }
+    }
+
     #[inline]
     pub fn len(&self) -> usize {
         self.raw.len()
@@ -71,6 +88,32 @@ impl ForkChoiceGraph {
         self.raw.is_empty()
     }
 
+    /// Estimated number of bytes held by the buffered headers, including their extra data,
+    /// and by the indices over them. Allocator overhead and unused capacity of the caches
+    /// are not counted. Linear in the number of buffered headers.
+    pub fn memory_usage(&self) -> usize {
+        // Every cache entry is a separately allocated node with links to its neighbours,
+        // plus a pointer to it in the hash table.
+        fn entry<V>() -> usize {
+            size_of::<H256>() + size_of::<V>() + 3 * size_of::<usize>()
+        }
+
+        let raw = self
+            .raw
+            .iter()
+            .map(|(_, header)| entry::<BlockHeader>() + header.extra_data.len())
+            .sum::<usize>();
+        let skip_list = self
+            .skip_list
+            .iter()
+            .map(|(_, children)| entry::<HashSet<H256>>() + children.capacity() * size_of::<H256>())
+            .sum::<usize>();
+
+        raw + skip_list
+            + self.chains.len() * entry::<(U256, Depth, Ancestor)>()
+            + self.q.len() * entry::<()>()
+    }
+
     #[inline]
     pub fn insert_with_hash(&mut self, hash: H256, header: BlockHeader) {
         if self.q.contains_key(&hash) {
@@ -183,6 +226,74 @@ mod tests {
     use bytes::Bytes;
     use std::collections::HashMap;
 
+    #[test]
+    fn prune_below() {
+        let mut graph = ForkChoiceGraph::new();
+
+        let mut parent_hash = H256::default();
+        for number in (1..=10u64).map(BlockNumber) {
+            let header = BlockHeader {
+                parent_hash,
+                number,
+                difficulty: U256::from(10u64),
+                ..Default::default()
+            };
+            parent_hash = header.hash();
+            graph.insert(header);
+        }
+        let head = parent_hash;
+        assert_eq!(graph.len(), 10);
+
+        graph.prune_below(BlockNumber(6));
+        assert_eq!(graph.len(), 5);
+        assert_eq!(graph.chain_head(), Some(head));
+        assert_eq!(
+            graph
+                .backtrack(&head)
+                .into_iter()
+                .map(|(_, header)| header.number.0)
+                .collect::<Vec<_>>(),
+            vec![6, 7, 8, 9, 10]
+        );
+
+        graph.prune_below(BlockNumber(11));
+        assert!(graph.is_empty());
+    }
+
+    #[test]
+    fn memory_usage() {
+        let mut graph = ForkChoiceGraph::new();
+        assert_eq!(graph.memory_usage(), 0);
+
+        let mut parent_hash = H256::default();
+        let mut usage = 0;
+        for number in (1..=10u64).map(BlockNumber) {
+            let header = BlockHeader {
+                parent_hash,
+                number,
+                difficulty: U256::from(10u64),
+                extra_data: vec![0; 32].into(),
+                ..Default::default()
+            };
+            parent_hash = header.hash();
+            graph.insert(header);
+
+            let grown = graph.memory_usage();
+            assert!(grown >= usage + size_of::<BlockHeader>() + 32, "#{number}");
+            usage = grown;
+        }
+
+        graph.prune_below(BlockNumber(6));
+        let pruned = graph.memory_usage();
+        assert!(pruned < usage);
+        assert!(pruned >= 5 * (size_of::<BlockHeader>() + 32));
+
+        // Only the children of the first parent, which was never buffered, are left.
+        graph.prune_below(BlockNumber(11));
+        assert!(graph.is_empty());
+        assert!(graph.memory_usage() < size_of::<BlockHeader>());
+    }
+
     #[test]
     fn test_max_td() {
         const FORKED_EXTRA_DATA: &[u8] = b"forked";
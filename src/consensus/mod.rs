@@ -19,6 +19,7 @@ use std::{
     fmt::{Debug, Display},
     net::{Ipv4Addr, SocketAddr, SocketAddrV4},
     sync::Arc,
+    time::Duration,
 };
 use tokio::sync::watch;
 
@@ -81,6 +82,18 @@ pub trait Consensus: Debug + Send + Sync + 'static {
         with_future_timestamp_check: bool,
     ) -> Result<(), DuoError>;
 
+    /// Like [`Consensus::validate_block_header`], minus the rules of the sealing scheme, e. g.
+    /// clique signing periods. Used when seals are not verified, see
+    /// [`ConsensusMode::NoSealVerification`].
+    fn validate_block_header_without_seal(
+        &self,
+        header: &BlockHeader,
+        parent: &BlockHeader,
+        with_future_timestamp_check: bool,
+    ) -> Result<(), DuoError> {
+        self.validate_block_header(header, parent, with_future_timestamp_check)
+    }
+
     /// Finalizes block execution by applying changes in the state of accounts or of the consensus itself
     ///
     /// NOTE: For Ethash See YP Section 11.3 "Reward Application".
@@ -172,9 +185,10 @@ impl Display for CliqueError {
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ValidationError {
     FutureBlock {
+        number: BlockNumber,
         now: u64,
         got: u64,
-    }, // Block has a timestamp in the future
+    }, // Block has a timestamp too far in the future
 
     // See [YP] Section 4.3.2 "Holistic Validity", Eq (31)
     WrongStateRoot {
@@ -212,9 +226,17 @@ pub enum ValidationError {
         used: u64,
         limit: u64,
     }, // Hg > Hl
-    InvalidGasLimit, // |Hl-P(H)Hl|≥P(H)Hl/1024 ∨ Hl<5000
+    InvalidGasLimit, // Hl<5000
+    GasLimitOutOfBounds {
+        parent_number: BlockNumber,
+        parent_gas_limit: u64,
+        number: BlockNumber,
+        gas_limit: u64,
+    }, // |Hl-P(H)Hl|≥P(H)Hl/1024
     InvalidTimestamp {
+        parent_number: BlockNumber,
         parent: u64,
+        number: BlockNumber,
         current: u64,
     }, // Hs ≤ P(H)Hs
     ExtraDataTooLong, // ‖Hx‖ > 32
@@ -316,11 +338,60 @@ pub fn pre_validate_transaction(
     Ok(())
 }
 
+/// Layout of header extra data required by a chain's seal engine.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExtraDataFormat {
+    /// Free-form, up to the given number of bytes.
+    Capped(usize),
+    /// Vanity, signer list and seal. Only checkpoint blocks, the first of each epoch, list
+    /// signers. The epoch is not known for clique blocks of chains that later merged.
+    Clique { epoch: Option<u64> },
+}
+
+impl ExtraDataFormat {
+    /// Format of the given block: clique on clique chains, including the blocks of merged
+    /// chains that were sealed by clique, 32 bytes at most for ethash and proof of stake.
+    pub fn for_block(chain_spec: &ChainSpec, number: BlockNumber) -> Self {
+        match &chain_spec.consensus.seal_verification {
+            SealVerificationParams::Clique { epoch, .. } => Self::Clique {
+                epoch: Some(*epoch),
+            },
+            SealVerificationParams::Beacon {
+                since, beneficiary, ..
+            } if !switch_is_active(*since, number)
+                && matches!(
+                    beneficiary.range(..=number).next_back(),
+                    Some((_, BeneficiaryFunction::Clique))
+                ) =>
+            {
+                Self::Clique { epoch: None }
+            }
+            SealVerificationParams::Beacon { .. } => Self::Capped(32),
+        }
+    }
+
+    pub fn validate(&self, header: &BlockHeader) -> Result<(), DuoError> {
+        match *self {
+            Self::Capped(limit) => {
+                if header.extra_data.len() > limit {
+                    return Err(ValidationError::ExtraDataTooLong.into());
+                }
+                Ok(())
+            }
+            Self::Clique { epoch } => clique::validate_extra_data(header, epoch),
+        }
+    }
+}
+
 pub fn engine_factory(
     db: Option<Arc<MdbxWithDirHandle<WriteMap>>>,
     chain_config: ChainSpec,
     listen_addr: Option<SocketAddr>,
 ) -> anyhow::Result<Box<dyn Consensus>> {
+    let max_future_drift = chain_config.consensus.max_future_drift.map_or(
+        ConsensusEngineBase::DEFAULT_MAX_FUTURE_DRIFT,
+        Duration::from_secs,
+    );
     Ok(match chain_config.consensus.seal_verification {
         SealVerificationParams::Clique { period, epoch } => {
             let initial_signers = match chain_config.genesis.seal {
@@ -334,6 +405,7 @@ pub fn engine_factory(
             Box::new(Clique::new(
                 chain_config.params.chain_id,
                 chain_config.consensus.eip1559_block,
+                max_future_drift,
                 period,
                 epoch,
                 initial_signers,
@@ -355,6 +427,7 @@ pub fn engine_factory(
             chain_config.params.chain_id,
             chain_config.params.network_id,
             chain_config.consensus.eip1559_block,
+            max_future_drift,
             block_reward.into(),
             beneficiary.into(),
             terminal_total_difficulty,
@@ -364,3 +437,118 @@ pub fn engine_factory(
         )),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::res::chainspec::{GOERLI, MAINNET, RINKEBY};
+
+    #[test]
+    fn extra_data_format() {
+        assert_eq!(
+            ExtraDataFormat::for_block(&MAINNET, BlockNumber(1)),
+            ExtraDataFormat::Capped(32)
+        );
+        assert_eq!(
+            ExtraDataFormat::for_block(&RINKEBY, BlockNumber(1)),
+            ExtraDataFormat::Clique { epoch: Some(30000) }
+        );
+        assert_eq!(
+            ExtraDataFormat::for_block(&GOERLI, BlockNumber(1)),
+            ExtraDataFormat::Clique { epoch: None }
+        );
+        assert_eq!(
+            ExtraDataFormat::for_block(&GOERLI, BlockNumber(7382819)),
+            ExtraDataFormat::Capped(32)
+        );
+
+        let header = |number, extra_data: Vec<u8>| BlockHeader {
+            number: BlockNumber(number),
+            extra_data: extra_data.into(),
+            ..Default::default()
+        };
+        let clique = |signers: &[u8]| {
+            let mut extra_data = vec![0; 32];
+            for &signer in signers {
+                extra_data.extend_from_slice(Address::repeat_byte(signer).as_bytes());
+            }
+            extra_data.extend_from_slice(&[0; 65]);
+            extra_data
+        };
+        let check = |format: ExtraDataFormat, number, extra_data| {
+            format
+                .validate(&header(number, extra_data))
+                .map_err(|e| match e {
+                    DuoError::Validation(e) => e,
+                    DuoError::Internal(e) => panic!("unexpected error {e}"),
+                })
+        };
+
+        let ethash = ExtraDataFormat::Capped(32);
+        assert_eq!(check(ethash, 1, vec![]), Ok(()));
+        assert_eq!(check(ethash, 1, vec![0; 32]), Ok(()));
+        assert_eq!(
+            check(ethash, 1, vec![0; 33]),
+            Err(ValidationError::ExtraDataTooLong)
+        );
+
+        let rinkeby = ExtraDataFormat::Clique { epoch: Some(30000) };
+        assert_eq!(check(rinkeby, 1, clique(&[])), Ok(()));
+        assert_eq!(check(rinkeby, 30000, clique(&[1, 2])), Ok(()));
+        for (number, extra_data, error) in [
+            (1, vec![0; 32], CliqueError::WrongExtraData),
+            (1, vec![0; 32 + 10 + 65], CliqueError::WrongExtraData),
+            (1, clique(&[1]), CliqueError::CheckpointInNonEpochBlock),
+            (30000, clique(&[]), CliqueError::InvalidCheckpoint),
+            // Signers must be sorted.
+            (30000, clique(&[2, 1]), CliqueError::InvalidCheckpoint),
+        ] {
+            assert_eq!(
+                check(rinkeby, number, extra_data),
+                Err(ValidationError::CliqueError(error))
+            );
+        }
+
+        // Without epochs any well-formed signer list is accepted.
+        let goerli = ExtraDataFormat::Clique { epoch: None };
+        assert_eq!(check(goerli, 1, clique(&[])), Ok(()));
+        assert_eq!(check(goerli, 1, clique(&[1, 2])), Ok(()));
+        assert_eq!(
+            check(goerli, 1, vec![0; 32]),
+            Err(ValidationError::CliqueError(CliqueError::WrongExtraData))
+        );
+    }
+
+    #[test]
+    fn chainspec_max_future_drift() {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let parent = BlockHeader {
+            number: BlockNumber(1),
+            gas_limit: 10_000_000,
+            timestamp: now,
+            ..Default::default()
+        };
+        let header = BlockHeader {
+            number: BlockNumber(2),
+            timestamp: now + 1800,
+            ..parent.clone()
+        };
+        let is_future = |chain_spec| {
+            matches!(
+                engine_factory(None, chain_spec, None)
+                    .unwrap()
+                    .validate_block_header(&header, &parent, true),
+                Err(DuoError::Validation(ValidationError::FutureBlock { .. }))
+            )
+        };
+
+        assert!(is_future(MAINNET.clone()));
+
+        let mut chain_spec = MAINNET.clone();
+        chain_spec.consensus.max_future_drift = Some(3600);
+        assert!(!is_future(chain_spec));
+    }
+}
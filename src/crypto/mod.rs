@@ -48,6 +48,78 @@ pub fn is_valid_signature(r: H256, s: H256) -> bool {
     true
 }
 
+/// Keccak-256 implementation. [`keccak256`], and with it header and trie hashing, uses
+/// [`SelectedKeccak`], picked at build time.
+pub trait KeccakBackend {
+    fn keccak256(data: &[u8]) -> H256;
+}
+
+/// Keccak-256 from the `sha3` crate, the default backend.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sha3Keccak;
+
+impl KeccakBackend for Sha3Keccak {
+    fn keccak256(data: &[u8]) -> H256 {
+        H256::from_slice(&Keccak256::digest(data))
+    }
+}
+
+/// Keccak-256 from the `tiny-keccak` crate.
+#[cfg(feature = "tiny-keccak")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TinyKeccak;
+
+#[cfg(feature = "tiny-keccak")]
+impl KeccakBackend for TinyKeccak {
+    fn keccak256(data: &[u8]) -> H256 {
+        let mut hasher = tiny_keccak::Keccak::v256();
+        tiny_keccak::Hasher::update(&mut hasher, data);
+        let mut out = H256::zero();
+        tiny_keccak::Hasher::finalize(hasher, out.as_bytes_mut());
+        out
+    }
+}
+
+/// Backend used by [`keccak256`]: [`TinyKeccak`] with the `tiny-keccak` feature, otherwise
+/// [`Sha3Keccak`].
+#[cfg(not(feature = "tiny-keccak"))]
+pub type SelectedKeccak = Sha3Keccak;
+/// Backend used by [`keccak256`]: [`TinyKeccak`] with the `tiny-keccak` feature, otherwise
+/// [`Sha3Keccak`].
+#[cfg(feature = "tiny-keccak")]
+pub type SelectedKeccak = TinyKeccak;
+
 pub fn keccak256(data: impl AsRef<[u8]>) -> H256 {
-    H256::from_slice(&Keccak256::digest(data.as_ref()))
+    SelectedKeccak::keccak256(data.as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keccak_backends() {
+        let vectors = [
+            (
+                &b""[..],
+                hex!("c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"),
+            ),
+            (
+                &b"abc"[..],
+                hex!("4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45"),
+            ),
+        ];
+        for (data, expected) in vectors {
+            assert_eq!(Sha3Keccak::keccak256(data), H256(expected));
+            #[cfg(feature = "tiny-keccak")]
+            assert_eq!(TinyKeccak::keccak256(data), H256(expected));
+            assert_eq!(keccak256(data), H256(expected));
+        }
+
+        // Inputs longer than the 136 byte rate take several permutations.
+        let long = (0..1000_u32).map(|i| i as u8).collect::<Vec<_>>();
+        #[cfg(feature = "tiny-keccak")]
+        assert_eq!(TinyKeccak::keccak256(&long), Sha3Keccak::keccak256(&long));
+        assert_eq!(keccak256(&long), Sha3Keccak::keccak256(&long));
+    }
 }
@@ -2,7 +2,14 @@ use crate::kv::{traits::*, *};
 use ::mdbx::{DatabaseFlags, WriteFlags};
 pub use ::mdbx::{EnvironmentKind, Geometry, NoWriteMap, TransactionKind, WriteMap, RO, RW};
 use anyhow::Context;
-use std::{collections::HashMap, fs::DirBuilder, marker::PhantomData, ops::Deref, path::Path};
+use std::{
+    collections::HashMap,
+    fs::DirBuilder,
+    marker::PhantomData,
+    ops::Deref,
+    path::Path,
+    time::{Duration, Instant},
+};
 use tables::*;
 
 #[derive(Clone, Debug)]
@@ -115,6 +122,78 @@ impl<E: EnvironmentKind> MdbxEnvironment<E> {
             inner: self.inner.begin_rw_txn()?,
         })
     }
+
+    /// Opens a long-lived read transaction. It sees the database as of the moment it was
+    /// opened, regardless of writes committed afterwards, and does not block writers.
+    pub fn begin_snapshot(&self) -> anyhow::Result<SnapshotTransaction<'_, E>> {
+        Ok(SnapshotTransaction {
+            txn: self.begin()?,
+            opened_at: Instant::now(),
+        })
+    }
+}
+
+/// Read transaction pinned to the database version it was opened at.
+///
+/// Pages freed by later writes cannot be reused while the snapshot is alive, so holding it
+/// for too long grows the database. A warning is logged on drop if that was the case.
+#[derive(Debug)]
+pub struct SnapshotTransaction<'env, E>
+where
+    E: EnvironmentKind,
+{
+    txn: MdbxTransaction<'env, RO, E>,
+    opened_at: Instant,
+}
+
+impl<'env, E> SnapshotTransaction<'env, E>
+where
+    E: EnvironmentKind,
+{
+    pub const LONG_LIVED_THRESHOLD: Duration = Duration::from_secs(60);
+
+    /// Id of the database version this snapshot reads.
+    pub fn version(&self) -> u64 {
+        self.txn.id()
+    }
+
+    pub fn age(&self) -> Duration {
+        self.opened_at.elapsed()
+    }
+}
+
+impl<'env, E> Deref for SnapshotTransaction<'env, E>
+where
+    E: EnvironmentKind,
+{
+    type Target = MdbxTransaction<'env, RO, E>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.txn
+    }
+}
+
+impl<'env, E> Drop for SnapshotTransaction<'env, E>
+where
+    E: EnvironmentKind,
+{
+    fn drop(&mut self) {
+        let age = self.age();
+        if age > Self::LONG_LIVED_THRESHOLD {
+            tracing::warn!(
+                "Snapshot of database version {} was held for {age:?}, freed pages could not be reused meanwhile",
+                self.version()
+            );
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TableStats {
+    pub entries: usize,
+    pub total_bytes: u64,
+    /// Height of the B-tree.
+    pub depth: u32,
 }
 
 #[derive(Debug)]
@@ -169,6 +248,21 @@ where
         self.inner.id()
     }
 
+    /// Entry count and size on disk of the table, all zero if it is empty.
+    pub fn table_stats<T: Table>(&self, table: T) -> anyhow::Result<TableStats> {
+        let st = self
+            .inner
+            .db_stat(&self.inner.open_db(Some(table.db_name().as_ref()))?)
+            .with_context(|| format!("failed to get stats for table: {}", table.db_name()))?;
+
+        Ok(TableStats {
+            entries: st.entries(),
+            total_bytes: ((st.leaf_pages() + st.branch_pages() + st.overflow_pages())
+                * st.page_size() as usize) as u64,
+            depth: st.depth(),
+        })
+    }
+
     pub fn cursor<'tx, T>(&'tx self, table: T) -> anyhow::Result<MdbxCursor<'tx, K, T>>
     where
         'env: 'tx,
@@ -193,6 +287,29 @@ where
             )?
             .map(|v| v.0))
     }
+
+    /// Looks up many keys at once, same as calling [`MdbxTransaction::get`] for each. Uses a
+    /// single cursor and visits the keys in database order.
+    pub fn get_multi<T>(&self, table: T, keys: &[T::Key]) -> anyhow::Result<Vec<Option<T::Value>>>
+    where
+        T: Table,
+        T::Key: Clone + TableDecode,
+    {
+        let mut order = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| (key.clone().encode(), i))
+            .collect::<Vec<_>>();
+        order.sort_unstable_by(|(a, _), (b, _)| a.as_ref().cmp(b.as_ref()));
+
+        let mut out = (0..keys.len()).map(|_| None).collect::<Vec<_>>();
+        let mut cursor = self.cursor(table)?;
+        for (_, i) in order {
+            out[i] = cursor.seek_exact(keys[i].clone())?.map(|(_, v)| v);
+        }
+
+        Ok(out)
+    }
 }
 
 impl<'env, E: EnvironmentKind> MdbxTransaction<'env, RW, E> {
@@ -533,3 +650,124 @@ where
         )?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        kv::new_mem_chaindata,
+        models::{BlockNumber, H256},
+    };
+
+    #[test]
+    fn snapshot_ignores_later_writes() {
+        let db = new_mem_chaindata().unwrap();
+
+        let write = |value| {
+            std::thread::scope(|s| {
+                s.spawn(|| {
+                    let txn = db.begin_mutable().unwrap();
+                    txn.set(tables::Version, (), value).unwrap();
+                    txn.commit().unwrap();
+                });
+            })
+        };
+
+        write(1);
+        let snapshot = db.begin_snapshot().unwrap();
+        write(2);
+
+        assert_eq!(snapshot.get(tables::Version, ()).unwrap(), Some(1));
+        assert_eq!(
+            db.begin().unwrap().get(tables::Version, ()).unwrap(),
+            Some(2)
+        );
+        assert!(db.begin().unwrap().id() > snapshot.version());
+    }
+
+    #[test]
+    fn map_full() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_database(
+            &CHAINDATA_TABLES,
+            dir.path(),
+            MdbxGeometry {
+                initial_size: 1 << 20,
+                growth_step: Some(256 << 10),
+                max_size: 2 << 20,
+                page_size: None,
+            },
+        )
+        .unwrap();
+
+        // Writes past the maximum size fail instead of growing the map.
+        let txn = db.begin_mutable().unwrap();
+        let e = (0_u64..1000)
+            .find_map(|i| {
+                txn.set(tables::TrieAccount, i.to_be_bytes().to_vec(), vec![0; 4096])
+                    .err()
+            })
+            .expect("writes did not run out of space");
+        assert!(
+            matches!(e.downcast_ref(), Some(::mdbx::Error::MapFull)),
+            "{e:?}"
+        );
+    }
+
+    #[test]
+    fn get_multi() {
+        let db = new_mem_chaindata().unwrap();
+        let txn = db.begin_mutable().unwrap();
+        for number in [1, 3, 4, 700] {
+            txn.set(
+                tables::CanonicalHeader,
+                BlockNumber(number),
+                H256::from_low_u64_be(number),
+            )
+            .unwrap();
+        }
+
+        let keys = [700, 2, 1, 4, 1000, 3, 1].map(BlockNumber);
+        let expected = keys
+            .iter()
+            .map(|&key| txn.get(tables::CanonicalHeader, key).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            txn.get_multi(tables::CanonicalHeader, &keys).unwrap(),
+            expected
+        );
+        assert_eq!(expected.iter().flatten().count(), 5);
+        assert!(txn
+            .get_multi(tables::CanonicalHeader, &[])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn table_stats() {
+        let db = new_mem_chaindata().unwrap();
+
+        let txn = db.begin_mutable().unwrap();
+        assert_eq!(
+            txn.table_stats(tables::CanonicalHeader).unwrap(),
+            TableStats::default()
+        );
+
+        for number in 0..3 {
+            txn.set(tables::CanonicalHeader, BlockNumber(number), H256::zero())
+                .unwrap();
+        }
+        let stats = txn.table_stats(tables::CanonicalHeader).unwrap();
+        assert_eq!(stats.entries, 3);
+        assert!(stats.total_bytes > 0);
+        assert_eq!(stats.depth, 1);
+        txn.commit().unwrap();
+
+        let txn = db.begin().unwrap();
+        assert_eq!(txn.table_stats(tables::CanonicalHeader).unwrap(), stats);
+        assert_eq!(
+            txn.table_stats(tables::Header).unwrap(),
+            TableStats::default()
+        );
+    }
+}
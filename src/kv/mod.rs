@@ -51,10 +51,50 @@ impl<E: EnvironmentKind> From<mdbx::MdbxEnvironment<E>> for MdbxWithDirHandle<E>
     }
 }
 
+/// Size and growth of the database map.
+///
+/// The map grows by [`MdbxGeometry::growth_step`] whenever a write transaction runs out of
+/// space, and writes fail with [`::mdbx::Error::MapFull`] once it would grow past
+/// [`MdbxGeometry::max_size`]. Stages that save in bursts, such as the headers stage writing a
+/// whole downloaded batch in one transaction, grow the map several times per commit if the step
+/// is small compared to the burst, so the step should be at least a few times the size of a
+/// typical commit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MdbxGeometry {
+    /// Size of the map when the database is created.
+    pub initial_size: u64,
+    /// MDBX picks a step relative to the map size if unset.
+    pub growth_step: Option<u64>,
+    /// Size the map may never grow past, enough for an archive node on mainnet by default.
+    pub max_size: u64,
+    /// Page size in bytes, a power of two between 256 and 65536. Only used when the database
+    /// is created, MDBX picks one if unset.
+    pub page_size: Option<usize>,
+}
+
+impl Default for MdbxGeometry {
+    fn default() -> Self {
+        Self {
+            initial_size: 0,
+            growth_step: Some(n_gib_bytes!(4) as u64),
+            max_size: n_tib_bytes!(4) as u64,
+            page_size: None,
+        }
+    }
+}
+
 pub fn new_mem_chaindata() -> anyhow::Result<MdbxWithDirHandle<WriteMap>> {
     let tmpdir = tempfile::tempdir()?;
     Ok(MdbxWithDirHandle {
-        inner: new_environment(&CHAINDATA_TABLES, tmpdir.path(), n_mib_bytes!(64), None)?,
+        inner: new_environment(
+            &CHAINDATA_TABLES,
+            tmpdir.path(),
+            MdbxGeometry {
+                growth_step: None,
+                max_size: n_mib_bytes!(64) as u64,
+                ..Default::default()
+            },
+        )?,
         _tmpdir: Some(tmpdir),
     })
 }
@@ -62,9 +102,17 @@ pub fn new_mem_chaindata() -> anyhow::Result<MdbxWithDirHandle<WriteMap>> {
 pub fn new_database(
     chart: &DatabaseChart,
     path: &std::path::Path,
+) -> anyhow::Result<MdbxWithDirHandle<WriteMap>> {
+    open_database(chart, path, MdbxGeometry::default())
+}
+
+pub fn open_database(
+    chart: &DatabaseChart,
+    path: &std::path::Path,
+    geometry: MdbxGeometry,
 ) -> anyhow::Result<MdbxWithDirHandle<WriteMap>> {
     Ok(MdbxWithDirHandle {
-        inner: new_environment(chart, path, n_tib_bytes!(4), Some(n_gib_bytes!(4) as usize))?,
+        inner: new_environment(chart, path, geometry)?,
         _tmpdir: None,
     })
 }
@@ -72,16 +120,18 @@ pub fn new_database(
 fn new_environment(
     chart: &DatabaseChart,
     path: &std::path::Path,
-    size_upper_limit: u128,
-    growth_step: Option<usize>,
+    geometry: MdbxGeometry,
 ) -> anyhow::Result<mdbx::MdbxEnvironment<WriteMap>> {
+    let to_usize = |size: u64| size.try_into().unwrap_or(usize::MAX);
     let mut builder = ::mdbx::Environment::<WriteMap>::new();
     builder.set_max_dbs(CHAINDATA_TABLES.len());
     builder.set_geometry(Geometry {
-        size: Some(0..size_upper_limit.try_into().unwrap_or(usize::MAX)),
-        growth_step: growth_step.map(|s| s.try_into().unwrap_or(isize::MAX)),
+        size: Some(to_usize(geometry.initial_size)..to_usize(geometry.max_size)),
+        growth_step: geometry
+            .growth_step
+            .map(|s| s.try_into().unwrap_or(isize::MAX)),
         shrink_threshold: None,
-        page_size: None,
+        page_size: geometry.page_size.map(::mdbx::PageSize::Set),
     });
     builder.set_rp_augment_limit(16 * 256 * 1024);
     mdbx::MdbxEnvironment::open_rw(builder, path, chart)
@@ -441,6 +441,23 @@ where
     }
 }
 
+impl TableEncode for H512 {
+    type Encoded = [u8; 64];
+
+    fn encode(self) -> Self::Encoded {
+        self.0
+    }
+}
+
+impl TableDecode for H512 {
+    fn decode(b: &[u8]) -> anyhow::Result<Self> {
+        match b.len() {
+            64 => Ok(H512::from_slice(b)),
+            other => Err(InvalidLength::<64> { got: other }.into()),
+        }
+    }
+}
+
 impl TableEncode for U256 {
     type Encoded = VariableVec<KECCAK_LENGTH>;
 
@@ -830,6 +847,7 @@ decl_table!(TxSender => HeaderKey => Vec<Address>);
 decl_table!(LastHeader => () => HeaderKey);
 decl_table!(Issuance => Vec<u8> => Vec<u8>);
 decl_table!(Version => () => u64);
+decl_table!(PeerCooldown => H512 => u64);
 
 pub type DatabaseChart = BTreeMap<&'static str, TableInfo>;
 
@@ -888,7 +906,7 @@ pub static CHAINDATA_TABLES: Lazy<Arc<DatabaseChart>> = Lazy::new(|| {
 });
 
 pub static SENTRY_TABLES: Lazy<Arc<DatabaseChart>> =
-    Lazy::new(|| Arc::new([].into_iter().collect()));
+    Lazy::new(|| Arc::new([table_entry!(PeerCooldown)].into_iter().collect()));
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,171 @@
+use super::BlockNumber;
+use std::num::NonZeroU64;
+
+/// Inclusive range of block numbers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BlockNumberRange {
+    pub start: BlockNumber,
+    pub end: BlockNumber,
+}
+
+impl BlockNumberRange {
+    pub const fn new(start: BlockNumber, end: BlockNumber) -> Self {
+        Self { start, end }
+    }
+
+    /// Number of blocks in the range, saturating at `u64::MAX` for the full range.
+    pub const fn len(&self) -> u64 {
+        if self.end.0 < self.start.0 {
+            0
+        } else {
+            (self.end.0 - self.start.0).saturating_add(1)
+        }
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub const fn contains(&self, block_number: BlockNumber) -> bool {
+        self.start.0 <= block_number.0 && block_number.0 <= self.end.0
+    }
+
+    /// Same range with the start moved down to the nearest multiple of `size`.
+    pub const fn aligned_to_slice(&self, size: NonZeroU64) -> Self {
+        Self {
+            start: align_block_num_to_slice_start(self.start, size),
+            end: self.end,
+        }
+    }
+
+    /// Number of slices of `size` blocks needed to cover the range, the last one possibly shorter.
+    /// Saturates at `u64::MAX` for single block slices of the full range.
+    pub const fn slice_count(&self, size: NonZeroU64) -> u64 {
+        if self.is_empty() {
+            0
+        } else {
+            ((self.end.0 - self.start.0) / size.get()).saturating_add(1)
+        }
+    }
+
+    /// Splits the range into consecutive slices of `size` blocks starting at `self.start`.
+    /// Only the last slice may be shorter.
+    pub fn iter_slices(&self, size: NonZeroU64) -> impl Iterator<Item = BlockNumberRange> {
+        let Self { start, end } = *self;
+        (0..self.slice_count(size)).map(move |i| {
+            let slice_start = BlockNumber(start.0 + i * size.get());
+            Self::new(
+                slice_start,
+                std::cmp::min(
+                    end,
+                    BlockNumber(slice_start.0.saturating_add(size.get() - 1)),
+                ),
+            )
+        })
+    }
+}
+
+impl IntoIterator for BlockNumberRange {
+    type Item = BlockNumber;
+    type IntoIter = std::ops::RangeInclusive<BlockNumber>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.start..=self.end
+    }
+}
+
+/// Rounds `block_number` down to the start of its slice of `size` blocks.
+pub const fn align_block_num_to_slice_start(
+    block_number: BlockNumber,
+    size: NonZeroU64,
+) -> BlockNumber {
+    BlockNumber(block_number.0 / size.get() * size.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u64, end: u64) -> BlockNumberRange {
+        BlockNumberRange::new(BlockNumber(start), BlockNumber(end))
+    }
+
+    fn size(size: u64) -> NonZeroU64 {
+        NonZeroU64::new(size).unwrap()
+    }
+
+    #[test]
+    fn len_and_contains() {
+        assert_eq!(range(5, 5).len(), 1);
+        assert_eq!(range(5, 10).len(), 6);
+        assert!(range(6, 5).is_empty());
+
+        assert!(range(5, 10).contains(BlockNumber(5)));
+        assert!(range(5, 10).contains(BlockNumber(10)));
+        assert!(!range(5, 10).contains(BlockNumber(4)));
+        assert!(!range(5, 10).contains(BlockNumber(11)));
+    }
+
+    #[test]
+    fn aligned_to_slice() {
+        assert_eq!(range(0, 10).aligned_to_slice(size(4)), range(0, 10));
+        assert_eq!(range(7, 10).aligned_to_slice(size(4)), range(4, 10));
+        assert_eq!(range(8, 10).aligned_to_slice(size(4)), range(8, 10));
+        assert_eq!(
+            align_block_num_to_slice_start(BlockNumber(1023), size(1024)),
+            BlockNumber(0)
+        );
+        assert_eq!(
+            range(u64::MAX, u64::MAX).aligned_to_slice(size(4)),
+            range(u64::MAX - 3, u64::MAX)
+        );
+    }
+
+    #[test]
+    fn iter_slices() {
+        for (r, slice_size, slices) in [
+            (range(1, 1), 4, vec![(1, 1)]),
+            (range(1, 8), 4, vec![(1, 4), (5, 8)]),
+            (range(1, 9), 4, vec![(1, 4), (5, 8), (9, 9)]),
+            (range(10, 9), 4, vec![]),
+        ] {
+            assert_eq!(r.slice_count(size(slice_size)), slices.len() as u64);
+            assert_eq!(
+                r.iter_slices(size(slice_size)).collect::<Vec<_>>(),
+                slices
+                    .into_iter()
+                    .map(|(start, end)| range(start, end))
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn near_u64_max() {
+        let full = range(0, u64::MAX);
+        assert_eq!(full.len(), u64::MAX);
+        assert!(full.contains(BlockNumber(u64::MAX)));
+        assert_eq!(full.slice_count(size(1)), u64::MAX);
+        assert_eq!(full.slice_count(size(1 << 63)), 2);
+        assert_eq!(
+            full.iter_slices(size(1 << 63)).collect::<Vec<_>>(),
+            vec![range(0, (1 << 63) - 1), range(1 << 63, u64::MAX)]
+        );
+
+        let top = range(u64::MAX - 5, u64::MAX);
+        assert_eq!(top.len(), 6);
+        assert_eq!(
+            top.iter_slices(size(4)).collect::<Vec<_>>(),
+            vec![
+                range(u64::MAX - 5, u64::MAX - 2),
+                range(u64::MAX - 1, u64::MAX)
+            ]
+        );
+        assert_eq!(
+            range(u64::MAX, u64::MAX)
+                .iter_slices(size(u64::MAX))
+                .collect::<Vec<_>>(),
+            vec![range(u64::MAX, u64::MAX)]
+        );
+    }
+}
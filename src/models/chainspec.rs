@@ -147,6 +147,16 @@ pub struct ConsensusParams {
         with = "::serde_with::rust::unwrap_or_skip"
     )]
     pub eip1559_block: Option<BlockNumber>,
+    /// How far ahead of the local clock header timestamps may be, in seconds.
+    /// [`ConsensusEngineBase::DEFAULT_MAX_FUTURE_DRIFT`] if unset.
+    ///
+    /// [`ConsensusEngineBase::DEFAULT_MAX_FUTURE_DRIFT`]: crate::consensus::ConsensusEngineBase::DEFAULT_MAX_FUTURE_DRIFT
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "::serde_with::rust::unwrap_or_skip"
+    )]
+    pub max_future_drift: Option<u64>,
 }
 
 pub fn switch_is_active(switch: Option<BlockNumber>, block_number: BlockNumber) -> bool {
@@ -266,6 +276,36 @@ pub struct Upgrades {
     pub paris: Option<BlockNumber>,
 }
 
+impl Upgrades {
+    /// Scheduled upgrades in order of activation, with the revision each of them switches to.
+    pub fn schedule(&self) -> impl Iterator<Item = (Revision, BlockNumber)> {
+        [
+            (Revision::Homestead, self.homestead),
+            (Revision::Tangerine, self.tangerine),
+            (Revision::Spurious, self.spurious),
+            (Revision::Byzantium, self.byzantium),
+            (Revision::Constantinople, self.constantinople),
+            (Revision::Petersburg, self.petersburg),
+            (Revision::Istanbul, self.istanbul),
+            (Revision::Berlin, self.berlin),
+            (Revision::London, self.london),
+            (Revision::Paris, self.paris),
+        ]
+        .into_iter()
+        .filter_map(|(revision, block)| Some((revision, block?)))
+    }
+
+    /// Latest revision active at the given block.
+    pub fn revision_at(&self, block_number: impl Into<BlockNumber>) -> Revision {
+        let block_number = block_number.into();
+        self.schedule()
+            .filter(|&(_, fork_block)| block_number >= fork_block)
+            .map(|(revision, _)| revision)
+            .max()
+            .unwrap_or(Revision::Frontier)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Params {
     pub chain_id: ChainId,
@@ -416,6 +456,7 @@ mod tests {
                         epoch: 30_000,
                     },
                     eip1559_block: Some(8897988.into()),
+                    max_future_drift: None,
                 },
                 upgrades: Upgrades {
                     homestead: Some(1.into()),
@@ -477,6 +518,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn revision_at() {
+        for spec in [&*MAINNET, &*ROPSTEN, &*RINKEBY, &*GOERLI, &*SEPOLIA] {
+            for (_, fork_block) in spec.upgrades.schedule() {
+                for block_number in [
+                    fork_block.0.saturating_sub(1),
+                    fork_block.0,
+                    fork_block.0 + 1,
+                ] {
+                    assert_eq!(
+                        spec.upgrades.revision_at(block_number),
+                        spec.collect_block_spec(block_number).revision,
+                        "{} #{block_number}",
+                        spec.name
+                    );
+                }
+            }
+        }
+
+        assert_eq!(MAINNET.upgrades.revision_at(BlockNumber(0)), Revision::Frontier);
+        assert_eq!(
+            MAINNET.upgrades.revision_at(BlockNumber(12_964_999)),
+            Revision::Berlin
+        );
+        assert_eq!(
+            MAINNET.upgrades.revision_at(BlockNumber(12_965_000)),
+            Revision::London
+        );
+    }
+
     #[test]
     fn distinct_block_numbers() {
         assert_eq!(
@@ -1,14 +1,39 @@
 use crate::{
+    consensus::BeneficiaryFunction,
     genesis::GenesisState,
-    models::{BlockNumber, ChainSpec, NetworkId, H256},
+    models::{BlockNumber, ChainId, ChainSpec, NetworkId, Revision, SealVerificationParams, H256},
 };
+use anyhow::bail;
+use std::time::Duration;
 
 const REPOSITORY_URL: &str = "https://github.com/akula-bft/akula";
 
+/// Chain ids of public networks on which consensus checks can never be relaxed.
+const PUBLIC_CHAIN_IDS: [ChainId; 6] = [
+    ChainId(1),
+    ChainId(3),
+    ChainId(4),
+    ChainId(5),
+    ChainId(17_000),
+    ChainId(11_155_111),
+];
+
+/// How much of the consensus rules headers are checked against.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConsensusMode {
+    #[default]
+    Full,
+    /// Seals (PoW, clique signatures) and the rules of their sealing scheme, such as clique
+    /// extra data and signing periods, are not verified. Base header rules and linkage still
+    /// apply. Only available on private networks, see [`ChainConfig::without_seal_verification`].
+    NoSealVerification,
+}
+
 #[derive(Debug, Clone)]
 pub struct ChainConfig {
     pub chain_spec: ChainSpec,
     pub genesis_hash: H256,
+    consensus_mode: ConsensusMode,
 }
 
 impl From<ChainSpec> for ChainConfig {
@@ -17,6 +42,7 @@ impl From<ChainSpec> for ChainConfig {
         Self {
             chain_spec,
             genesis_hash: genesis.header(&genesis.initial_state()).hash(),
+            consensus_mode: ConsensusMode::default(),
         }
     }
 }
@@ -26,6 +52,16 @@ impl ChainConfig {
         ChainSpec::load_builtin(name).map(From::from)
     }
 
+    /// Preset of a live network, see [`ChainSpec::load_live`].
+    pub fn from_chain_name(name: &str) -> anyhow::Result<Self> {
+        ChainSpec::load_live(name).map(From::from)
+    }
+
+    /// Builds config from a genesis file in geth format, see [`ChainSpec::from_geth_genesis`].
+    pub fn from_genesis_json(reader: impl std::io::Read) -> anyhow::Result<Self> {
+        ChainSpec::from_geth_genesis(reader).map(From::from)
+    }
+
     pub const fn network_id(&self) -> NetworkId {
         self.chain_spec.params.network_id
     }
@@ -41,6 +77,77 @@ impl ChainConfig {
             .collect::<Vec<_>>()
     }
 
+    /// Revision active at the given block.
+    pub fn revision_at(&self, block_number: impl Into<BlockNumber>) -> Revision {
+        self.chain_spec.upgrades.revision_at(block_number)
+    }
+
+    /// Whether the given revision is active at the given block.
+    pub fn is_active(&self, revision: Revision, block_number: impl Into<BlockNumber>) -> bool {
+        self.revision_at(block_number) >= revision
+    }
+
+    pub fn is_berlin(&self, block_number: impl Into<BlockNumber>) -> bool {
+        self.is_active(Revision::Berlin, block_number)
+    }
+
+    pub fn is_london(&self, block_number: impl Into<BlockNumber>) -> bool {
+        self.is_active(Revision::London, block_number)
+    }
+
+    /// Expected time between blocks: the clique period, or the beacon chain slot time for
+    /// chains that are or will be merged.
+    pub fn block_time(&self) -> Duration {
+        match &self.chain_spec.consensus.seal_verification {
+            SealVerificationParams::Clique { period, .. } => *period,
+            SealVerificationParams::Beacon { .. } => Duration::from_secs(12),
+        }
+    }
+
+    /// Scheduled upgrades in order of activation.
+    pub fn fork_schedule(&self) -> impl Iterator<Item = (Revision, BlockNumber)> {
+        self.chain_spec.upgrades.schedule()
+    }
+
+    /// Maximum length of header extra data allowed by the seal engine for any block, `None`
+    /// if unbounded. Clique headers carry the signer list of checkpoint blocks there, also on
+    /// merged chains that were sealed by clique before the merge.
+    pub fn max_extra_data_length(&self) -> Option<usize> {
+        let SealVerificationParams::Beacon { beneficiary, .. } =
+            &self.chain_spec.consensus.seal_verification
+        else {
+            return None;
+        };
+
+        if beneficiary
+            .values()
+            .any(|f| *f == BeneficiaryFunction::Clique)
+        {
+            None
+        } else {
+            Some(32)
+        }
+    }
+
+    pub const fn consensus_mode(&self) -> ConsensusMode {
+        self.consensus_mode
+    }
+
+    /// Disables seal verification for test and dev networks. Refused on public chains.
+    pub fn without_seal_verification(mut self) -> anyhow::Result<Self> {
+        let chain_id = self.chain_spec.params.chain_id;
+        if PUBLIC_CHAIN_IDS.contains(&chain_id) {
+            bail!(
+                "seal verification cannot be disabled on public chain {} (chain id {})",
+                self.chain_name(),
+                chain_id.0
+            );
+        }
+
+        self.consensus_mode = ConsensusMode::NoSealVerification;
+        Ok(self)
+    }
+
     pub fn bootnodes(&self) -> Vec<String> {
         self.chain_spec.p2p.bootnodes.clone()
     }
@@ -49,3 +156,80 @@ impl ChainConfig {
         self.chain_spec.p2p.dns.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ChainId;
+
+    #[test]
+    fn live_network_presets() {
+        for (name, chain_id, forks) in [
+            (
+                "mainnet",
+                1,
+                vec![
+                    (Revision::Berlin, 12_244_000),
+                    (Revision::London, 12_965_000),
+                    (Revision::Paris, 15_537_394),
+                ],
+            ),
+            (
+                "goerli",
+                5,
+                vec![
+                    (Revision::Berlin, 4_460_644),
+                    (Revision::London, 5_062_605),
+                    (Revision::Paris, 7_382_819),
+                ],
+            ),
+            (
+                "sepolia",
+                11_155_111,
+                vec![(Revision::London, 0), (Revision::Paris, 1_450_409)],
+            ),
+        ] {
+            let config = ChainConfig::from_chain_name(name).unwrap();
+            assert_eq!(
+                config.chain_spec.params.chain_id,
+                ChainId(chain_id),
+                "{name}"
+            );
+            assert_eq!(config.network_id(), NetworkId(chain_id), "{name}");
+            assert!(!config.bootnodes().is_empty(), "{name}");
+            for (revision, block) in forks {
+                assert_eq!(config.revision_at(block), revision, "{name} #{block}");
+                if block > 0 {
+                    assert!(
+                        config.revision_at(block - 1) < revision,
+                        "{name} #{}",
+                        block - 1
+                    );
+                }
+            }
+            assert_eq!(config.block_time(), Duration::from_secs(12));
+        }
+
+        for name in ["ropsten", "rinkeby", "holesky", "unknown"] {
+            assert!(ChainConfig::from_chain_name(name).is_err(), "{name}");
+        }
+        assert!(ChainConfig::new("ropsten").is_ok());
+    }
+
+    #[test]
+    fn seal_verification_toggle() {
+        for name in ["mainnet", "ropsten", "rinkeby", "goerli", "sepolia"] {
+            let config = ChainConfig::new(name).unwrap();
+            assert!(config.without_seal_verification().is_err(), "{name}");
+        }
+
+        let mut chain_spec = ChainSpec::load_builtin("mainnet").unwrap();
+        chain_spec.params.chain_id = ChainId(1337);
+        let config = ChainConfig::from(chain_spec);
+        assert_eq!(config.consensus_mode(), ConsensusMode::Full);
+        assert_eq!(
+            config.without_seal_verification().unwrap().consensus_mode(),
+            ConsensusMode::NoSealVerification
+        );
+    }
+}
@@ -0,0 +1,447 @@
+use crate::models::*;
+use anyhow::{bail, format_err, Context};
+use bytes::Bytes;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io::Read,
+    time::Duration,
+};
+
+/// Base fee of the genesis block when London is active from genesis, per EIP-1559.
+const INITIAL_BASE_FEE: u64 = 1_000_000_000;
+
+/// Numeric value as found in geth genesis files: a JSON number, or a decimal or hex string.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+enum Quantity {
+    Number(u64),
+    String(String),
+}
+
+impl Quantity {
+    fn as_u64(&self) -> anyhow::Result<u64> {
+        match self {
+            Self::Number(v) => Ok(*v),
+            Self::String(s) => if let Some(hex) = s.strip_prefix("0x") {
+                if hex.is_empty() {
+                    Ok(0)
+                } else {
+                    u64::from_str_radix(hex, 16)
+                }
+            } else {
+                s.parse()
+            }
+            .with_context(|| format!("invalid quantity {s}")),
+        }
+    }
+
+    fn as_u256(&self) -> anyhow::Result<U256> {
+        match self {
+            Self::Number(v) => Ok(U256::from(*v)),
+            Self::String(s) => if let Some(hex) = s.strip_prefix("0x") {
+                if hex.is_empty() {
+                    Ok(U256::ZERO)
+                } else {
+                    U256::from_str_radix(hex, 16)
+                }
+            } else {
+                U256::from_str_radix(s, 10)
+            }
+            .with_context(|| format!("invalid quantity {s}")),
+        }
+    }
+}
+
+fn parse_hex(s: &str) -> anyhow::Result<Vec<u8>> {
+    hex::decode(s.strip_prefix("0x").unwrap_or(s)).with_context(|| format!("invalid hex {s}"))
+}
+
+fn parse_fixed<const N: usize>(s: &str) -> anyhow::Result<[u8; N]> {
+    parse_hex(s)?
+        .try_into()
+        .map_err(|v: Vec<u8>| format_err!("expected {N} bytes, got {}: {s}", v.len()))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GethCliqueConfig {
+    period: u64,
+    epoch: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GethChainConfig {
+    chain_id: Option<u64>,
+    homestead_block: Option<u64>,
+    eip150_block: Option<u64>,
+    eip158_block: Option<u64>,
+    byzantium_block: Option<u64>,
+    constantinople_block: Option<u64>,
+    petersburg_block: Option<u64>,
+    istanbul_block: Option<u64>,
+    berlin_block: Option<u64>,
+    london_block: Option<u64>,
+    merge_netsplit_block: Option<u64>,
+    terminal_total_difficulty: Option<Quantity>,
+    clique: Option<GethCliqueConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GethAccount {
+    balance: Option<Quantity>,
+    code: Option<String>,
+    #[serde(default)]
+    storage: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GethGenesis {
+    config: Option<GethChainConfig>,
+    difficulty: Option<Quantity>,
+    gas_limit: Option<Quantity>,
+    extra_data: Option<String>,
+    timestamp: Option<Quantity>,
+    nonce: Option<Quantity>,
+    mix_hash: Option<String>,
+    coinbase: Option<String>,
+    base_fee_per_gas: Option<Quantity>,
+    #[serde(default)]
+    alloc: HashMap<String, GethAccount>,
+}
+
+impl ChainSpec {
+    /// Loads chain spec from a genesis file in geth format. Unknown fields are ignored.
+    pub fn from_geth_genesis(reader: impl Read) -> anyhow::Result<Self> {
+        let genesis: GethGenesis =
+            serde_json::from_reader(reader).context("failed to parse geth genesis")?;
+
+        let config = genesis
+            .config
+            .ok_or_else(|| format_err!("missing required field `config`"))?;
+        let chain_id = config
+            .chain_id
+            .ok_or_else(|| format_err!("missing required field `config.chainId`"))?;
+        let difficulty = genesis
+            .difficulty
+            .ok_or_else(|| format_err!("missing required field `difficulty`"))?
+            .as_u256()
+            .context("invalid `difficulty`")?;
+        let gas_limit = genesis
+            .gas_limit
+            .ok_or_else(|| format_err!("missing required field `gasLimit`"))?
+            .as_u64()
+            .context("invalid `gasLimit`")?;
+        let extra_data = genesis
+            .extra_data
+            .as_deref()
+            .map(parse_hex)
+            .transpose()
+            .context("invalid `extraData`")?
+            .unwrap_or_default();
+
+        let upgrades = Upgrades {
+            homestead: config.homestead_block.map(BlockNumber),
+            tangerine: config.eip150_block.map(BlockNumber),
+            spurious: config.eip158_block.map(BlockNumber),
+            byzantium: config.byzantium_block.map(BlockNumber),
+            constantinople: config.constantinople_block.map(BlockNumber),
+            petersburg: config.petersburg_block.map(BlockNumber),
+            istanbul: config.istanbul_block.map(BlockNumber),
+            berlin: config.berlin_block.map(BlockNumber),
+            london: config.london_block.map(BlockNumber),
+            paris: config.merge_netsplit_block.map(BlockNumber),
+        };
+
+        let (seal_verification, seal) = if let Some(clique) = config.clique {
+            const VANITY_LEN: usize = 32;
+            const SIGNATURE_LEN: usize = 65;
+
+            if extra_data.len() < VANITY_LEN + SIGNATURE_LEN
+                || (extra_data.len() - VANITY_LEN - SIGNATURE_LEN) % ADDRESS_LENGTH != 0
+            {
+                bail!(
+                    "invalid clique `extraData` length {}, expected vanity, signers and signature",
+                    extra_data.len()
+                );
+            }
+
+            let score = if difficulty == U256::from(BlockScore::NoTurn as u8) {
+                BlockScore::NoTurn
+            } else if difficulty == U256::from(BlockScore::InTurn as u8) {
+                BlockScore::InTurn
+            } else {
+                bail!("invalid clique genesis `difficulty` {difficulty}")
+            };
+
+            (
+                SealVerificationParams::Clique {
+                    period: Duration::from_secs(clique.period),
+                    epoch: clique.epoch,
+                },
+                Seal::Clique {
+                    vanity: H256::from_slice(&extra_data[..VANITY_LEN]),
+                    score,
+                    signers: extra_data[VANITY_LEN..extra_data.len() - SIGNATURE_LEN]
+                        .chunks(ADDRESS_LENGTH)
+                        .map(Address::from_slice)
+                        .collect(),
+                },
+            )
+        } else {
+            let mut block_reward = BTreeMap::new();
+            block_reward.insert(BlockNumber(0), U256::from(5 * ETHER));
+            if let Some(byzantium) = upgrades.byzantium {
+                block_reward.insert(byzantium, U256::from(3 * ETHER));
+            }
+            if let Some(constantinople) = upgrades.constantinople {
+                block_reward.insert(constantinople, U256::from(2 * ETHER));
+            }
+
+            (
+                SealVerificationParams::Beacon {
+                    terminal_total_difficulty: config
+                        .terminal_total_difficulty
+                        .map(|v| v.as_u256())
+                        .transpose()
+                        .context("invalid `config.terminalTotalDifficulty`")?,
+                    terminal_block_hash: None,
+                    terminal_block_number: None,
+                    since: upgrades.paris,
+                    block_reward,
+                    beneficiary: Default::default(),
+                },
+                Seal::Ethash {
+                    vanity: Bytes::from(extra_data),
+                    difficulty,
+                    nonce: H64::from_low_u64_be(
+                        genesis
+                            .nonce
+                            .map(|v| v.as_u64())
+                            .transpose()
+                            .context("invalid `nonce`")?
+                            .unwrap_or_default(),
+                    ),
+                    mix_hash: genesis
+                        .mix_hash
+                        .as_deref()
+                        .map(parse_fixed::<32>)
+                        .transpose()
+                        .context("invalid `mixHash`")?
+                        .map(H256)
+                        .unwrap_or_default(),
+                },
+            )
+        };
+
+        let mut balances = HashMap::new();
+        for (address, account) in genesis.alloc {
+            let address = Address::from(
+                parse_fixed::<ADDRESS_LENGTH>(&address)
+                    .with_context(|| format!("invalid `alloc` address {address}"))?,
+            );
+
+            let has_code = account
+                .code
+                .as_deref()
+                .map(|code| !code.is_empty() && code != "0x")
+                .unwrap_or(false);
+            if has_code || !account.storage.is_empty() {
+                bail!("`alloc` account {address:?} has code or storage, which is not supported");
+            }
+
+            balances.insert(
+                address,
+                account
+                    .balance
+                    .map(|v| v.as_u256())
+                    .transpose()
+                    .with_context(|| format!("invalid balance of {address:?}"))?
+                    .unwrap_or_default(),
+            );
+        }
+
+        let base_fee_per_gas = match genesis.base_fee_per_gas {
+            Some(v) => Some(v.as_u256().context("invalid `baseFeePerGas`")?),
+            None if upgrades.london == Some(BlockNumber(0)) => Some(U256::from(INITIAL_BASE_FEE)),
+            None => None,
+        };
+
+        Ok(Self {
+            name: format!("Chain {chain_id}"),
+            consensus: ConsensusParams {
+                seal_verification,
+                eip1559_block: upgrades.london,
+                max_future_drift: None,
+            },
+            upgrades,
+            params: Params {
+                chain_id: ChainId(chain_id),
+                network_id: NetworkId(chain_id),
+                additional_forks: BTreeSet::new(),
+            },
+            genesis: Genesis {
+                number: BlockNumber(0),
+                author: genesis
+                    .coinbase
+                    .as_deref()
+                    .map(parse_fixed::<ADDRESS_LENGTH>)
+                    .transpose()
+                    .context("invalid `coinbase`")?
+                    .map(Address::from)
+                    .unwrap_or_default(),
+                gas_limit,
+                timestamp: genesis
+                    .timestamp
+                    .map(|v| v.as_u64())
+                    .transpose()
+                    .context("invalid `timestamp`")?
+                    .unwrap_or_default(),
+                seal,
+                base_fee_per_gas,
+            },
+            contracts: Default::default(),
+            balances: if balances.is_empty() {
+                Default::default()
+            } else {
+                [(BlockNumber(0), balances)].into_iter().collect()
+            },
+            p2p: P2PParams {
+                bootnodes: vec![],
+                dns: None,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    #[test]
+    fn clique_genesis() {
+        let spec = ChainSpec::from_geth_genesis(
+            r#"{
+                "config": {
+                    "chainId": 1337,
+                    "homesteadBlock": 0,
+                    "eip150Block": 0,
+                    "eip155Block": 0,
+                    "eip158Block": 0,
+                    "byzantiumBlock": 0,
+                    "constantinopleBlock": 0,
+                    "petersburgBlock": 0,
+                    "istanbulBlock": 0,
+                    "berlinBlock": 0,
+                    "londonBlock": 10,
+                    "clique": { "period": 5, "epoch": 30000 },
+                    "someFutureField": true
+                },
+                "difficulty": "1",
+                "gasLimit": "0x1c9c380",
+                "timestamp": "0x5f5e100",
+                "extraData": "0x000000000000000000000000000000000000000000000000000000000000000042eb768f2244c8811c63729a21a3569731535f060000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
+                "alloc": {
+                    "42eb768f2244c8811c63729a21a3569731535f06": { "balance": "1000000000000000000000" },
+                    "0x0000000000000000000000000000000000000001": { "balance": "0x1" }
+                }
+            }"#
+            .as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(spec.params.chain_id, ChainId(1337));
+        assert_eq!(spec.params.network_id, NetworkId(1337));
+        assert_eq!(spec.upgrades.berlin, Some(BlockNumber(0)));
+        assert_eq!(spec.upgrades.london, Some(BlockNumber(10)));
+        assert_eq!(spec.consensus.eip1559_block, Some(BlockNumber(10)));
+        assert_eq!(
+            spec.consensus.seal_verification,
+            SealVerificationParams::Clique {
+                period: Duration::from_secs(5),
+                epoch: 30_000,
+            }
+        );
+        assert_eq!(spec.genesis.gas_limit, 30_000_000);
+        assert_eq!(spec.genesis.timestamp, 100_000_000);
+        assert_eq!(spec.genesis.base_fee_per_gas, None);
+        assert_eq!(
+            spec.genesis.seal,
+            Seal::Clique {
+                vanity: H256::zero(),
+                score: BlockScore::NoTurn,
+                signers: vec![hex!("42eb768f2244c8811c63729a21a3569731535f06").into()],
+            }
+        );
+        assert_eq!(
+            spec.balances[&BlockNumber(0)],
+            [
+                (
+                    hex!("42eb768f2244c8811c63729a21a3569731535f06").into(),
+                    U256::from(1000 * ETHER)
+                ),
+                (Address::from_low_u64_be(1), U256::ONE),
+            ]
+            .into_iter()
+            .collect()
+        );
+    }
+
+    #[test]
+    fn ethash_genesis() {
+        let spec = ChainSpec::from_geth_genesis(
+            r#"{
+                "config": { "chainId": 12345, "byzantiumBlock": 5, "londonBlock": 0 },
+                "difficulty": 131072,
+                "gasLimit": 8000000,
+                "nonce": "0x42",
+                "extraData": "0x1234"
+            }"#
+            .as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            spec.genesis.base_fee_per_gas,
+            Some(U256::from(INITIAL_BASE_FEE))
+        );
+        assert_eq!(
+            spec.genesis.seal,
+            Seal::Ethash {
+                vanity: hex!("1234").to_vec().into(),
+                difficulty: U256::from(131_072_u64),
+                nonce: H64::from_low_u64_be(0x42),
+                mix_hash: H256::zero(),
+            }
+        );
+        let SealVerificationParams::Beacon { block_reward, .. } = spec.consensus.seal_verification else { unreachable!() };
+        assert_eq!(
+            block_reward,
+            [
+                (BlockNumber(0), U256::from(5 * ETHER)),
+                (BlockNumber(5), U256::from(3 * ETHER)),
+            ]
+            .into_iter()
+            .collect()
+        );
+    }
+
+    #[test]
+    fn missing_fields() {
+        for (json, field) in [
+            (r#"{ "difficulty": "0x1", "gasLimit": "0x1" }"#, "`config`"),
+            (
+                r#"{ "config": {}, "difficulty": "0x1", "gasLimit": "0x1" }"#,
+                "`config.chainId`",
+            ),
+            (r#"{ "config": { "chainId": 1 }, "gasLimit": "0x1" }"#, "`difficulty`"),
+            (r#"{ "config": { "chainId": 1 }, "difficulty": "0x1" }"#, "`gasLimit`"),
+        ] {
+            let e = ChainSpec::from_geth_genesis(json.as_bytes()).unwrap_err();
+            assert!(e.to_string().contains(field), "{e}");
+        }
+    }
+}
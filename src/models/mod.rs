@@ -1,8 +1,10 @@
 mod account;
 mod block;
+mod block_range;
 mod bloom;
 mod chainspec;
 mod config;
+mod geth_genesis;
 mod header;
 mod log;
 mod receipt;
@@ -10,8 +12,8 @@ mod revision;
 mod transaction;
 
 pub use self::{
-    account::*, block::*, bloom::*, chainspec::*, config::*, header::*, log::*, receipt::*,
-    revision::*, transaction::*,
+    account::*, block::*, block_range::*, bloom::*, chainspec::*, config::*, header::*, log::*,
+    receipt::*, revision::*, transaction::*,
 };
 
 use derive_more::*;
@@ -238,6 +240,34 @@ u64_wrapper!(ChainId);
 u64_wrapper!(NetworkId);
 u64_wrapper!(TxIndex);
 
+impl BlockNumber {
+    #[inline(always)]
+    pub const fn checked_add(self, rhs: u64) -> Option<Self> {
+        match self.0.checked_add(rhs) {
+            Some(v) => Some(Self(v)),
+            None => None,
+        }
+    }
+
+    #[inline(always)]
+    pub const fn checked_sub(self, rhs: u64) -> Option<Self> {
+        match self.0.checked_sub(rhs) {
+            Some(v) => Some(Self(v)),
+            None => None,
+        }
+    }
+
+    #[inline(always)]
+    pub const fn saturating_add(self, rhs: u64) -> Self {
+        Self(self.0.saturating_add(rhs))
+    }
+
+    #[inline(always)]
+    pub const fn saturating_sub(self, rhs: u64) -> Self {
+        Self(self.0.saturating_sub(rhs))
+    }
+}
+
 // Keccak-256 hash of an empty string, KEC("").
 pub const EMPTY_HASH: H256 = H256(hex!(
     "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
@@ -255,3 +285,22 @@ pub const EMPTY_ROOT: H256 = H256(hex!(
 
 pub const GIGA: u64 = 1_000_000_000; // = 10^9
 pub const ETHER: u128 = 1_000_000_000_000_000_000; // = 10^18
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_number_arithmetic() {
+        assert_eq!(BlockNumber(1).checked_add(2), Some(BlockNumber(3)));
+        assert_eq!(BlockNumber(u64::MAX).checked_add(1), None);
+        assert_eq!(
+            BlockNumber(u64::MAX).saturating_add(1),
+            BlockNumber(u64::MAX)
+        );
+
+        assert_eq!(BlockNumber(3).checked_sub(2), Some(BlockNumber(1)));
+        assert_eq!(BlockNumber(0).checked_sub(1), None);
+        assert_eq!(BlockNumber(1).saturating_sub(2), BlockNumber(0));
+    }
+}
@@ -1,4 +1,6 @@
-use super::{stash::Stash, Node, Sentry};
+use super::{
+    stash::Stash, Node, PeerReputation, RequestRateLimiter, Sentry, SentryConnectionState,
+};
 use crate::{
     models::{BlockNumber, ChainConfig, H256, U256},
     p2p::types::Status,
@@ -6,7 +8,8 @@ use crate::{
 use hashlink::LruCache;
 use http::Uri;
 use parking_lot::{Mutex, RwLock};
-use std::sync::Arc;
+use rand::{rngs::StdRng, SeedableRng};
+use std::{sync::Arc, time::Duration};
 use tokio::sync::{watch, Notify};
 use tonic::transport::Channel;
 
@@ -16,6 +19,10 @@ pub struct NodeBuilder {
     stash: Option<Arc<dyn Stash>>,
     config: ChainConfig,
     status: Option<Status>,
+    reputation: Option<PeerReputation>,
+    request_rate_limiter: Option<Arc<RequestRateLimiter>>,
+    peer_selection_seed: Option<u64>,
+    send_timeout: Option<Duration>,
 }
 
 impl NodeBuilder {
@@ -26,6 +33,10 @@ impl NodeBuilder {
             sentries: Default::default(),
             stash: Default::default(),
             status: Default::default(),
+            reputation: Default::default(),
+            request_rate_limiter: Default::default(),
+            peer_selection_seed: Default::default(),
+            send_timeout: Default::default(),
         }
     }
 
@@ -51,6 +62,31 @@ impl NodeBuilder {
         self
     }
 
+    pub fn set_peer_reputation(mut self, reputation: PeerReputation) -> Self {
+        self.reputation = Some(reputation);
+        self
+    }
+
+    /// Limits outgoing header and body requests. The limiter may be shared with other nodes.
+    pub fn set_request_rate_limiter(mut self, limiter: Arc<RequestRateLimiter>) -> Self {
+        self.request_rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Makes the choice of peers for requests reproducible, e.g. to compare benchmark runs.
+    /// Seeded from entropy by default. Peers picked by the sentries stay random.
+    pub fn set_peer_selection_seed(mut self, seed: u64) -> Self {
+        self.peer_selection_seed = Some(seed);
+        self
+    }
+
+    /// How long to wait for a sentry to complete a send, [`Node::DEFAULT_SEND_TIMEOUT`] by
+    /// default.
+    pub fn set_send_timeout(mut self, timeout: Duration) -> Self {
+        self.send_timeout = Some(timeout);
+        self
+    }
+
     pub fn build(self) -> anyhow::Result<Node> {
         let stash = self.stash.unwrap_or_else(|| Arc::new(()));
         let sentries = self.sentries;
@@ -63,6 +99,10 @@ impl NodeBuilder {
         let forks = config.forks().into_iter().map(|f| *f).collect::<Vec<_>>();
 
         let (chain_tip_sender, chain_tip) = watch::channel(Default::default());
+        let sentry_states = sentries
+            .iter()
+            .map(|_| Arc::new(watch::channel(SentryConnectionState::Connected).0))
+            .collect();
 
         Ok(Node {
             stash,
@@ -72,9 +112,44 @@ impl NodeBuilder {
             chain_tip,
             chain_tip_sender,
             bad_blocks: Default::default(),
+            reputation: self.reputation.unwrap_or_default(),
+            peer_counters: Default::default(),
+            request_rate_limiter: self.request_rate_limiter,
+            send_timeout: self.send_timeout.unwrap_or(Node::DEFAULT_SEND_TIMEOUT),
+            peer_selection_rng: Mutex::new(
+                self.peer_selection_seed
+                    .map(StdRng::seed_from_u64)
+                    .unwrap_or_else(StdRng::from_entropy),
+            ),
+            sentry_states,
             block_cache: Mutex::new(LruCache::new(64)),
             block_cache_notify: Notify::new(),
             forks,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::res::chainspec::MAINNET;
+    use rand::Rng;
+
+    #[tokio::test]
+    async fn peer_selection_seed() {
+        let draws = |seed: Option<u64>| {
+            let mut builder = NodeBuilder::new(ChainConfig::from(MAINNET.clone()))
+                .add_sentry(Uri::from_static("http://127.0.0.1:1"));
+            if let Some(seed) = seed {
+                builder = builder.set_peer_selection_seed(seed);
+            }
+            let node = builder.build().unwrap();
+            let mut rng = node.peer_selection_rng.lock();
+            (0..16).map(|_| rng.gen::<u64>()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(draws(Some(1)), draws(Some(1)));
+        assert_ne!(draws(Some(1)), draws(Some(2)));
+        assert_ne!(draws(None), draws(None));
+    }
+}
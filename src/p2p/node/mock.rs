@@ -0,0 +1,811 @@
+//! In-process sentry for deterministic tests of code driving a [`Node`].
+
+use super::{Node, NodeBuilder, PeerId};
+use crate::{
+    models::{BlockHeader, ChainConfig},
+    p2p::types::{BlockHeaders, BlockId, GetBlockHeaders, MessageId},
+};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use ethereum_interfaces::{
+    sentry::{
+        self as grpc_sentry,
+        sentry_server::{Sentry, SentryServer},
+        HandShakeReply, InboundMessage, OutboundMessageData, PeerByIdReply, PeerCountReply,
+        PeerEvent, PeerEventsRequest, PeerMinBlockRequest, SentPeers, SetStatusReply,
+    },
+    types::NodeInfoReply,
+};
+use fastrlp::{Decodable, Encodable};
+use futures::Stream;
+use parking_lot::Mutex;
+use rand::seq::IteratorRandom;
+use std::{
+    collections::HashSet,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio::{net::TcpListener, sync::broadcast};
+use tokio_stream::{wrappers::BroadcastStream, StreamExt};
+use tonic::{transport::Server, Request, Response, Status};
+
+type HeaderResponder = dyn Fn(PeerId, &GetBlockHeaders) -> Option<Vec<BlockHeader>> + Send + Sync;
+
+/// Scriptable sentry that records what the node sends and answers header requests on behalf
+/// of fake peers.
+///
+/// Every connected peer receives every outbound message. Header requests are answered by the
+/// responder set with [`MockSentry::set_header_responder`]; peers for which it returns `None`
+/// stay silent, as if the request timed out. Penalized peers are disconnected.
+pub struct MockSentry {
+    peers: Mutex<Vec<PeerId>>,
+    header_responder: Mutex<Option<Arc<HeaderResponder>>>,
+    requests: Mutex<Vec<GetBlockHeaders>>,
+    requested_peers: Mutex<HashSet<PeerId>>,
+    penalties: Mutex<Vec<PeerId>>,
+    /// `None` ends all message streams.
+    inbound: broadcast::Sender<Option<InboundMessage>>,
+    down: AtomicBool,
+    sends_stalled: AtomicBool,
+}
+
+impl Default for MockSentry {
+    fn default() -> Self {
+        Self {
+            peers: Default::default(),
+            header_responder: Default::default(),
+            requests: Default::default(),
+            requested_peers: Default::default(),
+            penalties: Default::default(),
+            inbound: broadcast::channel(1024).0,
+            down: Default::default(),
+            sends_stalled: Default::default(),
+        }
+    }
+}
+
+impl MockSentry {
+    pub fn new(peers: impl IntoIterator<Item = PeerId>) -> Arc<Self> {
+        let sentry = Self::default();
+        sentry.peers.lock().extend(peers);
+        Arc::new(sentry)
+    }
+
+    /// Serves the sentry on a local port and builds a node connected to it.
+    pub async fn node(self: &Arc<Self>, config: ChainConfig) -> anyhow::Result<Arc<Node>> {
+        self.node_with(NodeBuilder::new(config)).await
+    }
+
+    /// Like [`MockSentry::node`], but with a node builder that is already set up.
+    pub async fn node_with(self: &Arc<Self>, builder: NodeBuilder) -> anyhow::Result<Arc<Node>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let incoming = async_stream::stream! {
+            loop {
+                yield listener.accept().await.map(|(stream, _)| stream);
+            }
+        };
+
+        tokio::spawn(
+            Server::builder()
+                .add_service(SentryServer::from_arc(self.clone()))
+                .serve_with_incoming(incoming),
+        );
+
+        Ok(Arc::new(
+            builder
+                .add_sentry(format!("http://{addr}").parse::<http::Uri>()?)
+                .build()?,
+        ))
+    }
+
+    pub fn set_header_responder<F>(&self, responder: F)
+    where
+        F: Fn(PeerId, &GetBlockHeaders) -> Option<Vec<BlockHeader>> + Send + Sync + 'static,
+    {
+        *self.header_responder.lock() = Some(Arc::new(responder));
+    }
+
+    /// Makes every peer answer ascending header requests from the given chain, indexed by
+    /// block number.
+    pub fn serve_chain(&self, chain: Vec<BlockHeader>) {
+        self.set_header_responder(move |_, request| {
+            let BlockId::Number(start) = request.params.start else {
+                return None;
+            };
+            Some(
+                chain
+                    .iter()
+                    .skip(start.0 as usize)
+                    .step_by(request.params.skip as usize + 1)
+                    .take(request.params.limit as usize)
+                    .cloned()
+                    .collect(),
+            )
+        });
+    }
+
+    pub fn connect_peer(&self, peer_id: PeerId) {
+        self.peers.lock().push(peer_id);
+    }
+
+    pub fn drop_peer(&self, peer_id: PeerId) {
+        self.peers.lock().retain(|&peer| peer != peer_id);
+    }
+
+    pub fn connected_peers(&self) -> Vec<PeerId> {
+        self.peers.lock().clone()
+    }
+
+    /// Header requests received so far, in order.
+    pub fn requests_received(&self) -> Vec<GetBlockHeaders> {
+        self.requests.lock().clone()
+    }
+
+    /// Peers that were sent at least one header request.
+    pub fn requested_peers(&self) -> HashSet<PeerId> {
+        self.requested_peers.lock().clone()
+    }
+
+    /// Peers penalized so far, in order.
+    pub fn penalties_issued(&self) -> Vec<PeerId> {
+        self.penalties.lock().clone()
+    }
+
+    /// Delivers a message to the node as if the peer sent it.
+    pub fn inject(&self, peer_id: PeerId, id: MessageId, msg: &impl Encodable) {
+        let mut data = BytesMut::new();
+        msg.encode(&mut data);
+        let _ = self.inbound.send(Some(InboundMessage {
+            id: grpc_sentry::MessageId::from(id) as i32,
+            data: data.freeze(),
+            peer_id: Some(peer_id.into()),
+        }));
+    }
+
+    /// Delivers a raw, possibly malformed, payload to the node as if the peer sent it.
+    pub fn inject_raw(&self, peer_id: PeerId, id: MessageId, data: impl Into<Bytes>) {
+        let _ = self.inbound.send(Some(InboundMessage {
+            id: grpc_sentry::MessageId::from(id) as i32,
+            data: data.into(),
+            peer_id: Some(peer_id.into()),
+        }));
+    }
+
+    /// Simulates the sentry going down: open message streams end and new ones are refused
+    /// until it is brought back up.
+    pub fn set_down(&self, down: bool) {
+        self.down.store(down, Ordering::SeqCst);
+        if down {
+            let _ = self.inbound.send(None);
+        }
+    }
+
+    /// Simulates a wedged sentry: sends never complete while stalled, and nothing is
+    /// delivered to the peers.
+    pub fn set_sends_stalled(&self, stalled: bool) {
+        self.sends_stalled.store(stalled, Ordering::SeqCst);
+    }
+
+    async fn wait_unless_stalled(&self) {
+        if self.sends_stalled.load(Ordering::SeqCst) {
+            futures::future::pending::<()>().await;
+        }
+    }
+
+    fn deliver(&self, peers: Vec<PeerId>, data: Option<OutboundMessageData>) -> SentPeers {
+        let Some(data) = data else {
+            return SentPeers { peers: vec![] };
+        };
+
+        if data.id == grpc_sentry::MessageId::GetBlockHeaders66 as i32 {
+            if let Ok(request) = GetBlockHeaders::decode(&mut &*data.data) {
+                self.requests.lock().push(request.clone());
+                self.requested_peers.lock().extend(&peers);
+
+                let responder = self.header_responder.lock().clone();
+                if let Some(responder) = responder {
+                    for &peer_id in &peers {
+                        if let Some(headers) = (responder)(peer_id, &request) {
+                            self.inject(
+                                peer_id,
+                                MessageId::BlockHeaders,
+                                &BlockHeaders {
+                                    request_id: request.request_id,
+                                    headers,
+                                },
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        SentPeers {
+            peers: peers.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl Sentry for MockSentry {
+    async fn penalize_peer(
+        &self,
+        request: Request<grpc_sentry::PenalizePeerRequest>,
+    ) -> Result<Response<()>, Status> {
+        let peer_id = request
+            .into_inner()
+            .peer_id
+            .ok_or_else(|| Status::invalid_argument("no peer id"))?
+            .into();
+        self.penalties.lock().push(peer_id);
+        self.drop_peer(peer_id);
+
+        Ok(Response::new(()))
+    }
+
+    async fn peers(&self, _: Request<()>) -> Result<Response<grpc_sentry::PeersReply>, Status> {
+        Err(Status::unimplemented(""))
+    }
+
+    async fn peer_count(
+        &self,
+        _: Request<grpc_sentry::PeerCountRequest>,
+    ) -> Result<Response<PeerCountReply>, Status> {
+        Ok(Response::new(PeerCountReply {
+            count: self.peers.lock().len() as u64,
+        }))
+    }
+
+    async fn peer_by_id(
+        &self,
+        _: Request<grpc_sentry::PeerByIdRequest>,
+    ) -> Result<Response<PeerByIdReply>, Status> {
+        Err(Status::unimplemented(""))
+    }
+
+    type PeerEventsStream = Pin<Box<dyn Stream<Item = Result<PeerEvent, Status>> + Send + Sync>>;
+
+    async fn peer_events(
+        &self,
+        _: Request<PeerEventsRequest>,
+    ) -> Result<Response<Self::PeerEventsStream>, Status> {
+        Ok(Response::new(Box::pin(futures::stream::pending())))
+    }
+
+    async fn send_message_by_min_block(
+        &self,
+        request: Request<grpc_sentry::SendMessageByMinBlockRequest>,
+    ) -> Result<Response<SentPeers>, Status> {
+        self.wait_unless_stalled().await;
+        Ok(Response::new(self.deliver(
+            self.connected_peers(),
+            request.into_inner().data,
+        )))
+    }
+
+    async fn send_message_by_id(
+        &self,
+        request: Request<grpc_sentry::SendMessageByIdRequest>,
+    ) -> Result<Response<SentPeers>, Status> {
+        self.wait_unless_stalled().await;
+        let grpc_sentry::SendMessageByIdRequest { peer_id, data } = request.into_inner();
+        let peer_id: PeerId = peer_id
+            .ok_or_else(|| Status::invalid_argument("no peer id"))?
+            .into();
+        let peers = self
+            .connected_peers()
+            .into_iter()
+            .filter(|&peer| peer == peer_id)
+            .collect();
+
+        Ok(Response::new(self.deliver(peers, data)))
+    }
+
+    async fn send_message_to_random_peers(
+        &self,
+        request: Request<grpc_sentry::SendMessageToRandomPeersRequest>,
+    ) -> Result<Response<SentPeers>, Status> {
+        self.wait_unless_stalled().await;
+        let grpc_sentry::SendMessageToRandomPeersRequest { max_peers, data } = request.into_inner();
+        let peers = self
+            .connected_peers()
+            .into_iter()
+            .choose_multiple(&mut rand::thread_rng(), max_peers as usize);
+
+        Ok(Response::new(self.deliver(peers, data)))
+    }
+
+    async fn send_message_to_all(
+        &self,
+        request: Request<OutboundMessageData>,
+    ) -> Result<Response<SentPeers>, Status> {
+        self.wait_unless_stalled().await;
+        Ok(Response::new(self.deliver(
+            self.connected_peers(),
+            Some(request.into_inner()),
+        )))
+    }
+
+    async fn peer_min_block(
+        &self,
+        _: Request<PeerMinBlockRequest>,
+    ) -> Result<Response<()>, Status> {
+        Ok(Response::new(()))
+    }
+
+    async fn set_status(
+        &self,
+        _: Request<grpc_sentry::StatusData>,
+    ) -> Result<Response<SetStatusReply>, Status> {
+        Ok(Response::new(SetStatusReply {}))
+    }
+
+    async fn hand_shake(&self, _: Request<()>) -> Result<Response<HandShakeReply>, Status> {
+        Ok(Response::new(HandShakeReply {
+            protocol: grpc_sentry::Protocol::Eth66 as i32,
+        }))
+    }
+
+    type MessagesStream = Pin<Box<dyn Stream<Item = Result<InboundMessage, Status>> + Send + Sync>>;
+
+    async fn messages(
+        &self,
+        request: Request<grpc_sentry::MessagesRequest>,
+    ) -> Result<Response<Self::MessagesStream>, Status> {
+        if self.down.load(Ordering::SeqCst) {
+            return Err(Status::unavailable("sentry is down"));
+        }
+
+        let ids = request.into_inner().ids.into_iter().collect::<HashSet<_>>();
+
+        Ok(Response::new(Box::pin(
+            BroadcastStream::new(self.inbound.subscribe())
+                .filter_map(|res| res.ok())
+                .take_while(Option::is_some)
+                .filter_map(move |msg| msg.filter(|msg| ids.is_empty() || ids.contains(&msg.id)))
+                .map(Ok),
+        )))
+    }
+
+    async fn node_info(&self, _: Request<()>) -> Result<Response<NodeInfoReply>, Status> {
+        Err(Status::unimplemented(""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{Block, BlockNumber, H256},
+        p2p::{
+            node::{PenaltyReason, SendTimeout, SentryConnectionState},
+            types::*,
+        },
+        res::chainspec::MAINNET,
+    };
+    use std::time::Duration;
+    use tokio::sync::watch;
+
+    #[tokio::test]
+    async fn serves_headers_and_records_penalties() {
+        let peers = [PeerId::repeat_byte(1), PeerId::repeat_byte(2)];
+        let sentry = MockSentry::new(peers);
+        sentry.serve_chain(
+            (0..10)
+                .map(|number| BlockHeader {
+                    number: BlockNumber(number),
+                    ..Default::default()
+                })
+                .collect(),
+        );
+        let node = sentry
+            .node(ChainConfig::from(MAINNET.clone()))
+            .await
+            .unwrap();
+
+        assert_eq!(node.total_peers().await, 2);
+        assert_eq!(
+            node.wait_for_peers(2, Duration::from_secs(1))
+                .await
+                .unwrap(),
+            2
+        );
+
+        let mut stream = node.stream_headers().await;
+        let request = HeaderRequest {
+            start: BlockId::Number(BlockNumber(3)),
+            limit: 4,
+            ..Default::default()
+        };
+        node.send_header_request(Some(42), request, None)
+            .await
+            .unwrap();
+
+        for _ in peers {
+            let msg = tokio::time::timeout(Duration::from_secs(5), stream.next())
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(peers.contains(&msg.peer_id));
+            let Message::BlockHeaders(BlockHeaders {
+                request_id,
+                headers,
+            }) = msg.msg
+            else {
+                panic!("unexpected message {:?}", msg.msg);
+            };
+            assert_eq!(request_id, 42);
+            assert_eq!(
+                headers.iter().map(|h| h.number.0).collect::<Vec<_>>(),
+                vec![3, 4, 5, 6]
+            );
+        }
+
+        assert_eq!(
+            sentry.requests_received(),
+            vec![GetBlockHeaders {
+                request_id: 42,
+                params: request.into(),
+            }]
+        );
+
+        node.penalize_peer(peers[0], PenaltyReason::BadHeader).await;
+        assert_eq!(sentry.penalties_issued(), vec![peers[0]]);
+        let stats = node.peer_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].peer_id, stats[0].penalties), (peers[0], 1));
+        // Reputation has barely started to recover.
+        assert!(stats[0].reputation < -0.99 * PenaltyReason::BadHeader.weight());
+        assert_eq!(sentry.connected_peers(), vec![peers[1]]);
+        assert_eq!(node.total_peers().await, 1);
+    }
+
+    #[tokio::test]
+    async fn new_block_announcements() {
+        let peer = PeerId::repeat_byte(1);
+        let sentry = MockSentry::new([peer]);
+        let node = sentry
+            .node(ChainConfig::from(MAINNET.clone()))
+            .await
+            .unwrap();
+        let mut announcements = Box::pin(node.new_block_announcements().await);
+
+        let announced = vec![
+            BlockHashAndNumber {
+                hash: H256::repeat_byte(1),
+                number: BlockNumber(10),
+            },
+            BlockHashAndNumber {
+                hash: H256::repeat_byte(2),
+                number: BlockNumber(11),
+            },
+        ];
+        sentry.inject(
+            peer,
+            MessageId::NewBlockHashes,
+            &NewBlockHashes(announced.clone()),
+        );
+        let header = BlockHeader {
+            number: BlockNumber(12),
+            ..Default::default()
+        };
+        sentry.inject(
+            peer,
+            MessageId::NewBlock,
+            &NewBlock {
+                block: Block {
+                    header: header.clone(),
+                    transactions: vec![],
+                    ommers: vec![],
+                },
+                total_difficulty: 1,
+            },
+        );
+
+        let mut received = Vec::new();
+        while received.len() < 3 {
+            received.push(
+                tokio::time::timeout(Duration::from_secs(5), announcements.next())
+                    .await
+                    .unwrap()
+                    .unwrap(),
+            );
+        }
+        assert_eq!(received[..2], announced[..]);
+        assert_eq!(
+            received[2],
+            BlockHashAndNumber {
+                hash: header.hash(),
+                number: BlockNumber(12),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_headers() {
+        let peers = [PeerId::repeat_byte(1), PeerId::repeat_byte(2)];
+        let sentry = MockSentry::new(peers);
+        let node = sentry
+            .node(ChainConfig::from(MAINNET.clone()))
+            .await
+            .unwrap();
+        let mut stream = node.stream_headers().await;
+
+        let msg = BlockHeaders {
+            request_id: 7,
+            headers: vec![BlockHeader::default()],
+        };
+        let mut encoded = BytesMut::new();
+        msg.encode(&mut encoded);
+        let whole = encoded.clone().freeze();
+        let truncated = encoded.split_to(encoded.len() - 10).freeze();
+        let garbage = Bytes::from_static(&[0xff, 0x00, 0x13, 0x37]);
+
+        for data in [truncated.clone(), garbage.clone()] {
+            let err = crate::p2p::types::InboundMessage::new(
+                grpc_sentry::InboundMessage {
+                    id: grpc_sentry::MessageId::BlockHeaders66 as i32,
+                    data,
+                    peer_id: Some(peers[0].into()),
+                },
+                0,
+            )
+            .unwrap_err();
+            assert!(
+                matches!(
+                    err,
+                    InboundMessageError::Decode {
+                        id: MessageId::BlockHeaders,
+                        ..
+                    }
+                ),
+                "{err}"
+            );
+        }
+
+        // Anything after a complete message is rejected too, from where it starts.
+        let mut trailing = BytesMut::from(&whole[..]);
+        trailing.extend_from_slice(&[0x80]);
+        let err = crate::p2p::types::InboundMessage::new(
+            grpc_sentry::InboundMessage {
+                id: grpc_sentry::MessageId::BlockHeaders66 as i32,
+                data: trailing.freeze(),
+                peer_id: Some(peers[0].into()),
+            },
+            0,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                InboundMessageError::Decode {
+                    id: MessageId::BlockHeaders,
+                    offset,
+                    ..
+                } if offset == whole.len()
+            ),
+            "{err}"
+        );
+
+        sentry.inject_raw(peers[0], MessageId::BlockHeaders, truncated);
+        sentry.inject_raw(peers[1], MessageId::BlockHeaders, garbage);
+        sentry.inject(peers[1], MessageId::BlockHeaders, &msg);
+
+        // Only the well-formed message comes through.
+        let received = tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received.peer_id, peers[1]);
+        assert_eq!(received.msg, Message::BlockHeaders(msg));
+
+        // Penalties are reported to the sentry in the background.
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while sentry.penalties_issued().len() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let mut penalties = sentry.penalties_issued();
+        penalties.sort();
+        assert_eq!(penalties, peers.to_vec());
+    }
+
+    async fn wait_for_state(
+        state: &mut watch::Receiver<SentryConnectionState>,
+        expected: SentryConnectionState,
+    ) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while *state.borrow() != expected {
+                state.changed().await.unwrap();
+            }
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn reconnects_to_sentry() {
+        let peer = PeerId::repeat_byte(1);
+        let sentry = MockSentry::new([peer]);
+        sentry.serve_chain(
+            (0..10)
+                .map(|number| BlockHeader {
+                    number: BlockNumber(number),
+                    ..Default::default()
+                })
+                .collect(),
+        );
+        let node = sentry
+            .node(ChainConfig::from(MAINNET.clone()))
+            .await
+            .unwrap();
+        let mut state = node.sentry_state(0);
+
+        // Reconnection happens while the stream is polled.
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut stream = node.stream_headers().await;
+        tokio::spawn(async move {
+            while let Some(msg) = stream.next().await {
+                let _ = tx.send(msg);
+            }
+        });
+
+        sentry.set_down(true);
+        wait_for_state(&mut state, SentryConnectionState::Reconnecting).await;
+        assert!(!node.is_sentry_connected());
+
+        sentry.set_down(false);
+        wait_for_state(&mut state, SentryConnectionState::Connected).await;
+        assert!(node.is_sentry_connected());
+
+        // Requests sent after reconnecting are answered through the same stream.
+        let request = HeaderRequest {
+            start: BlockId::Number(BlockNumber(2)),
+            limit: 3,
+            ..Default::default()
+        };
+        node.send_header_request(Some(7), request, None)
+            .await
+            .unwrap();
+        let msg = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let Message::BlockHeaders(BlockHeaders {
+            request_id,
+            headers,
+        }) = msg.msg
+        else {
+            panic!("unexpected message {:?}", msg.msg);
+        };
+        assert_eq!(request_id, 7);
+        assert_eq!(
+            headers.iter().map(|h| h.number.0).collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+    }
+
+    #[tokio::test]
+    async fn caps_header_request_peers() {
+        let peers = (1..=10).map(PeerId::repeat_byte).collect::<Vec<_>>();
+        let sentry = MockSentry::new(peers.clone());
+        let node = sentry
+            .node(ChainConfig::from(MAINNET.clone()))
+            .await
+            .unwrap();
+
+        let requests = |first: u64| {
+            (first..first + 20)
+                .map(|n| {
+                    (
+                        n,
+                        HeaderRequest {
+                            start: BlockId::Number(BlockNumber(n * 100)),
+                            limit: 100,
+                            ..Default::default()
+                        },
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut picked = Vec::new();
+        for round in 0..3 {
+            node.send_header_requests_to_peers(requests(round * 20), &mut picked, 3)
+                .await
+                .unwrap();
+        }
+        assert_eq!(sentry.requests_received().len(), 60);
+        assert_eq!(picked.len(), 3);
+        assert_eq!(
+            sentry.requested_peers(),
+            picked.iter().map(|&(_, peer_id)| peer_id).collect()
+        );
+
+        // A peer that falls out of favour is dropped. The sentry may pick an already picked
+        // peer as replacement, in which case the next round tries again.
+        let (_, banned) = picked[0];
+        for _ in 0..3 {
+            node.penalize_peer(banned, PenaltyReason::BadHeader).await;
+        }
+        for round in 3..20 {
+            node.send_header_requests_to_peers(requests(round * 20), &mut picked, 3)
+                .await
+                .unwrap();
+            if picked.len() == 3 {
+                break;
+            }
+        }
+        assert_eq!(picked.len(), 3);
+        assert!(!picked.iter().any(|&(_, peer_id)| peer_id == banned));
+        assert_eq!(sentry.requested_peers().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn times_out_stalled_sends() {
+        let peer = PeerId::repeat_byte(1);
+        let sentry = MockSentry::new([peer]);
+        let timeout = Duration::from_millis(200);
+        let node = sentry
+            .node_with(
+                NodeBuilder::new(ChainConfig::from(MAINNET.clone())).set_send_timeout(timeout),
+            )
+            .await
+            .unwrap();
+
+        let request = |n| {
+            (
+                n,
+                HeaderRequest {
+                    start: BlockId::Number(BlockNumber(n * 100)),
+                    limit: 100,
+                    ..Default::default()
+                },
+            )
+        };
+        let mut picked = Vec::new();
+        node.send_header_requests_to_peers([request(0)], &mut picked, 1)
+            .await
+            .unwrap();
+        assert_eq!(picked, [(0, peer)]);
+
+        sentry.set_sends_stalled(true);
+        let sent = tokio::time::timeout(
+            Duration::from_secs(5),
+            node.send_header_requests_to_peers([request(1), request(2)], &mut picked, 1),
+        )
+        .await
+        .expect("stalled send was not cut short");
+        assert_eq!(
+            sent,
+            Err(SendTimeout {
+                sentry_id: 0,
+                timeout
+            })
+        );
+        assert_eq!(
+            node.send_header_request(Some(3), request(3).1, None).await,
+            Err(SendTimeout {
+                sentry_id: 0,
+                timeout
+            })
+        );
+
+        // The sentry is to blame, so the peer is neither dropped nor penalized.
+        assert_eq!(picked, [(0, peer)]);
+        assert!(sentry.penalties_issued().is_empty());
+        assert!(node.reputation.is_reputable(peer));
+
+        sentry.set_sends_stalled(false);
+        node.send_header_requests_to_peers([request(4)], &mut picked, 1)
+            .await
+            .unwrap();
+        assert_eq!(sentry.requests_received().len(), 2);
+    }
+}
@@ -1,8 +1,22 @@
 #![allow(clippy::module_inception)]
 
 mod builder;
+#[cfg(test)]
+pub mod mock;
 mod node;
+mod rate_limit;
+mod reputation;
 mod stash;
+mod stats;
 mod stream;
+mod tip;
 
-pub use self::{builder::*, node::*, stream::NodeStream};
+pub use self::{
+    builder::*,
+    node::*,
+    rate_limit::*,
+    reputation::*,
+    stats::*,
+    stream::{NodeStream, SentryConnectionState},
+    tip::*,
+};
@@ -1,6 +1,13 @@
 #![allow(unreachable_code)]
 
-use super::{stash::Stash, stream::*};
+use super::{
+    rate_limit::*,
+    reputation::{PeerReputation, PenaltyReason},
+    stash::Stash,
+    stats::*,
+    stream::*,
+    tip::TipScan,
+};
 use crate::{
     models::{BlockNumber, ChainConfig, MessageWithSignature, H256},
     p2p::types::*,
@@ -16,7 +23,7 @@ use fastrlp::*;
 use futures::stream::FuturesUnordered;
 use hashlink::LruCache;
 use parking_lot::{Mutex, RwLock};
-use rand::{thread_rng, Rng};
+use rand::{rngs::StdRng, thread_rng, Rng};
 use std::{
     collections::HashSet,
     future::{pending, Future},
@@ -24,6 +31,7 @@ use std::{
     time::Duration,
 };
 use task_group::TaskGroup;
+use thiserror::Error;
 use tokio::sync::{watch, Notify};
 use tokio_stream::StreamExt;
 use tonic::transport::Channel;
@@ -36,6 +44,15 @@ pub type PeerId = H512;
 
 pub type RequestId = u64;
 
+/// A sentry did not complete a send within [`Node::send_timeout`]. The sentry is to blame
+/// rather than the peers, the message may not have reached any of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error)]
+#[error("sentry {sentry_id} did not complete a send within {timeout:?}")]
+pub struct SendTimeout {
+    pub sentry_id: SentryId,
+    pub timeout: Duration,
+}
+
 #[derive(Debug)]
 pub struct Node {
     pub stash: Arc<dyn Stash>,
@@ -53,12 +70,28 @@ pub struct Node {
     pub block_cache_notify: Notify,
     /// Table of block hashes of the blocks known to not belong to the canonical chain.
     pub bad_blocks: DashSet<H256>,
+    /// Reputation scores of the peers penalized by this node.
+    pub reputation: PeerReputation,
+    /// Responses, latencies, timeouts and penalties of each peer, see [`Node::peer_stats`].
+    pub peer_counters: PeerCounters,
+    /// Optional budget for outgoing header and body requests.
+    pub request_rate_limiter: Option<Arc<RequestRateLimiter>>,
+    /// How long to wait for a sentry to complete a send before giving up with
+    /// [`SendTimeout`].
+    pub send_timeout: Duration,
+    /// Randomness for spreading requests over peers, see
+    /// [`NodeBuilder::set_peer_selection_seed`](super::NodeBuilder::set_peer_selection_seed).
+    pub peer_selection_rng: Mutex<StdRng>,
+    /// Message stream state of each sentry, in the same order as `sentries`.
+    pub sentry_states: Vec<Arc<watch::Sender<SentryConnectionState>>>,
     /// Chain forks.
     pub forks: Vec<u64>,
 }
 
 impl Node {
+    pub const DEFAULT_SEND_TIMEOUT: Duration = Duration::from_secs(2);
     const SYNC_INTERVAL: Duration = Duration::from_secs(5);
+    const PEER_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
     /// Start node synchronization.
     pub async fn start_sync(self: Arc<Self>, tip_discovery: bool) -> anyhow::Result<()> {
@@ -264,6 +297,24 @@ impl Node {
             }
         });
 
+        for (sentry_id, state) in self.sentry_states.iter().enumerate() {
+            let handler = self.clone();
+            let mut state = state.subscribe();
+
+            tasks.spawn(async move {
+                // A restarted sentry has forgotten our status.
+                while state.changed().await.is_ok() {
+                    let connected = *state.borrow() == SentryConnectionState::Connected;
+                    if connected {
+                        debug!("Sentry {sentry_id} reconnected, re-sending status");
+                        handler.update_chain_head(None).await;
+                    }
+                }
+
+                Ok::<(), anyhow::Error>(())
+            });
+        }
+
         pending::<()>().await;
 
         Ok(())
@@ -304,6 +355,15 @@ impl Node {
         msg: Message,
         pred: PeerFilter,
     ) -> HashSet<(SentryId, PeerId)> {
+        self.try_send_message(msg, pred).await.unwrap_or_default()
+    }
+
+    /// Like [`Node::send_message`], but fails if a sentry timed out and no peer was reached.
+    pub async fn try_send_message(
+        &self,
+        msg: Message,
+        pred: PeerFilter,
+    ) -> Result<HashSet<(SentryId, PeerId)>, SendTimeout> {
         debug!("Sending message: {msg:?} to peers {pred:?}");
         let id = grpc_sentry::MessageId::from(msg.id()) as i32;
         let data = || -> bytes::Bytes {
@@ -312,24 +372,26 @@ impl Node {
             buf.freeze()
         }();
 
-        self.send_raw(grpc_sentry::OutboundMessageData { id, data }, pred)
+        self.try_send_raw(grpc_sentry::OutboundMessageData { id, data }, pred)
             .await
     }
 
+    /// Sends every request to all peers. Fails if some request timed out and nothing was sent.
     pub async fn send_many_header_requests<T>(
         self: Arc<Self>,
         requests: T,
-    ) -> HashSet<(SentryId, PeerId)>
+    ) -> Result<HashSet<(SentryId, PeerId)>, SendTimeout>
     where
-        T: IntoIterator<Item = HeaderRequest>,
+        T: IntoIterator<Item = (RequestId, HeaderRequest)>,
     {
-        requests
+        let mut timed_out = None;
+        let sent = requests
             .into_iter()
-            .map(|request| {
+            .map(|(request_id, request)| {
                 let node = self.clone();
                 tokio::spawn(async move {
-                    trace!("Sending header request: {request:?}");
-                    node.send_header_request(None, request, None).await
+                    trace!("Sending header request {request_id}: {request:?}");
+                    node.send_header_request(Some(request_id), request, None).await
                 })
             })
             .collect::<FuturesUnordered<_>>()
@@ -337,8 +399,119 @@ impl Node {
             .await
             .into_iter()
             .flat_map(|res| res.ok())
+            .filter_map(|res| res.map_err(|e| timed_out = Some(e)).ok())
             .flatten()
-            .collect()
+            .collect::<HashSet<_>>();
+
+        match timed_out {
+            Some(e) if sent.is_empty() => Err(e),
+            _ => Ok(sent),
+        }
+    }
+
+    /// Sends header requests to at most `max_peers` distinct peers, one peer per request.
+    ///
+    /// `peers` holds the picked peers between calls. Disreputable and disconnected peers
+    /// are dropped and replaced by random ones picked by the sentries. Requests are spread
+    /// over the picked peers, the most reputable first.
+    ///
+    /// Stops at the first send that times out, the remaining requests are not sent. Peers
+    /// behind a timed out send stay picked.
+    pub async fn send_header_requests_to_peers<T>(
+        &self,
+        requests: T,
+        peers: &mut Vec<(SentryId, PeerId)>,
+        max_peers: usize,
+    ) -> Result<HashSet<(SentryId, PeerId)>, SendTimeout>
+    where
+        T: IntoIterator<Item = (RequestId, HeaderRequest)>,
+    {
+        let encode = |request_id, request: HeaderRequest| {
+            self.peer_counters.record_request(request_id);
+            let mut data = BytesMut::new();
+            GetBlockHeaders {
+                request_id,
+                params: request.into(),
+            }
+            .encode(&mut data);
+            grpc_sentry::OutboundMessageData {
+                id: grpc_sentry::MessageId::from(MessageId::GetBlockHeaders) as i32,
+                data: data.freeze(),
+            }
+        };
+
+        peers.retain(|&(_, peer_id)| self.reputation.is_reputable(peer_id));
+
+        let mut requests = requests.into_iter();
+        let mut sent = HashSet::new();
+
+        // Top up the picked peers, each pick costs a request to the new peers.
+        for sentry_id in 0..self.sentries.len() {
+            if peers.len() >= max_peers {
+                break;
+            }
+            let Some((request_id, request)) = requests.next() else {
+                break;
+            };
+
+            self.acquire_request_permit().await;
+            let picked = self
+                .send_to_random_peers(
+                    sentry_id,
+                    encode(request_id, request),
+                    (max_peers - peers.len()) as u64,
+                )
+                .await?;
+            for peer in &picked {
+                if !peers.contains(peer) {
+                    peers.push(*peer);
+                }
+            }
+            sent.extend(picked);
+        }
+
+        if peers.is_empty() {
+            return Ok(sent);
+        }
+
+        peers.sort_by(|(_, a), (_, b)| {
+            self.reputation
+                .score(*b)
+                .total_cmp(&self.reputation.score(*a))
+        });
+
+        // Start at a random peer so that re-sent requests do not always go to the same one.
+        let offset = self.peer_selection_rng.lock().gen_range(0..peers.len());
+        let mut disconnected = HashSet::new();
+        let mut timed_out = None;
+        for (i, (request_id, request)) in requests.enumerate() {
+            let (sentry_id, peer_id) = peers[(offset + i) % peers.len()];
+            self.acquire_request_permit().await;
+            match self
+                .try_send_raw(
+                    encode(request_id, request),
+                    PeerFilter::Peer(peer_id, sentry_id),
+                )
+                .await
+            {
+                Ok(to) => {
+                    if to.is_empty() {
+                        disconnected.insert((sentry_id, peer_id));
+                    }
+                    sent.extend(to);
+                }
+                Err(e) => {
+                    timed_out = Some(e);
+                    break;
+                }
+            }
+        }
+        peers.retain(|peer| !disconnected.contains(peer));
+
+        match timed_out {
+            Some(e) => Err(e),
+            None => Ok(sent),
+        }
     }
 
     pub async fn send_header_request(
@@ -346,10 +519,13 @@ impl Node {
         request_id: Option<u64>,
         request: HeaderRequest,
         max_block: Option<BlockNumber>,
-    ) -> HashSet<(SentryId, PeerId)> {
-        self.send_message(
+    ) -> Result<HashSet<(SentryId, PeerId)>, SendTimeout> {
+        self.acquire_request_permit().await;
+        let request_id = request_id.unwrap_or_else(|| rand::thread_rng().gen::<u64>());
+        self.peer_counters.record_request(request_id);
+        self.try_send_message(
             Message::GetBlockHeaders(GetBlockHeaders {
-                request_id: request_id.unwrap_or_else(|| rand::thread_rng().gen::<u64>()),
+                request_id,
                 params: request.into(),
             }),
             if let Some(max_block) = max_block {
@@ -368,6 +544,7 @@ impl Node {
         blocks: &'a [(BlockNumber, H256)],
         min_block_filter: bool,
     ) -> Option<(SentryId, PeerId)> {
+        self.acquire_request_permit().await;
         self.update_chain_head(None).await;
 
         let hashes = blocks.iter().map(|(_, h)| *h).collect::<Vec<_>>();
@@ -468,8 +645,7 @@ impl Node {
     async fn sync_stream(&self) -> NodeStream {
         self.update_chain_head(None).await;
 
-        let sentries = self.sentries.iter().collect::<Vec<_>>();
-        SentryStream::join_all(sentries, Self::SYNC_PREDICATE).await
+        SentryStream::join_all(self.sentry_connections(), Self::SYNC_PREDICATE).await
     }
 
     const RAW_PREDICATE: [i32; 4] = [
@@ -482,8 +658,7 @@ impl Node {
     pub async fn stream_raw(&self) -> NodeStream {
         self.update_chain_head(None).await;
 
-        let sentries = self.sentries.iter().collect::<Vec<_>>();
-        SentryStream::join_all(sentries, Self::RAW_PREDICATE).await
+        SentryStream::join_all(self.sentry_connections(), Self::RAW_PREDICATE).await
     }
 
     const TRANSACTIONS_PREDICATE: [i32; 4] = [
@@ -494,21 +669,86 @@ impl Node {
     ];
 
     pub async fn stream_transactions(&self) -> NodeStream {
-        SentryStream::join_all(self.sentries.iter(), Self::TRANSACTIONS_PREDICATE).await
+        SentryStream::join_all(self.sentry_connections(), Self::TRANSACTIONS_PREDICATE).await
     }
 
     pub async fn stream_by_predicate<T>(&self, pred: T) -> NodeStream
     where
         T: IntoIterator<Item = i32>,
     {
-        SentryStream::join_all(self.sentries.iter(), pred).await
+        SentryStream::join_all(self.sentry_connections(), pred).await
+    }
+
+    const ANNOUNCEMENTS_PREDICATE: [i32; 2] = [
+        grpc_sentry::MessageId::NewBlockHashes66 as i32,
+        grpc_sentry::MessageId::NewBlock66 as i32,
+    ];
+
+    /// Blocks announced in a `NewBlockHashes` or `NewBlock` message, `None` for other
+    /// messages.
+    fn announced_blocks(msg: Message) -> Option<Vec<BlockHashAndNumber>> {
+        match msg {
+            Message::NewBlockHashes(NewBlockHashes(blocks)) => Some(blocks),
+            Message::NewBlock(new_block) => Some(vec![BlockHashAndNumber {
+                hash: new_block.block.header.hash(),
+                number: new_block.block.header.number,
+            }]),
+            _ => None,
+        }
+    }
+
+    /// Blocks announced by peers with `NewBlockHashes` and `NewBlock` gossip.
+    pub async fn new_block_announcements(
+        &self,
+    ) -> impl futures::Stream<Item = BlockHashAndNumber> + Send {
+        let stream = self
+            .stream_by_predicate(Self::ANNOUNCEMENTS_PREDICATE)
+            .await;
+        futures::StreamExt::flat_map(stream, |msg| {
+            tokio_stream::iter(Self::announced_blocks(msg.msg).unwrap_or_default())
+        })
+    }
+
+    /// Listens to block announcements for `duration`, or until `max_peers` peers have
+    /// announced a block, and picks the highest block announced by at least
+    /// `min_agreement` of them as the chain tip, see [`TipScan::tally`].
+    pub async fn scan_for_tip(
+        &self,
+        min_agreement: usize,
+        max_peers: usize,
+        duration: Duration,
+    ) -> TipScan {
+        let mut stream = self
+            .stream_by_predicate(Self::ANNOUNCEMENTS_PREDICATE)
+            .await;
+        let deadline = tokio::time::sleep(duration);
+        tokio::pin!(deadline);
+
+        let mut announcements = Vec::new();
+        let mut peers = HashSet::new();
+        while peers.len() < max_peers {
+            let msg = tokio::select! {
+                msg = stream.next() => msg,
+                _ = &mut deadline => break,
+            };
+            let Some(msg) = msg else {
+                break;
+            };
+
+            let Some(blocks) = Self::announced_blocks(msg.msg) else {
+                continue;
+            };
+            peers.insert(msg.peer_id);
+            announcements.extend(blocks.into_iter().map(|block| (msg.peer_id, block)));
+        }
+
+        TipScan::tally(announcements, min_agreement)
     }
 
     const HEADERS_PREDICATE: [i32; 1] = [grpc_sentry::MessageId::BlockHeaders66 as i32];
 
     pub async fn stream_headers(&self) -> NodeStream {
-        let sentries = self.sentries.iter().collect::<Vec<_>>();
-        SentryStream::join_all(sentries, Self::HEADERS_PREDICATE).await
+        SentryStream::join_all(self.sentry_connections(), Self::HEADERS_PREDICATE).await
     }
 
     const BODIES_PREDICATE: [i32; 1] = [grpc_sentry::MessageId::BlockBodies66 as i32];
@@ -516,8 +756,7 @@ impl Node {
     pub async fn stream_bodies(&self) -> NodeStream {
         self.update_chain_head(None).await;
 
-        let sentries = self.sentries.iter().collect::<Vec<_>>();
-        SentryStream::join_all(sentries, Self::BODIES_PREDICATE).await
+        SentryStream::join_all(self.sentry_connections(), Self::BODIES_PREDICATE).await
     }
 
     #[inline]
@@ -547,7 +786,58 @@ impl Node {
         sum
     }
 
-    pub async fn penalize_peer(&self, peer_id: impl Into<ethereum_interfaces::types::H512>) {
+    /// Waits until at least `min` peers are connected across all sentries.
+    pub async fn wait_for_peers(&self, min: usize, timeout: Duration) -> anyhow::Result<usize> {
+        tokio::time::timeout(timeout, async {
+            loop {
+                let peers = self.total_peers().await;
+                if peers >= min {
+                    return peers;
+                }
+                tokio::time::sleep(Self::PEER_POLL_INTERVAL).await;
+            }
+        })
+        .await
+        .map_err(|_| anyhow::format_err!("fewer than {min} peers connected after {timeout:?}"))
+    }
+
+    /// Whether messages are coming in from at least one sentry.
+    pub fn is_sentry_connected(&self) -> bool {
+        self.sentry_states
+            .iter()
+            .any(|state| *state.borrow() == SentryConnectionState::Connected)
+    }
+
+    /// Waits until at least one sentry is connected.
+    pub async fn wait_for_sentry_connection(&self) {
+        while !self.is_sentry_connected() {
+            tokio::time::sleep(Self::PEER_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Watches the message stream state of the given sentry.
+    pub fn sentry_state(&self, sentry_id: SentryId) -> watch::Receiver<SentryConnectionState> {
+        self.sentry_states[sentry_id].subscribe()
+    }
+
+    fn sentry_connections(
+        &self,
+    ) -> impl Iterator<Item = (&Sentry, &Arc<watch::Sender<SentryConnectionState>>)> {
+        self.sentries.iter().zip(&self.sentry_states)
+    }
+
+    /// Current counters and reputation of every peer that has answered a request or been
+    /// penalized, ordered by peer id.
+    pub fn peer_stats(&self) -> Vec<PeerStat> {
+        self.peer_counters.snapshot(self.reputation.scores())
+    }
+
+    /// Lowers peer reputation and reports it to the sentries.
+    pub async fn penalize_peer(&self, peer_id: PeerId, penalty: PenaltyReason) {
+        debug!("Penalizing peer {peer_id}: {penalty:?}");
+        self.reputation.penalize(peer_id, penalty);
+        self.peer_counters.record_penalty(peer_id);
+
         let request = grpc_sentry::PenalizePeerRequest {
             peer_id: Some(peer_id.into()),
             penalty: 0i32,
@@ -570,48 +860,106 @@ impl Node {
 }
 
 impl Node {
-    const TIMEOUT: Duration = Duration::from_secs(2);
+    async fn send_to_random_peers(
+        &self,
+        sentry_id: SentryId,
+        data: grpc_sentry::OutboundMessageData,
+        max_peers: u64,
+    ) -> Result<HashSet<(SentryId, PeerId)>, SendTimeout> {
+        let mut sentry = self.sentries[sentry_id].clone();
+        let request =
+            sentry.send_message_to_random_peers(grpc_sentry::SendMessageToRandomPeersRequest {
+                data: Some(data),
+                max_peers,
+            });
+
+        match tokio::time::timeout(self.send_timeout, request).await {
+            Ok(Ok(reply)) => Ok(reply
+                .into_inner()
+                .peers
+                .into_iter()
+                .map(|peer_id| (sentry_id, peer_id.into()))
+                .collect()),
+            Ok(Err(_)) => Ok(HashSet::new()),
+            Err(_) => Err(SendTimeout {
+                sentry_id,
+                timeout: self.send_timeout,
+            }),
+        }
+    }
+
+    async fn acquire_request_permit(&self) {
+        if let Some(limiter) = &self.request_rate_limiter {
+            limiter.acquire().await;
+        }
+    }
 
     async fn send_raw(
         &self,
         data: impl Into<grpc_sentry::OutboundMessageData>,
         predicate: PeerFilter,
     ) -> HashSet<(SentryId, PeerId)> {
+        self.try_send_raw(data, predicate).await.unwrap_or_default()
+    }
+
+    /// Sends through every sentry matching `predicate`. Fails if a sentry timed out and no
+    /// peer was reached through the others.
+    async fn try_send_raw(
+        &self,
+        data: impl Into<grpc_sentry::OutboundMessageData>,
+        predicate: PeerFilter,
+    ) -> Result<HashSet<(SentryId, PeerId)>, SendTimeout> {
         let data = data.into();
+        let timeout = self.send_timeout;
 
         async fn map_await<T, I, F>(
             iter: T,
             data: grpc_sentry::OutboundMessageData,
+            timeout: Duration,
             closure: F,
-        ) -> HashSet<(SentryId, PeerId)>
+        ) -> Result<HashSet<(SentryId, PeerId)>, SendTimeout>
         where
             T: IntoIterator<Item = (SentryId, Sentry)>,
             I: Future<Output = Result<tonic::Response<SentPeers>, tonic::Status>>,
             F: Fn(Sentry, grpc_sentry::OutboundMessageData) -> I,
         {
-            iter.into_iter()
+            let results = iter
+                .into_iter()
                 .map(|(id, sentry)| {
                     let data = data.clone();
                     let fut = closure(sentry, data);
                     async move {
-                        if let Ok(Ok(v)) = tokio::time::timeout(Node::TIMEOUT, fut).await {
-                            return v
+                        match tokio::time::timeout(timeout, fut).await {
+                            Ok(Ok(v)) => Ok(v
                                 .into_inner()
                                 .peers
                                 .into_iter()
                                 .map(|peer_id| (id, peer_id.into()))
-                                .collect();
+                                .collect::<HashSet<_>>()),
+                            Ok(Err(_)) => Ok(HashSet::new()),
+                            Err(_) => Err(SendTimeout {
+                                sentry_id: id,
+                                timeout,
+                            }),
                         }
-
-                        HashSet::new()
                     }
                 })
                 .collect::<FuturesUnordered<_>>()
                 .collect::<Vec<_>>()
-                .await
-                .into_iter()
-                .flatten()
-                .collect()
+                .await;
+
+            let mut sent = HashSet::new();
+            let mut timed_out = None;
+            for res in results {
+                match res {
+                    Ok(peers) => sent.extend(peers),
+                    Err(e) => timed_out = Some(e),
+                }
+            }
+            match timed_out {
+                Some(e) if sent.is_empty() => Err(e),
+                _ => Ok(sent),
+            }
         }
 
         match predicate {
@@ -619,6 +967,7 @@ impl Node {
                 map_await(
                     self.sentries.iter().cloned().enumerate(),
                     data,
+                    timeout,
                     |mut sentry, data| async move { sentry.send_message_to_all(data).await },
                 )
                 .await
@@ -627,6 +976,7 @@ impl Node {
                 map_await(
                     self.sentries.iter().cloned().enumerate(),
                     data,
+                    timeout,
                     |mut sentry, data| async move {
                         sentry
                             .send_message_to_random_peers(
@@ -642,7 +992,7 @@ impl Node {
             }
             PeerFilter::Peer(peer_id, sentry_id) => {
                 let iter = std::iter::once((sentry_id, self.sentries[sentry_id].clone()));
-                map_await(iter, data, |mut sentry, data| async move {
+                map_await(iter, data, timeout, |mut sentry, data| async move {
                     sentry
                         .send_message_by_id(grpc_sentry::SendMessageByIdRequest {
                             data: Some(data),
@@ -656,6 +1006,7 @@ impl Node {
                 map_await(
                     self.sentries.iter().cloned().enumerate(),
                     data,
+                    timeout,
                     |mut sentry, data| async move {
                         sentry
                             .send_message_by_min_block(grpc_sentry::SendMessageByMinBlockRequest {
@@ -0,0 +1,80 @@
+use parking_lot::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Token bucket limiting the rate of outgoing requests.
+///
+/// The bucket holds up to `burst` tokens and refills at `rate` tokens per second; every
+/// request takes one token. Clones of an `Arc<RequestRateLimiter>` share the same budget.
+#[derive(Debug)]
+pub struct RequestRateLimiter {
+    rate: f64,
+    burst: f64,
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RequestRateLimiter {
+    /// Creates a full bucket. Both `rate` and `burst` must be positive.
+    pub fn new(rate: f64, burst: u32) -> Self {
+        assert!(rate > 0.0, "request rate must be positive");
+        assert!(burst > 0, "request burst must be positive");
+
+        Self {
+            rate,
+            burst: burst as f64,
+            bucket: Mutex::new((burst as f64, Instant::now())),
+        }
+    }
+
+    /// Takes a token, or returns how long to wait until one is available.
+    fn try_acquire_at(&self, now: Instant) -> Result<(), Duration> {
+        let mut bucket = self.bucket.lock();
+        let (tokens, updated_at) = *bucket;
+        let tokens = f64::min(
+            self.burst,
+            tokens + now.saturating_duration_since(updated_at).as_secs_f64() * self.rate,
+        );
+
+        if tokens >= 1.0 {
+            *bucket = (tokens - 1.0, now);
+            Ok(())
+        } else {
+            *bucket = (tokens, now);
+            Err(Duration::from_secs_f64((1.0 - tokens) / self.rate))
+        }
+    }
+
+    /// Waits until a token is available and takes it.
+    pub async fn acquire(&self) {
+        while let Err(delay) = self.try_acquire_at(Instant::now()) {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket() {
+        let limiter = RequestRateLimiter::new(2.0, 2);
+        let now = Instant::now();
+
+        assert_eq!(limiter.try_acquire_at(now), Ok(()));
+        assert_eq!(limiter.try_acquire_at(now), Ok(()));
+        assert_eq!(limiter.try_acquire_at(now), Err(Duration::from_millis(500)));
+
+        let now = now + Duration::from_millis(250);
+        assert_eq!(limiter.try_acquire_at(now), Err(Duration::from_millis(250)));
+
+        let now = now + Duration::from_millis(250);
+        assert_eq!(limiter.try_acquire_at(now), Ok(()));
+
+        // Idle time does not accumulate more than `burst` tokens.
+        let now = now + Duration::from_secs(60);
+        assert_eq!(limiter.try_acquire_at(now), Ok(()));
+        assert_eq!(limiter.try_acquire_at(now), Ok(()));
+        assert!(limiter.try_acquire_at(now).is_err());
+    }
+}
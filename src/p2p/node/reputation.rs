@@ -0,0 +1,136 @@
+use super::PeerId;
+use parking_lot::Mutex;
+use std::{collections::HashMap, time::Duration};
+use tokio::time::Instant;
+
+/// Kind of peer misbehaviour, each carrying its own weight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PenaltyReason {
+    BadHeader,
+    Timeout,
+    InvalidRlp,
+}
+
+impl PenaltyReason {
+    pub const fn weight(self) -> f64 {
+        match self {
+            PenaltyReason::BadHeader => 50.0,
+            PenaltyReason::Timeout => 10.0,
+            PenaltyReason::InvalidRlp => 50.0,
+        }
+    }
+}
+
+/// Per-peer reputation scores.
+///
+/// Every peer starts at zero and loses [`PenaltyReason::weight`] on each penalty. Negative scores
+/// recover towards zero exponentially with the configured half-life, so a peer that
+/// misbehaved once is not ignored forever.
+#[derive(Debug)]
+pub struct PeerReputation {
+    half_life: Duration,
+    threshold: f64,
+    scores: Mutex<HashMap<PeerId, (f64, Instant)>>,
+}
+
+impl Default for PeerReputation {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_HALF_LIFE, Self::DEFAULT_THRESHOLD)
+    }
+}
+
+impl PeerReputation {
+    pub const DEFAULT_HALF_LIFE: Duration = Duration::from_secs(600);
+    pub const DEFAULT_THRESHOLD: f64 = -100.0;
+
+    /// Peers whose score drops to `threshold` or below are considered disreputable.
+    pub fn new(half_life: Duration, threshold: f64) -> Self {
+        Self {
+            half_life,
+            threshold,
+            scores: Default::default(),
+        }
+    }
+
+    fn decayed(&self, score: f64, updated_at: Instant, now: Instant) -> f64 {
+        if self.half_life.is_zero() {
+            return 0.0;
+        }
+
+        let half_lives =
+            now.saturating_duration_since(updated_at).as_secs_f64() / self.half_life.as_secs_f64();
+        score * 0.5_f64.powf(half_lives)
+    }
+
+    fn penalize_at(&self, peer_id: PeerId, penalty: PenaltyReason, now: Instant) {
+        let mut scores = self.scores.lock();
+        let entry = scores.entry(peer_id).or_insert((0.0, now));
+        *entry = (self.decayed(entry.0, entry.1, now) - penalty.weight(), now);
+    }
+
+    fn score_at(&self, peer_id: PeerId, now: Instant) -> f64 {
+        self.scores
+            .lock()
+            .get(&peer_id)
+            .map(|&(score, updated_at)| self.decayed(score, updated_at, now))
+            .unwrap_or(0.0)
+    }
+
+    pub fn penalize(&self, peer_id: PeerId, penalty: PenaltyReason) {
+        self.penalize_at(peer_id, penalty, Instant::now())
+    }
+
+    /// Current score of the peer, zero for peers that were never penalized.
+    pub fn score(&self, peer_id: PeerId) -> f64 {
+        self.score_at(peer_id, Instant::now())
+    }
+
+    pub fn is_reputable(&self, peer_id: PeerId) -> bool {
+        self.score(peer_id) > self.threshold
+    }
+
+    /// Current scores of all penalized peers.
+    pub fn scores(&self) -> Vec<(PeerId, f64)> {
+        let now = Instant::now();
+        let mut scores = self.scores.lock();
+        // Forget peers that have fully recovered.
+        scores.retain(|_, &mut (score, updated_at)| self.decayed(score, updated_at, now) < -0.01);
+        scores
+            .iter()
+            .map(|(&peer_id, &(score, updated_at))| (peer_id, self.decayed(score, updated_at, now)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn penalties_decay() {
+        let reputation = PeerReputation::new(Duration::from_secs(60), -100.0);
+        let peer = PeerId::repeat_byte(1);
+        let other = PeerId::repeat_byte(2);
+        let now = Instant::now();
+
+        reputation.penalize_at(peer, PenaltyReason::BadHeader, now);
+        reputation.penalize_at(peer, PenaltyReason::InvalidRlp, now);
+        assert_eq!(reputation.score_at(peer, now), -100.0);
+        assert_eq!(reputation.score_at(other, now), 0.0);
+
+        assert_eq!(
+            reputation.score_at(peer, now + Duration::from_secs(60)),
+            -50.0
+        );
+        assert_eq!(
+            reputation.score_at(peer, now + Duration::from_secs(120)),
+            -25.0
+        );
+
+        reputation.penalize_at(peer, PenaltyReason::Timeout, now + Duration::from_secs(60));
+        assert_eq!(
+            reputation.score_at(peer, now + Duration::from_secs(120)),
+            -30.0
+        );
+    }
+}
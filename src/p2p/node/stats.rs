@@ -0,0 +1,279 @@
+use super::{PeerId, RequestId};
+use lru::LruCache;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// Snapshot of what a peer has done for this node, see [`super::Node::peer_stats`].
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct PeerStat {
+    pub peer_id: PeerId,
+    /// Current [`super::PeerReputation`] score.
+    pub reputation: f64,
+    /// Number of accepted responses to our requests.
+    pub responses: u64,
+    /// Encoded size of the accepted responses.
+    pub bytes_delivered: u64,
+    pub timeouts: u64,
+    pub penalties: u64,
+    /// 95th percentile of the recent request-to-response latencies, see
+    /// [`PeerCounters::latency_p95`].
+    pub latency_p95: Option<Duration>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Counters {
+    responses: u64,
+    bytes_delivered: u64,
+    timeouts: u64,
+    penalties: u64,
+    /// Most recent latencies, oldest first.
+    latencies: VecDeque<Duration>,
+}
+
+impl Counters {
+    fn latency_p95(&self) -> Option<Duration> {
+        if self.latencies.len() < PeerCounters::MIN_LATENCY_SAMPLES {
+            return None;
+        }
+
+        let mut latencies = self.latencies.iter().copied().collect::<Vec<_>>();
+        latencies.sort_unstable();
+        latencies
+            .get((latencies.len() * 95).div_ceil(100) - 1)
+            .copied()
+    }
+}
+
+/// Per-peer request counters for monitoring, read through [`PeerCounters::snapshot`], and the
+/// response latencies that [`PeerCounters::response_timeout`] adapts to.
+#[derive(Debug)]
+pub struct PeerCounters {
+    counters: Mutex<HashMap<PeerId, Counters>>,
+    /// When recent requests were sent, to tell the latency of their answers.
+    sent_at: Mutex<LruCache<RequestId, Instant>>,
+}
+
+impl Default for PeerCounters {
+    fn default() -> Self {
+        Self {
+            counters: Default::default(),
+            sent_at: Mutex::new(LruCache::new(Self::MAX_TRACKED_REQUESTS)),
+        }
+    }
+}
+
+impl PeerCounters {
+    /// Number of latencies kept per peer.
+    const LATENCY_SAMPLES: usize = 32;
+    /// Number of latencies needed before a peer gets an adaptive timeout.
+    const MIN_LATENCY_SAMPLES: usize = 5;
+    const MAX_TRACKED_REQUESTS: usize = 4096;
+    /// Lower bound of adaptive timeouts, so that a run of quick answers does not make the
+    /// next slightly slower one time out.
+    pub const MIN_RESPONSE_TIMEOUT: Duration = Duration::from_millis(250);
+
+    fn update(&self, peer_id: PeerId, update: impl FnOnce(&mut Counters)) {
+        update(self.counters.lock().entry(peer_id).or_default())
+    }
+
+    pub fn record_response(&self, peer_id: PeerId, bytes: u64) {
+        self.update(peer_id, |counters| {
+            counters.responses += 1;
+            counters.bytes_delivered += bytes;
+        })
+    }
+
+    pub fn record_timeout(&self, peer_id: PeerId) {
+        self.update(peer_id, |counters| counters.timeouts += 1)
+    }
+
+    pub fn record_penalty(&self, peer_id: PeerId) {
+        self.update(peer_id, |counters| counters.penalties += 1)
+    }
+
+    /// Records that the request has just been sent.
+    pub fn record_request(&self, request_id: RequestId) {
+        self.sent_at.lock().put(request_id, Instant::now());
+    }
+
+    /// Records the latency of a peer's answer to a request seen by
+    /// [`PeerCounters::record_request`]. Answers to unknown requests are ignored.
+    pub fn record_answer(&self, peer_id: PeerId, request_id: RequestId) {
+        let sent_at = self.sent_at.lock().peek(&request_id).copied();
+        if let Some(sent_at) = sent_at {
+            self.record_latency(peer_id, sent_at.elapsed());
+        }
+    }
+
+    pub fn record_latency(&self, peer_id: PeerId, latency: Duration) {
+        self.update(peer_id, |counters| {
+            if counters.latencies.len() == Self::LATENCY_SAMPLES {
+                counters.latencies.pop_front();
+            }
+            counters.latencies.push_back(latency);
+        })
+    }
+
+    /// 95th percentile of the peer's recent latencies, `None` until enough are recorded.
+    pub fn latency_p95(&self, peer_id: PeerId) -> Option<Duration> {
+        self.counters
+            .lock()
+            .get(&peer_id)
+            .and_then(Counters::latency_p95)
+    }
+
+    /// How long to wait for an answer from any of `peers`: twice the slowest p95 latency
+    /// among them, but at least [`PeerCounters::MIN_RESPONSE_TIMEOUT`]. Falls back to
+    /// `fallback` if there are no peers or one of them has too few latencies recorded.
+    pub fn response_timeout(
+        &self,
+        peers: impl IntoIterator<Item = PeerId>,
+        fallback: Duration,
+    ) -> Duration {
+        let counters = self.counters.lock();
+        let mut timeout = None;
+        for peer_id in peers {
+            let Some(p95) = counters.get(&peer_id).and_then(Counters::latency_p95) else {
+                return fallback;
+            };
+            timeout = std::cmp::max(timeout, Some(p95.saturating_mul(2)));
+        }
+
+        timeout.map_or(fallback, |timeout| {
+            std::cmp::max(timeout, Self::MIN_RESPONSE_TIMEOUT)
+        })
+    }
+
+    /// Counters of all peers seen so far, with the given reputation scores filled in.
+    /// Peers that only appear in `scores` get zero counters.
+    pub fn snapshot(&self, scores: impl IntoIterator<Item = (PeerId, f64)>) -> Vec<PeerStat> {
+        let mut counters = self.counters.lock().clone();
+        let mut scores = scores.into_iter().collect::<HashMap<_, _>>();
+        for &peer_id in scores.keys() {
+            counters.entry(peer_id).or_default();
+        }
+
+        let mut stats = counters
+            .into_iter()
+            .map(|(peer_id, counters)| PeerStat {
+                peer_id,
+                reputation: scores.remove(&peer_id).unwrap_or(0.0),
+                responses: counters.responses,
+                bytes_delivered: counters.bytes_delivered,
+                timeouts: counters.timeouts,
+                penalties: counters.penalties,
+                latency_p95: counters.latency_p95(),
+            })
+            .collect::<Vec<_>>();
+        stats.sort_unstable_by_key(|stat| stat.peer_id);
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot() {
+        let counters = PeerCounters::default();
+        let peers = [1, 2, 3].map(PeerId::repeat_byte);
+
+        counters.record_response(peers[0], 100);
+        counters.record_response(peers[0], 50);
+        counters.record_timeout(peers[1]);
+        counters.record_penalty(peers[1]);
+
+        assert_eq!(
+            counters.snapshot([(peers[1], -10.0), (peers[2], -50.0)]),
+            vec![
+                PeerStat {
+                    peer_id: peers[0],
+                    reputation: 0.0,
+                    responses: 2,
+                    bytes_delivered: 150,
+                    timeouts: 0,
+                    penalties: 0,
+                    latency_p95: None,
+                },
+                PeerStat {
+                    peer_id: peers[1],
+                    reputation: -10.0,
+                    responses: 0,
+                    bytes_delivered: 0,
+                    timeouts: 1,
+                    penalties: 1,
+                    latency_p95: None,
+                },
+                PeerStat {
+                    peer_id: peers[2],
+                    reputation: -50.0,
+                    responses: 0,
+                    bytes_delivered: 0,
+                    timeouts: 0,
+                    penalties: 0,
+                    latency_p95: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn adaptive_timeout() {
+        let counters = PeerCounters::default();
+        let peers = [1, 2, 3].map(PeerId::repeat_byte);
+        let fallback = Duration::from_secs(5);
+        let ms = Duration::from_millis;
+
+        // Too few samples to go by.
+        for latency in [100, 200, 300, 400] {
+            counters.record_latency(peers[0], ms(latency));
+        }
+        assert_eq!(counters.latency_p95(peers[0]), None);
+        assert_eq!(counters.response_timeout([peers[0]], fallback), fallback);
+
+        for latency in 5..=20 {
+            counters.record_latency(peers[0], ms(latency * 100));
+        }
+        assert_eq!(counters.latency_p95(peers[0]), Some(ms(1900)));
+        assert_eq!(counters.response_timeout([peers[0]], fallback), ms(3800));
+
+        // Only the recent latencies count: the peer got faster.
+        for _ in 0..PeerCounters::LATENCY_SAMPLES {
+            counters.record_latency(peers[0], ms(40));
+        }
+        assert_eq!(counters.latency_p95(peers[0]), Some(ms(40)));
+        assert_eq!(
+            counters.response_timeout([peers[0]], fallback),
+            PeerCounters::MIN_RESPONSE_TIMEOUT
+        );
+
+        // The slowest peer sets the timeout, and an unknown one sets it back to the fallback.
+        for _ in 0..PeerCounters::MIN_LATENCY_SAMPLES {
+            counters.record_latency(peers[1], ms(1000));
+        }
+        assert_eq!(
+            counters.response_timeout([peers[0], peers[1]], fallback),
+            ms(2000)
+        );
+        assert_eq!(
+            counters.response_timeout([peers[0], peers[1], peers[2]], fallback),
+            fallback
+        );
+        assert_eq!(counters.response_timeout([], fallback), fallback);
+
+        assert_eq!(
+            counters
+                .snapshot([])
+                .into_iter()
+                .map(|stat| stat.latency_p95)
+                .collect::<Vec<_>>(),
+            vec![Some(ms(40)), Some(ms(1000))]
+        );
+    }
+}
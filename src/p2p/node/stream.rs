@@ -2,9 +2,20 @@ use super::Sentry;
 use crate::p2p::types::InboundMessage;
 use ethereum_interfaces::sentry::{self as grpc_sentry, PenalizePeerRequest};
 use futures::Stream;
-use std::{pin::Pin, time::Duration};
-use tokio::sync::mpsc;
+use std::{pin::Pin, sync::Arc, time::Duration};
+use tokio::sync::{mpsc, watch};
 use tokio_stream::StreamExt;
+use tracing::*;
+
+/// State of the message streams from a sentry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SentryConnectionState {
+    Connected,
+    /// A message stream dropped and is being re-established.
+    Reconnecting,
+    /// Reconnecting keeps failing. Attempts go on at the maximum backoff.
+    Failed,
+}
 
 pub struct SentryStream;
 
@@ -12,13 +23,18 @@ pub type NodeStream = Pin<Box<dyn Stream<Item = InboundMessage> + Send>>;
 
 impl SentryStream {
     const BACKOFF: Duration = Duration::from_millis(100);
+    const MAX_BACKOFF: Duration = Duration::from_secs(30);
+    const FAILED_AFTER_ATTEMPTS: u32 = 10;
 
+    /// Subscribes to messages from the sentry. Dropped subscriptions are re-established with
+    /// exponential backoff, reporting progress to `connection`.
     #[allow(clippy::new_ret_no_self)]
     pub async fn new(
         sentry: &Sentry,
         sentry_id: usize,
         pred: Vec<i32>,
-    ) -> anyhow::Result<NodeStream> {
+        connection: Arc<watch::Sender<SentryConnectionState>>,
+    ) -> NodeStream {
         let (penalize_tx, mut penalize_rx) = mpsc::channel(4);
         tokio::task::spawn({
             let mut sentry = sentry.clone();
@@ -34,34 +50,93 @@ impl SentryStream {
             }
         });
 
-        let stream = {
-            let mut sentry = sentry.clone();
-            let mut inner_stream = sentry
-                .messages(grpc_sentry::MessagesRequest { ids: pred })
-                .await?
-                .into_inner();
-
-            Box::pin(async_stream::stream! {
-                loop {
-                    if let Some(Ok(msg)) = inner_stream.next().await {
-                        let peer_id = msg.peer_id.clone();
+        let mut sentry = sentry.clone();
+        // Subscribe right away so that no messages are missed before the stream is polled.
+        let mut subscription = Some(
+            sentry
+                .messages(grpc_sentry::MessagesRequest { ids: pred.clone() })
+                .await,
+        );
 
-                        if let Ok(msg) = InboundMessage::new(msg, sentry_id) {
-                            yield msg;
+        Box::pin(async_stream::stream! {
+            let mut attempt = 0_u32;
+            loop {
+                let response = match subscription.take() {
+                    Some(response) => response,
+                    None => {
+                        sentry
+                            .messages(grpc_sentry::MessagesRequest { ids: pred.clone() })
+                            .await
+                    }
+                };
+                let mut inner_stream = match response {
+                    Ok(response) => {
+                        if attempt > 0 {
+                            info!("Reconnected to sentry {sentry_id}");
+                        }
+                        attempt = 0;
+                        Self::set_state(&connection, SentryConnectionState::Connected);
+                        response.into_inner()
+                    }
+                    Err(e) => {
+                        attempt = attempt.saturating_add(1);
+                        let delay = std::cmp::min(
+                            Self::BACKOFF.saturating_mul(2_u32.saturating_pow(attempt - 1)),
+                            Self::MAX_BACKOFF,
+                        );
+                        debug!(
+                            "Failed to subscribe to sentry {sentry_id}, retrying in {delay:?}: {e}"
+                        );
+                        Self::set_state(&connection, if attempt >= Self::FAILED_AFTER_ATTEMPTS {
+                            SentryConnectionState::Failed
                         } else {
+                            SentryConnectionState::Reconnecting
+                        });
+                        tokio::time::sleep(delay).await;
+                        continue;
+                    }
+                };
+
+                while let Some(msg) = inner_stream.next().await {
+                    let msg = match msg {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            debug!("Message stream from sentry {sentry_id} failed: {e}");
+                            break;
+                        }
+                    };
+                    let peer_id = msg.peer_id.clone();
+
+                    match InboundMessage::new(msg, sentry_id) {
+                        Ok(msg) => yield msg,
+                        Err(e) => {
+                            debug!("Rejecting message from peer {peer_id:?}: {e}");
                             let _ = penalize_tx.send(peer_id).await;
                         }
                     }
                 }
-            })
-        };
 
-        Ok::<_, anyhow::Error>(stream)
+                warn!("Lost message stream from sentry {sentry_id}, reconnecting");
+                Self::set_state(&connection, SentryConnectionState::Reconnecting);
+            }
+        })
+    }
+
+    fn set_state(connection: &watch::Sender<SentryConnectionState>, state: SentryConnectionState) {
+        // Several streams share the state, only report actual changes.
+        if *connection.borrow() != state {
+            connection.send_replace(state);
+        }
     }
 
     pub async fn join_all<'sentry, T, P>(iter: T, pred: P) -> NodeStream
     where
-        T: IntoIterator<Item = &'sentry Sentry>,
+        T: IntoIterator<
+            Item = (
+                &'sentry Sentry,
+                &'sentry Arc<watch::Sender<SentryConnectionState>>,
+            ),
+        >,
         P: IntoIterator<Item = i32>,
     {
         let pred = pred.into_iter().collect::<Vec<_>>();
@@ -70,12 +145,12 @@ impl SentryStream {
             futures::future::join_all(
                 iter.into_iter()
                     .enumerate()
-                    .map(|(sentry_id, sentry)| Self::new(sentry, sentry_id, pred.clone()))
+                    .map(|(sentry_id, (sentry, connection))| {
+                        Self::new(sentry, sentry_id, pred.clone(), connection.clone())
+                    })
                     .collect::<Vec<_>>(),
             )
-            .await
-            .into_iter()
-            .filter_map(Result::ok),
+            .await,
         ))
     }
 }
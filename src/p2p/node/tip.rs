@@ -0,0 +1,115 @@
+use super::PeerId;
+use crate::p2p::types::BlockHashAndNumber;
+use std::collections::HashMap;
+
+/// Head advertised by some peers, see [`TipScan`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TipCandidate {
+    pub tip: BlockHashAndNumber,
+    /// Number of peers whose highest announced block this is.
+    pub peers: usize,
+}
+
+/// Chain tips advertised by peers, as collected by [`super::Node::scan_for_tip`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TipScan {
+    /// Every advertised head, the most agreed on first, and the highest first among the
+    /// equally agreed on ones.
+    pub candidates: Vec<TipCandidate>,
+    /// Highest candidate advertised by at least the required number of peers, so that a
+    /// single lying peer cannot pick the target.
+    pub best: Option<BlockHashAndNumber>,
+}
+
+impl TipScan {
+    /// Tallies announcements in the order they were received. Each peer counts once, for the
+    /// highest block it announced, or the last one announced at that height.
+    pub fn tally(
+        announcements: impl IntoIterator<Item = (PeerId, BlockHashAndNumber)>,
+        min_agreement: usize,
+    ) -> Self {
+        let mut heads = HashMap::<PeerId, BlockHashAndNumber>::new();
+        for (peer_id, block) in announcements {
+            let head = heads.entry(peer_id).or_insert(block);
+            if block.number >= head.number {
+                *head = block;
+            }
+        }
+
+        let mut peers = HashMap::<BlockHashAndNumber, usize>::new();
+        for head in heads.into_values() {
+            *peers.entry(head).or_default() += 1;
+        }
+
+        let mut candidates = peers
+            .into_iter()
+            .map(|(tip, peers)| TipCandidate { tip, peers })
+            .collect::<Vec<_>>();
+        candidates.sort_unstable_by(|a, b| {
+            (b.peers, b.tip.number, b.tip.hash).cmp(&(a.peers, a.tip.number, a.tip.hash))
+        });
+
+        let best = candidates
+            .iter()
+            .filter(|candidate| candidate.peers >= min_agreement.max(1))
+            .max_by_key(|candidate| (candidate.tip.number, candidate.peers))
+            .map(|candidate| candidate.tip);
+
+        Self { candidates, best }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{BlockNumber, H256};
+
+    #[test]
+    fn conflicting_heads() {
+        let peers = [1, 2, 3, 4, 5].map(PeerId::repeat_byte);
+        let block = |number, hash| BlockHashAndNumber {
+            number: BlockNumber(number),
+            hash: H256::repeat_byte(hash),
+        };
+        let announcements = [
+            (peers[0], block(99, 0xaa)),
+            (peers[0], block(100, 0xaa)),
+            (peers[1], block(100, 0xaa)),
+            (peers[2], block(100, 0xbb)),
+            (peers[3], block(101, 0xcc)),
+            (peers[3], block(101, 0xaa)),
+            // Far ahead of everyone else, but on its own.
+            (peers[4], block(1_000_000, 0xdd)),
+        ];
+
+        let scan = TipScan::tally(announcements, 2);
+        assert_eq!(
+            scan.candidates,
+            vec![
+                TipCandidate {
+                    tip: block(100, 0xaa),
+                    peers: 2,
+                },
+                TipCandidate {
+                    tip: block(1_000_000, 0xdd),
+                    peers: 1,
+                },
+                TipCandidate {
+                    tip: block(101, 0xaa),
+                    peers: 1,
+                },
+                TipCandidate {
+                    tip: block(100, 0xbb),
+                    peers: 1,
+                },
+            ]
+        );
+        assert_eq!(scan.best, Some(block(100, 0xaa)));
+
+        assert_eq!(
+            TipScan::tally(announcements, 1).best,
+            Some(block(1_000_000, 0xdd))
+        );
+        assert_eq!(TipScan::tally(announcements, 3).best, None);
+    }
+}
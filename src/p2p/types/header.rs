@@ -24,6 +24,28 @@ impl Default for HeaderRequest {
     }
 }
 
+impl HeaderRequest {
+    /// Numbers of the headers answering the request, in the order peers send them, or `None`
+    /// if it starts at a hash.
+    pub fn block_numbers(&self) -> Option<impl Iterator<Item = BlockNumber>> {
+        let BlockId::Number(start) = self.start else {
+            return None;
+        };
+        let step = self.skip.saturating_add(1);
+        let reverse = self.reverse;
+
+        Some((0..self.limit).map_while(move |i| {
+            let offset = i.checked_mul(step)?;
+            let number = if reverse {
+                start.0.checked_sub(offset)
+            } else {
+                start.0.checked_add(offset)
+            };
+            number.map(BlockNumber)
+        }))
+    }
+}
+
 pub struct Announce {
     pub hash: H256,
     pub number: BlockNumber,
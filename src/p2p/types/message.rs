@@ -3,7 +3,6 @@ use crate::{
     p2p::types::*,
     sentry::devp2p::PeerId,
 };
-use anyhow::anyhow;
 use ethereum_interfaces::sentry as grpc_sentry;
 use fastrlp::*;
 use rand::Rng;
@@ -181,40 +180,67 @@ pub struct InboundMessage {
     pub sentry_id: usize,
 }
 
+/// Reason an inbound message was rejected before reaching any subscriber.
+#[derive(Debug, thiserror::Error)]
+pub enum InboundMessageError {
+    #[error("Unsupported message id: {0}")]
+    UnsupportedMessageId(i32),
+    #[error(transparent)]
+    InvalidMessageId(#[from] InvalidMessageId),
+    #[error("Failed to decode {id:?} message at byte {offset}: {source}")]
+    Decode {
+        id: MessageId,
+        /// Offset into the message payload where decoding stopped, or where data trailing a
+        /// complete message starts.
+        offset: usize,
+        source: DecodeError,
+    },
+}
+
+fn decode<T: Decodable>(id: MessageId, data: &[u8]) -> Result<T, InboundMessageError> {
+    let buf = &mut &*data;
+    let decoded = T::decode(buf);
+    let offset = data.len() - buf.len();
+    match decoded {
+        Ok(_) if !buf.is_empty() => Err(InboundMessageError::Decode {
+            id,
+            offset,
+            source: DecodeError::Custom("trailing data after message"),
+        }),
+        Ok(v) => Ok(v),
+        Err(source) => Err(InboundMessageError::Decode { id, offset, source }),
+    }
+}
+
 impl InboundMessage {
+    /// Decodes the whole message payload, so malformed messages are rejected on receipt.
     #[inline]
-    pub fn new(value: grpc_sentry::InboundMessage, sentry_id: usize) -> anyhow::Result<Self> {
-        let msg_data_slice = &mut &*value.data;
-        let msg = match MessageId::try_from(match grpc_sentry::MessageId::from_i32(value.id) {
-            Some(msg_id) => msg_id,
-            _ => return Err(anyhow!("Unsupported message id: {}", value.id)),
-        })? {
-            MessageId::NewBlockHashes => {
-                Message::NewBlockHashes(Decodable::decode(msg_data_slice)?)
-            }
-            MessageId::NewBlock => Message::NewBlock(Box::new(Decodable::decode(msg_data_slice)?)),
-            MessageId::Transactions => Message::Transactions(Decodable::decode(msg_data_slice)?),
+    pub fn new(
+        value: grpc_sentry::InboundMessage,
+        sentry_id: usize,
+    ) -> Result<Self, InboundMessageError> {
+        let data = &*value.data;
+        let id = MessageId::try_from(
+            grpc_sentry::MessageId::from_i32(value.id)
+                .ok_or(InboundMessageError::UnsupportedMessageId(value.id))?,
+        )?;
+        let msg = match id {
+            MessageId::NewBlockHashes => Message::NewBlockHashes(decode(id, data)?),
+            MessageId::NewBlock => Message::NewBlock(Box::new(decode(id, data)?)),
+            MessageId::Transactions => Message::Transactions(decode(id, data)?),
             MessageId::NewPooledTransactionHashes => {
-                Message::NewPooledTransactionHashes(Decodable::decode(msg_data_slice)?)
-            }
-            MessageId::GetBlockHeaders => {
-                Message::GetBlockHeaders(Decodable::decode(msg_data_slice)?)
-            }
-            MessageId::GetBlockBodies => {
-                Message::GetBlockBodies(Decodable::decode(msg_data_slice)?)
+                Message::NewPooledTransactionHashes(decode(id, data)?)
             }
+            MessageId::GetBlockHeaders => Message::GetBlockHeaders(decode(id, data)?),
+            MessageId::GetBlockBodies => Message::GetBlockBodies(decode(id, data)?),
             MessageId::GetNodeData => todo!(),
             MessageId::GetReceipts => todo!(),
-            MessageId::GetPooledTransactions => {
-                Message::GetPooledTransactions(Decodable::decode(msg_data_slice)?)
-            }
-            MessageId::BlockHeaders => Message::BlockHeaders(Decodable::decode(msg_data_slice)?),
-            MessageId::BlockBodies => Message::BlockBodies(Decodable::decode(msg_data_slice)?),
+            MessageId::GetPooledTransactions => Message::GetPooledTransactions(decode(id, data)?),
+            MessageId::BlockHeaders => Message::BlockHeaders(decode(id, data)?),
+            MessageId::BlockBodies => Message::BlockBodies(decode(id, data)?),
             MessageId::NodeData => todo!(),
             MessageId::Receipts => todo!(),
-            MessageId::PooledTransactions => {
-                Message::PooledTransactions(Decodable::decode(msg_data_slice)?)
-            }
+            MessageId::PooledTransactions => Message::PooledTransactions(decode(id, data)?),
             _ => todo!(),
         };
         Ok(InboundMessage {
@@ -1,3 +1,6 @@
+//! Bundled [`ChainSpec`] presets. Upgrades are scheduled by block number, so networks that
+//! schedule them by timestamp, such as holesky, are out of scope and have no preset.
+
 use crate::models::ChainSpec;
 use once_cell::sync::Lazy;
 
@@ -1,7 +1,8 @@
 use crate::sentry::devp2p::types::*;
 use async_stream::stream;
 use futures::{stream::BoxStream, StreamExt};
-use std::{collections::HashMap, net::SocketAddr, pin::Pin, task::Poll, time::Duration};
+use parking_lot::RwLock;
+use std::{collections::HashMap, net::SocketAddr, pin::Pin, sync::Arc, task::Poll, time::Duration};
 use tokio::time::sleep;
 use tokio_stream::Stream;
 
@@ -14,14 +15,61 @@ pub use self::dns::DnsDiscovery;
 
 pub type Discovery = BoxStream<'static, anyhow::Result<NodeRecord>>;
 
+/// Peers that are always dialed, e.g. on private chains without discovery bootnodes.
+/// May be changed while a [`StaticNodes`] discovery is running.
+#[derive(Debug, Default)]
+pub struct StaticPeers(RwLock<HashMap<PeerId, SocketAddr>>);
+
+impl StaticPeers {
+    pub fn new(nodes: impl IntoIterator<Item = NodeRecord>) -> Self {
+        let peers = Self::default();
+        for node in nodes {
+            peers.add(node);
+        }
+        peers
+    }
+
+    /// Returns whether the peer was not a static peer yet. Replaces its address if it was.
+    pub fn add(&self, NodeRecord { id, addr }: NodeRecord) -> bool {
+        self.0.write().insert(id, addr).is_none()
+    }
+
+    /// Returns whether the peer was a static peer. Does not disconnect it.
+    pub fn remove(&self, id: PeerId) -> bool {
+        self.0.write().remove(&id).is_some()
+    }
+
+    pub fn contains(&self, id: PeerId) -> bool {
+        self.0.read().contains_key(&id)
+    }
+
+    pub fn nodes(&self) -> Vec<NodeRecord> {
+        self.0
+            .read()
+            .iter()
+            .map(|(&id, &addr)| NodeRecord { id, addr })
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.read().is_empty()
+    }
+}
+
 pub struct StaticNodes(Pin<Box<dyn Stream<Item = anyhow::Result<NodeRecord>> + Send + 'static>>);
 
 impl StaticNodes {
-    pub fn new(nodes: HashMap<SocketAddr, PeerId>, delay: Duration) -> Self {
+    /// Yields the static peers in turn, `delay` apart. Changes to `peers` are picked up
+    /// after each round.
+    pub fn new(peers: Arc<StaticPeers>, delay: Duration) -> Self {
         Self(Box::pin(stream! {
             loop {
-                for (&addr, &id) in &nodes {
-                    yield Ok(NodeRecord { id, addr });
+                let nodes = peers.nodes();
+                if nodes.is_empty() {
+                    sleep(delay).await;
+                }
+                for node in nodes {
+                    yield Ok(node);
                     sleep(delay).await;
                 }
             }
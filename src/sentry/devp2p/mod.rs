@@ -20,9 +20,10 @@ mod types;
 pub mod util;
 
 pub use disc::*;
+pub use node_filter::{PeerAccessList, PeerCooldowns};
 pub use peer::{DisconnectReason, PeerStream};
 pub use rlpx::{ListenOptions, Swarm, SwarmBuilder};
 pub use types::{
-    CapabilityId, CapabilityInfo, CapabilityName, CapabilityServer, CapabilityVersion,
-    InboundEvent, Message, NodeRecord, OutboundEvent, PeerId,
+    parse_enodes, CapabilityId, CapabilityInfo, CapabilityName, CapabilityServer,
+    CapabilityVersion, InboundEvent, Message, NodeRecord, OutboundEvent, PeerId,
 };
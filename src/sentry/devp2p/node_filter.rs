@@ -1,11 +1,15 @@
-use super::types::PeerId;
+use super::{disc::StaticPeers, types::PeerId};
+use crate::kv::{mdbx::WriteMap, new_database, tables, MdbxWithDirHandle};
+use parking_lot::{Mutex, RwLock};
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     fmt::Debug,
+    path::Path,
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc,
     },
+    time::{Duration, SystemTime},
 };
 
 pub trait NodeFilter: Debug + Send + 'static {
@@ -21,13 +25,24 @@ pub trait NodeFilter: Debug + Send + 'static {
 pub struct MemoryNodeFilter {
     peer_limiter: Arc<AtomicUsize>,
     ban_list: HashSet<PeerId>,
+    cooldowns: Arc<PeerCooldowns>,
+    access_list: Arc<PeerAccessList>,
+    static_peers: Arc<StaticPeers>,
 }
 
 impl MemoryNodeFilter {
-    pub fn new(peer_limiter: Arc<AtomicUsize>) -> Self {
+    pub fn new(
+        peer_limiter: Arc<AtomicUsize>,
+        cooldowns: Arc<PeerCooldowns>,
+        access_list: Arc<PeerAccessList>,
+        static_peers: Arc<StaticPeers>,
+    ) -> Self {
         Self {
             peer_limiter,
             ban_list: Default::default(),
+            cooldowns,
+            access_list,
+            static_peers,
         }
     }
 }
@@ -39,9 +54,271 @@ impl NodeFilter for MemoryNodeFilter {
 
     fn is_banned(&self, id: PeerId) -> bool {
         self.ban_list.contains(&id)
+            || !self.access_list.permits(id, &self.static_peers)
+            || self.cooldowns.is_cooling_down(id)
     }
 
     fn ban(&mut self, id: PeerId) {
         self.ban_list.insert(id);
     }
 }
+
+/// Operator-configured peer access rules.
+///
+/// Denylisted peers are always refused. If an allowlist is set, only peers on it and static
+/// peers are accepted. Both lists may be changed at runtime through a shared handle.
+#[derive(Debug, Default)]
+pub struct PeerAccessList {
+    allowlist: RwLock<Option<HashSet<PeerId>>>,
+    denylist: RwLock<HashSet<PeerId>>,
+}
+
+impl PeerAccessList {
+    pub fn new(allowlist: Option<HashSet<PeerId>>, denylist: HashSet<PeerId>) -> Self {
+        Self {
+            allowlist: RwLock::new(allowlist),
+            denylist: RwLock::new(denylist),
+        }
+    }
+
+    /// Replaces the allowlist. `None` accepts every peer that is not denylisted.
+    pub fn set_allowlist(&self, allowlist: Option<HashSet<PeerId>>) {
+        *self.allowlist.write() = allowlist;
+    }
+
+    /// Returns whether the peer was not denylisted yet.
+    pub fn deny(&self, id: PeerId) -> bool {
+        self.denylist.write().insert(id)
+    }
+
+    /// Returns whether the peer was denylisted.
+    pub fn undeny(&self, id: PeerId) -> bool {
+        self.denylist.write().remove(&id)
+    }
+
+    pub fn permits(&self, id: PeerId, static_peers: &StaticPeers) -> bool {
+        !self.denylist.read().contains(&id)
+            && (static_peers.contains(id)
+                || self
+                    .allowlist
+                    .read()
+                    .as_ref()
+                    .map_or(true, |allowlist| allowlist.contains(&id)))
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Recently penalized peers, refused until their cooldown expires.
+///
+/// A first penalty refuses the peer for the `initial` cooldown. Each penalty during a cooldown,
+/// or within as long after it, doubles the cooldown up to `max`, so that a single retryable
+/// fault costs little while repeat offenders stay away.
+///
+/// When opened from a sentry database, expiry times are persisted there, so restarting
+/// does not let penalized peers straight back in.
+#[derive(Debug)]
+pub struct PeerCooldowns {
+    initial: Duration,
+    max: Duration,
+    /// Expiry, as a unix timestamp, and length in seconds of each peer's latest cooldown.
+    cooldowns: Mutex<HashMap<PeerId, (u64, u64)>>,
+    db: Option<MdbxWithDirHandle<WriteMap>>,
+}
+
+impl PeerCooldowns {
+    /// Cooldowns are disabled if `max` is zero.
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            cooldowns: Default::default(),
+            db: None,
+        }
+    }
+
+    /// Opens the cooldown list stored at `path`, forgetting cooldowns that have expired.
+    pub fn open(path: &Path, initial: Duration, max: Duration) -> anyhow::Result<Self> {
+        let db = new_database(&tables::SENTRY_TABLES, path)?;
+        let now = unix_now();
+
+        let mut cooldowns = HashMap::new();
+        let txn = db.begin_mutable()?;
+        let stored = txn
+            .cursor(tables::PeerCooldown)?
+            .walk(None)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        for (peer_id, expiry) in stored {
+            if expiry > now {
+                // Only the remaining time is known of cooldowns from before the restart.
+                cooldowns.insert(peer_id, (expiry, expiry - now));
+            } else {
+                txn.del(tables::PeerCooldown, peer_id, None)?;
+            }
+        }
+        txn.commit()?;
+
+        Ok(Self {
+            initial,
+            max,
+            cooldowns: Mutex::new(cooldowns),
+            db: Some(db),
+        })
+    }
+
+    /// Starts or extends the cooldown of a peer in memory, returning its expiry to
+    /// [`PeerCooldowns::persist`]. `None` if cooldowns are disabled.
+    pub fn penalize(&self, peer_id: PeerId) -> Option<u64> {
+        self.penalize_at(peer_id, unix_now())
+    }
+
+    fn penalize_at(&self, peer_id: PeerId, now: u64) -> Option<u64> {
+        let max = self.max.as_secs();
+        if max == 0 {
+            return None;
+        }
+
+        let mut cooldowns = self.cooldowns.lock();
+        let length = match cooldowns.get(&peer_id) {
+            Some(&(expiry, length)) if now < expiry.saturating_add(length) => {
+                length.saturating_mul(2)
+            }
+            _ => self.initial.as_secs(),
+        }
+        .clamp(1, max);
+        let expiry = now.saturating_add(length);
+        cooldowns.insert(peer_id, (expiry, length));
+
+        Some(expiry)
+    }
+
+    /// Stores a cooldown expiry returned by [`PeerCooldowns::penalize`] in the sentry
+    /// database, if opened from one. Opens a write transaction, so keep it off async tasks.
+    pub fn persist(&self, peer_id: PeerId, expiry: u64) -> anyhow::Result<()> {
+        if let Some(db) = &self.db {
+            let txn = db.begin_mutable()?;
+            txn.set(tables::PeerCooldown, peer_id, expiry)?;
+            txn.commit()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn is_cooling_down(&self, peer_id: PeerId) -> bool {
+        self.is_cooling_down_at(peer_id, unix_now())
+    }
+
+    fn is_cooling_down_at(&self, peer_id: PeerId, now: u64) -> bool {
+        self.cooldowns
+            .lock()
+            .get(&peer_id)
+            .map_or(false, |&(expiry, _)| expiry > now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sentry::devp2p::types::NodeRecord;
+
+    #[test]
+    fn access_list() {
+        let [a, b, c, d] = [1, 2, 3, 4].map(PeerId::repeat_byte);
+        let access_list = Arc::new(PeerAccessList::new(None, [c].into_iter().collect()));
+        let static_peers = Arc::new(StaticPeers::default());
+        let filter = MemoryNodeFilter::new(
+            Arc::new(AtomicUsize::new(10)),
+            Arc::new(PeerCooldowns::new(Duration::ZERO, Duration::ZERO)),
+            access_list.clone(),
+            static_peers.clone(),
+        );
+        assert!(filter.is_allowed(0, a));
+        assert!(filter.is_allowed(0, b));
+        assert!(!filter.is_allowed(0, c));
+
+        access_list.set_allowlist(Some([a, c].into_iter().collect()));
+        assert!(filter.is_allowed(0, a));
+        assert!(!filter.is_allowed(0, b));
+        // The denylist wins over the allowlist.
+        assert!(!filter.is_allowed(0, c));
+
+        // Static peers need not be allowlisted, but can be denied.
+        assert!(!filter.is_allowed(0, d));
+        for id in [c, d] {
+            static_peers.add(NodeRecord {
+                id,
+                addr: "127.0.0.1:30303".parse().unwrap(),
+            });
+        }
+        assert!(filter.is_allowed(0, d));
+        assert!(!filter.is_allowed(0, c));
+        static_peers.remove(c);
+
+        assert!(access_list.undeny(c));
+        assert!(filter.is_allowed(0, c));
+        assert!(access_list.deny(a));
+        assert!(!filter.is_allowed(0, a));
+
+        access_list.set_allowlist(None);
+        assert!(filter.is_allowed(0, b));
+    }
+
+    #[test]
+    fn cooldowns_survive_restart() {
+        const COOLDOWN: Duration = Duration::from_secs(600);
+
+        let dir = tempfile::tempdir().unwrap();
+        let peer = PeerId::repeat_byte(1);
+        let expired = PeerId::repeat_byte(2);
+        let now = unix_now();
+
+        {
+            let cooldowns = PeerCooldowns::open(dir.path(), COOLDOWN, COOLDOWN).unwrap();
+            assert!(!cooldowns.is_cooling_down(peer));
+
+            let expiry = cooldowns.penalize(peer).unwrap();
+            cooldowns.persist(peer, expiry).unwrap();
+            let expiry = cooldowns
+                .penalize_at(expired, now - 2 * COOLDOWN.as_secs())
+                .unwrap();
+            cooldowns.persist(expired, expiry).unwrap();
+            assert!(cooldowns.is_cooling_down(peer));
+            assert!(!cooldowns.is_cooling_down(expired));
+        }
+
+        // Restart.
+        let cooldowns = PeerCooldowns::open(dir.path(), COOLDOWN, COOLDOWN).unwrap();
+        assert!(cooldowns.is_cooling_down(peer));
+        assert!(!cooldowns.is_cooling_down_at(peer, now + COOLDOWN.as_secs() + 1));
+        assert!(!cooldowns.is_cooling_down_at(expired, 0));
+
+        let txn = cooldowns.db.as_ref().unwrap().begin().unwrap();
+        assert!(txn.get(tables::PeerCooldown, expired).unwrap().is_none());
+    }
+
+    #[test]
+    fn cooldowns_escalate() {
+        let cooldowns = PeerCooldowns::new(Duration::from_secs(30), Duration::from_secs(100));
+        let peer = PeerId::repeat_byte(1);
+
+        // A single fault costs the initial cooldown, repeated ones double it up to the max.
+        assert_eq!(cooldowns.penalize_at(peer, 1000), Some(1030));
+        assert_eq!(cooldowns.penalize_at(peer, 1010), Some(1070));
+        assert_eq!(cooldowns.penalize_at(peer, 1100), Some(1200));
+        assert_eq!(cooldowns.penalize_at(peer, 1200), Some(1300));
+
+        // Behaving for as long as the last cooldown starts over.
+        assert_eq!(cooldowns.penalize_at(peer, 1400), Some(1430));
+        assert!(cooldowns.is_cooling_down_at(peer, 1429));
+        assert!(!cooldowns.is_cooling_down_at(peer, 1430));
+
+        let disabled = PeerCooldowns::new(Duration::from_secs(30), Duration::ZERO);
+        assert_eq!(disabled.penalize_at(peer, 1000), None);
+        assert!(!disabled.is_cooling_down_at(peer, 1000));
+    }
+}
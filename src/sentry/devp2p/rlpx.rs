@@ -1,8 +1,8 @@
 //! RLPx protocol implementation in Rust
 
 use super::{
-    disc::Discovery,
-    node_filter::{MemoryNodeFilter, NodeFilter},
+    disc::{Discovery, StaticPeers},
+    node_filter::{MemoryNodeFilter, NodeFilter, PeerAccessList, PeerCooldowns},
     peer::*,
     transport::{TcpServer, TokioCidrListener, Transport},
     types::*,
@@ -519,6 +519,9 @@ pub struct SwarmBuilder {
     task_group: Option<Arc<TaskGroup>>,
     listen_options: Option<ListenOptions>,
     client_version: String,
+    peer_cooldowns: Option<Arc<PeerCooldowns>>,
+    peer_access_list: Option<Arc<PeerAccessList>>,
+    static_peers: Option<Arc<StaticPeers>>,
 }
 
 impl SwarmBuilder {
@@ -537,6 +540,22 @@ impl SwarmBuilder {
         self
     }
 
+    pub fn with_peer_cooldowns(mut self, peer_cooldowns: Arc<PeerCooldowns>) -> Self {
+        self.peer_cooldowns = Some(peer_cooldowns);
+        self
+    }
+
+    pub fn with_peer_access_list(mut self, peer_access_list: Arc<PeerAccessList>) -> Self {
+        self.peer_access_list = Some(peer_access_list);
+        self
+    }
+
+    /// Static peers, which are exempt from the allowlist of the peer access list.
+    pub fn with_static_peers(mut self, static_peers: Arc<StaticPeers>) -> Self {
+        self.static_peers = Some(static_peers);
+        self
+    }
+
     /// Create a new RLPx node
     pub async fn build<C: CapabilityServer>(
         self,
@@ -551,6 +570,10 @@ impl SwarmBuilder {
             capability_mask.into(),
             capability_server,
             self.listen_options,
+            self.peer_cooldowns
+                .unwrap_or_else(|| Arc::new(PeerCooldowns::new(Duration::ZERO, Duration::ZERO))),
+            self.peer_access_list.unwrap_or_default(),
+            self.static_peers.unwrap_or_default(),
         )
         .await
     }
@@ -594,6 +617,9 @@ impl Swarm<()> {
             task_group: None,
             listen_options: None,
             client_version: format!("rust-devp2p/{}", env!("CARGO_PKG_VERSION")),
+            peer_cooldowns: None,
+            peer_access_list: None,
+            static_peers: None,
         }
     }
 }
@@ -617,6 +643,9 @@ impl<C: CapabilityServer> Swarm<C> {
         capabilities: CapabilitySet,
         capability_server: Arc<C>,
         listen_options: Option<ListenOptions>,
+        peer_cooldowns: Arc<PeerCooldowns>,
+        peer_access_list: Arc<PeerAccessList>,
+        static_peers: Arc<StaticPeers>,
     ) -> anyhow::Result<Arc<Self>> {
         let tasks = task_group.unwrap_or_default();
 
@@ -628,9 +657,12 @@ impl<C: CapabilityServer> Swarm<C> {
             .as_ref()
             .map_or(usize::MAX, |options| options.max_peers.get());
         let streams = Arc::new(Mutex::new(PeerStreams::new(max_peers)));
-        let node_filter = Arc::new(Mutex::new(MemoryNodeFilter::new(Arc::new(
-            max_peers.into(),
-        ))));
+        let node_filter = Arc::new(Mutex::new(MemoryNodeFilter::new(
+            Arc::new(max_peers.into()),
+            peer_cooldowns,
+            peer_access_list,
+            static_peers,
+        )));
 
         let capabilities = Arc::new(capabilities);
 
@@ -22,21 +22,39 @@ impl FromStr for NodeRecord {
     type Err = Box<dyn std::error::Error + Send + Sync>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        const PREFIX: &str = "enode://";
-
-        let (prefix, data) = s.split_at(PREFIX.len());
-        if prefix != PREFIX {
-            return Err("Not an enode".into());
-        }
-
-        let mut parts = data.split('@');
-        let id = parts.next().ok_or("Failed to read remote ID")?.parse()?;
-        let addr = parts.next().ok_or("Failed to read address")?.parse()?;
+        let data = s.strip_prefix("enode://").ok_or("Not an enode URL")?;
+        // Discovery port is not needed to connect.
+        let data = data.split_once('?').map_or(data, |(data, _)| data);
+
+        let (id, addr) = data
+            .split_once('@')
+            .ok_or("No '@' between node ID and address")?;
+        let id = id
+            .parse()
+            .map_err(|e| format!("Invalid node ID {id:?}: {e}"))?;
+        let addr = addr
+            .parse()
+            .map_err(|e| format!("Invalid address {addr:?}: {e}"))?;
 
         Ok(Self { id, addr })
     }
 }
 
+/// Parses a list of enode URLs, failing with the first malformed one.
+pub fn parse_enodes<'a, T>(urls: impl IntoIterator<Item = &'a str>) -> anyhow::Result<Vec<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    urls.into_iter()
+        .enumerate()
+        .map(|(i, url)| {
+            url.parse()
+                .map_err(|e| anyhow::format_err!("Malformed enode URL #{i} {url:?}: {e}"))
+        })
+        .collect()
+}
+
 #[derive(Clone, Copy, Debug, Display, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct CapabilityName(pub ArrayString<4>);
 
@@ -149,3 +167,44 @@ pub struct Message {
     #[educe(Debug(method = "hex_debug"))]
     pub data: Bytes,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sentry::devp2p::disc::v4;
+
+    #[test]
+    fn parses_enode_lists() {
+        let id = "9246d00bc8fd1742e5ad2428b80fc4dc45d786283e05ef6edbd9002cbc335d40998444732fbe921cb88e1d2c73d1b1de53bae6a2237996e9bfe14f871baf7066";
+        let urls = [
+            format!("enode://{id}@18.168.182.86:30303"),
+            format!("enode://{id}@[::1]:30304?discport=30301"),
+        ];
+
+        let nodes = parse_enodes::<NodeRecord>(urls.iter().map(String::as_str)).unwrap();
+        assert_eq!(nodes.len(), 2);
+        assert!(nodes.iter().all(|node| node.id == id.parse().unwrap()));
+        assert_eq!(nodes[0].addr, "18.168.182.86:30303".parse().unwrap());
+        assert_eq!(nodes[1].addr, "[::1]:30304".parse().unwrap());
+
+        let bootnodes =
+            parse_enodes::<v4::NodeRecord>(urls[..1].iter().map(String::as_str)).unwrap();
+        assert_eq!(bootnodes[0].tcp_port, 30303);
+
+        for (url, error) in [
+            ("enode:/", "Not an enode URL"),
+            (
+                "enode://18.168.182.86:30303",
+                "No '@' between node ID and address",
+            ),
+            ("enode://abcd@18.168.182.86:30303", "Invalid node ID"),
+            (&*format!("enode://{id}@18.168.182.86"), "Invalid address"),
+        ] {
+            let e = parse_enodes::<NodeRecord>([urls[0].as_str(), url])
+                .unwrap_err()
+                .to_string();
+            assert!(e.starts_with("Malformed enode URL #1"), "{e}");
+            assert!(e.contains(error), "{e}");
+        }
+    }
+}
@@ -403,12 +403,30 @@ pub struct Opts {
     /// Disable DNS discovery
     #[clap(long, takes_value = false)]
     pub no_dns_discovery: bool,
+    /// Time for which peers are refused after a first penalty, in seconds.
+    #[clap(long, default_value = "30")]
+    pub peer_cooldown_initial: u64,
+    /// Maximum time for which penalized peers are refused, in seconds. Each penalty during
+    /// a cooldown doubles it, up to this. Survives restarts.
+    #[clap(long, default_value = "600")]
+    pub peer_cooldown: u64,
+    /// Only connect to these peers (node ids in hex), plus static peers.
+    #[clap(long)]
+    pub allow_peers: Vec<PeerId>,
+    /// Never connect to or send requests to these peers (node ids in hex).
+    #[clap(long)]
+    pub deny_peers: Vec<PeerId>,
 }
 
+/// Starts the sentry. `static_peers` are dialed along with `--static-peers` and may be
+/// changed while the sentry runs, as may `peer_access_list`, which `--allow-peers` and
+/// `--deny-peers` are added to.
 pub async fn run(
     opts: Opts,
     db_path: AkulaDataDir,
     network_params: P2PParams,
+    static_peers: Arc<StaticPeers>,
+    peer_access_list: Arc<PeerAccessList>,
 ) -> anyhow::Result<Arc<Swarm<CapabilityServerImpl>>> {
     let secret_key = {
         let secret_key_path = db_path.nodekey();
@@ -460,10 +478,10 @@ pub async fn run(
     let mut discovery_tasks: HashMap<String, Discovery> = HashMap::new();
 
     let bootnodes = if opts.discv4_bootnodes.is_empty() {
-        network_params
-            .bootnodes
-            .iter()
-            .map(|b| Discv4NR(b.parse().unwrap()))
+        parse_enodes(network_params.bootnodes.iter().map(String::as_str))
+            .context("Invalid bootnodes in chain spec")?
+            .into_iter()
+            .map(Discv4NR)
             .collect::<Vec<_>>()
     } else {
         opts.discv4_bootnodes
@@ -515,21 +533,22 @@ pub async fn run(
         discovery_tasks.insert("discv4".to_string(), Box::pin(task));
     }
 
-    if !opts.static_peers.is_empty() {
-        info!("Enabling static peers: {:?}", opts.static_peers);
-
-        let task = StaticNodes::new(
-            opts.static_peers
-                .iter()
-                .map(|&NR(NodeRecord { addr, id })| (addr, id))
-                .collect::<HashMap<_, _>>(),
-            Duration::from_millis(opts.static_peers_interval),
-        );
-
-        discovery_tasks.insert("static peers".to_string(), Box::pin(task));
+    for &NR(node) in &opts.static_peers {
+        static_peers.add(node);
+    }
+    if !static_peers.is_empty() {
+        info!("Enabling static peers: {:?}", static_peers.nodes());
     }
+    let no_static_peers = static_peers.is_empty();
+    discovery_tasks.insert(
+        "static peers".to_string(),
+        Box::pin(StaticNodes::new(
+            static_peers.clone(),
+            Duration::from_millis(opts.static_peers_interval),
+        )),
+    );
 
-    if discovery_tasks.is_empty() {
+    if discovery_tasks.len() == 1 && no_static_peers {
         warn!("All discovery methods are disabled, sentry will not search for peers.");
     }
 
@@ -541,6 +560,22 @@ pub async fn run(
 
     let no_new_peers = capability_server.no_new_peers_handle();
 
+    let peer_cooldowns = Arc::new(
+        PeerCooldowns::open(
+            &db_path.sentry_db(),
+            Duration::from_secs(opts.peer_cooldown_initial),
+            Duration::from_secs(opts.peer_cooldown),
+        )
+        .context("Failed to open sentry database")?,
+    );
+
+    if !opts.allow_peers.is_empty() {
+        peer_access_list.set_allowlist(Some(opts.allow_peers.iter().copied().collect()));
+    }
+    for &peer_id in &opts.deny_peers {
+        peer_access_list.deny(peer_id);
+    }
+
     let swarm = Swarm::builder()
         .with_task_group(tasks.clone())
         .with_listen_options(ListenOptions::new(
@@ -552,6 +587,9 @@ pub async fn run(
             no_new_peers,
         ))
         .with_client_version(version_string())
+        .with_peer_cooldowns(peer_cooldowns.clone())
+        .with_peer_access_list(peer_access_list.clone())
+        .with_static_peers(static_peers.clone())
         .build(
             btreemap! {
                 CapabilityId { name: capability_name(), version: protocol_version as CapabilityVersion } => 17,
@@ -575,7 +613,12 @@ pub async fn run(
     info!("RLPx node listening at {}", listen_addr);
 
     tasks.spawn(async move {
-        let svc = SentryServer::new(SentryService::new(capability_server));
+        let svc = SentryServer::new(SentryService::new(
+            capability_server,
+            peer_cooldowns,
+            peer_access_list,
+            static_peers,
+        ));
 
         info!("Sentry gRPC server starting on {}", opts.sentry_addr);
 
@@ -30,11 +30,24 @@ pub type PeersReplyStream =
 
 pub struct SentryService {
     capability_server: Arc<CapabilityServerImpl>,
+    peer_cooldowns: Arc<PeerCooldowns>,
+    peer_access_list: Arc<PeerAccessList>,
+    static_peers: Arc<StaticPeers>,
 }
 
 impl SentryService {
-    pub fn new(capability_server: Arc<CapabilityServerImpl>) -> Self {
-        Self { capability_server }
+    pub fn new(
+        capability_server: Arc<CapabilityServerImpl>,
+        peer_cooldowns: Arc<PeerCooldowns>,
+        peer_access_list: Arc<PeerAccessList>,
+        static_peers: Arc<StaticPeers>,
+    ) -> Self {
+        Self {
+            capability_server,
+            peer_cooldowns,
+            peer_access_list,
+            static_peers,
+        }
     }
 }
 
@@ -66,6 +79,8 @@ impl SentryService {
         let g = self.capability_server.peer_pipes.read();
         (pred)(&self.capability_server)
             .into_iter()
+            // Peers denied after they connected must not be sent anything either.
+            .filter(|&peer| self.peer_access_list.permits(peer, &self.static_peers))
             .filter_map(|peer| g.get(&peer).map(|pipes| (pipes.sender.clone(), peer)))
             .collect::<Vec<_>>()
     }
@@ -121,6 +136,14 @@ impl Sentry for SentryService {
             .peer_id
             .ok_or_else(|| tonic::Status::invalid_argument("no peer id"))?
             .into();
+        if let Some(expiry) = self.peer_cooldowns.penalize(peer) {
+            let peer_cooldowns = self.peer_cooldowns.clone();
+            match tokio::task::spawn_blocking(move || peer_cooldowns.persist(peer, expiry)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!("Failed to store cooldown for peer {peer}: {e}"),
+                Err(e) => warn!("Failed to store cooldown for peer {peer}: {e}"),
+            }
+        }
         if let Some(sender) = self.capability_server.sender(peer) {
             let _ = sender
                 .send(OutboundEvent::Disconnect {
@@ -0,0 +1,162 @@
+use crate::StageId;
+use auto_impl::auto_impl;
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Response, Server,
+};
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fmt::{Debug, Write},
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+
+/// Receives timing and throughput information after every stage invocation.
+#[auto_impl(&, Box, Arc)]
+pub trait StageMetricsObserver: Send + Sync + Debug {
+    fn on_stage_tick(&self, stage_id: StageId, duration: Duration, blocks_advanced: u64);
+}
+
+impl StageMetricsObserver for () {
+    fn on_stage_tick(&self, _: StageId, _: Duration, _: u64) {}
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StageMetricsEntry {
+    pub invocations: u64,
+    pub total_duration: Duration,
+    pub blocks_advanced: u64,
+}
+
+/// Observer that accumulates per-stage totals, to be periodically read by an exporter.
+#[derive(Debug, Default)]
+pub struct StageMetrics {
+    inner: Mutex<HashMap<StageId, StageMetricsEntry>>,
+}
+
+impl StageMetrics {
+    pub fn snapshot(&self) -> HashMap<StageId, StageMetricsEntry> {
+        self.inner.lock().clone()
+    }
+
+    /// Per-stage totals in the Prometheus text exposition format.
+    pub fn encode_prometheus(&self) -> String {
+        let mut snapshot = self.snapshot().into_iter().collect::<Vec<_>>();
+        snapshot.sort_by_key(|(stage_id, _)| stage_id.0);
+
+        let mut out = String::new();
+        let mut counter = |name: &str, help: &str, value: &dyn Fn(&StageMetricsEntry) -> String| {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} counter");
+            for (stage_id, entry) in &snapshot {
+                let _ = writeln!(out, "{name}{{stage=\"{}\"}} {}", stage_id.0, value(entry));
+            }
+        };
+        counter(
+            "akula_stage_invocations_total",
+            "Number of completed stage invocations.",
+            &|e| e.invocations.to_string(),
+        );
+        counter(
+            "akula_stage_duration_seconds_total",
+            "Time spent in completed stage invocations.",
+            &|e| e.total_duration.as_secs_f64().to_string(),
+        );
+        counter(
+            "akula_stage_blocks_advanced_total",
+            "Blocks by which stage invocations advanced the stage progress.",
+            &|e| e.blocks_advanced.to_string(),
+        );
+        out
+    }
+}
+
+/// Serves [`StageMetrics::encode_prometheus`] over HTTP at `addr`, on any path.
+pub async fn serve_prometheus(metrics: Arc<StageMetrics>, addr: SocketAddr) -> anyhow::Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |_| {
+                let metrics = metrics.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        Response::builder()
+                            .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                            .body(Body::from(metrics.encode_prometheus()))
+                            .unwrap(),
+                    )
+                }
+            }))
+        }
+    });
+
+    Server::try_bind(&addr)?.serve(make_service).await?;
+
+    Ok(())
+}
+
+impl StageMetricsObserver for StageMetrics {
+    fn on_stage_tick(&self, stage_id: StageId, duration: Duration, blocks_advanced: u64) {
+        let mut inner = self.inner.lock();
+        let entry = inner.entry(stage_id).or_default();
+        entry.invocations += 1;
+        entry.total_duration += duration;
+        entry.blocks_advanced += blocks_advanced;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accumulate() {
+        const HEADERS: StageId = StageId("Headers");
+        const BODIES: StageId = StageId("Bodies");
+
+        let metrics = StageMetrics::default();
+        metrics.on_stage_tick(HEADERS, Duration::from_secs(2), 100);
+        metrics.on_stage_tick(HEADERS, Duration::from_secs(3), 50);
+        metrics.on_stage_tick(BODIES, Duration::from_secs(1), 10);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(
+            snapshot[&HEADERS],
+            StageMetricsEntry {
+                invocations: 2,
+                total_duration: Duration::from_secs(5),
+                blocks_advanced: 150,
+            }
+        );
+        assert_eq!(
+            snapshot[&BODIES],
+            StageMetricsEntry {
+                invocations: 1,
+                total_duration: Duration::from_secs(1),
+                blocks_advanced: 10,
+            }
+        );
+
+        assert_eq!(
+            metrics.encode_prometheus(),
+            "\
+# HELP akula_stage_invocations_total Number of completed stage invocations.
+# TYPE akula_stage_invocations_total counter
+akula_stage_invocations_total{stage=\"Bodies\"} 1
+akula_stage_invocations_total{stage=\"Headers\"} 2
+# HELP akula_stage_duration_seconds_total Time spent in completed stage invocations.
+# TYPE akula_stage_duration_seconds_total counter
+akula_stage_duration_seconds_total{stage=\"Bodies\"} 1
+akula_stage_duration_seconds_total{stage=\"Headers\"} 5
+# HELP akula_stage_blocks_advanced_total Blocks by which stage invocations advanced the stage progress.
+# TYPE akula_stage_blocks_advanced_total counter
+akula_stage_blocks_advanced_total{stage=\"Bodies\"} 10
+akula_stage_blocks_advanced_total{stage=\"Headers\"} 150
+"
+        );
+    }
+}
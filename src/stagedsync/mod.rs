@@ -1,11 +1,16 @@
+pub mod metrics;
 pub mod stage;
 pub mod util;
 
-use self::stage::{Stage, StageInput, UnwindInput};
+use self::{
+    metrics::StageMetricsObserver,
+    stage::{Stage, StageInput, UnwindInput},
+};
 use crate::{kv::mdbx::*, models::*, stagedsync::stage::*, StageId};
 use futures::future::BoxFuture;
 use std::time::{Duration, Instant};
 use tokio::sync::watch::{Receiver as WatchReceiver, Sender as WatchSender};
+use tokio_util::sync::CancellationToken;
 use tracing::*;
 
 struct QueuedStage<'db, E>
@@ -15,8 +20,13 @@ where
     stage: Box<dyn Stage<'db, E>>,
     unwind_priority: usize,
     require_tip: bool,
+    /// Builds the replacement of a stage failing with [`StageError::Recoverable`].
+    rebuild: Option<StageBuilder<'db, E>>,
+    restarts_left: usize,
 }
 
+type StageBuilder<'db, E> = Box<dyn Fn() -> anyhow::Result<Box<dyn Stage<'db, E>>> + Send + Sync>;
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct StageExecutionReceipt {
     pub stage_id: StageId,
@@ -56,6 +66,8 @@ where
     start_with_unwind: Option<BlockNumber>,
     exit_after_sync: bool,
     delay_after_sync: Option<Duration>,
+    shutdown_token: Option<CancellationToken>,
+    metrics_observer: Box<dyn StageMetricsObserver>,
     post_cycle_callback:
         Option<Box<dyn Fn(StagedSyncStatus) -> BoxFuture<'static, ()> + Send + 'static>>,
 }
@@ -85,6 +97,8 @@ where
             start_with_unwind: None,
             exit_after_sync: false,
             delay_after_sync: None,
+            shutdown_token: None,
+            metrics_observer: Box::new(()),
             post_cycle_callback: None,
         }
     }
@@ -97,9 +111,37 @@ where
             stage: Box::new(stage),
             require_tip,
             unwind_priority: 0,
+            rebuild: None,
+            restarts_left: 0,
         })
     }
 
+    /// Queues a stage built by `build`. When the stage fails with [`StageError::Recoverable`],
+    /// it is replaced with a fresh one from `build` and the cycle is restarted from the last
+    /// commit, up to `max_restarts` times over the whole run. Further failures are fatal, as
+    /// are failures to build the stage.
+    pub fn push_restartable<S, F>(
+        &mut self,
+        build: F,
+        require_tip: bool,
+        max_restarts: usize,
+    ) -> anyhow::Result<()>
+    where
+        S: Stage<'db, E> + 'static,
+        F: Fn() -> anyhow::Result<S> + Send + Sync + 'static,
+    {
+        self.stages.push(QueuedStage {
+            stage: Box::new(build()?),
+            require_tip,
+            unwind_priority: 0,
+            rebuild: Some(Box::new(move || {
+                Ok(Box::new(build()?) as Box<dyn Stage<'db, E>>)
+            })),
+            restarts_left: max_restarts,
+        });
+        Ok(())
+    }
+
     pub fn push_with_unwind_priority<S>(
         &mut self,
         stage: S,
@@ -112,9 +154,60 @@ where
             stage: Box::new(stage),
             require_tip,
             unwind_priority,
+            rebuild: None,
+            restarts_left: 0,
         })
     }
 
+    /// Queues `stage` to run right after the stage with id `after`, with default unwind
+    /// priority. Hands the stage back if there is no such stage.
+    pub fn insert_after<S>(&mut self, after: StageId, stage: S, require_tip: bool) -> Result<(), S>
+    where
+        S: Stage<'db, E> + 'static,
+    {
+        let Some(index) = self.stage_index(after) else {
+            return Err(stage);
+        };
+
+        self.stages.insert(
+            index + 1,
+            QueuedStage {
+                stage: Box::new(stage),
+                require_tip,
+                unwind_priority: 0,
+                rebuild: None,
+                restarts_left: 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Swaps the queued stage with the same id for `stage`, which takes over its place, tip
+    /// requirement and unwind priority. Returns the replaced stage, or hands `stage` back if
+    /// no stage has its id.
+    pub fn replace<S>(&mut self, stage: S) -> Result<Box<dyn Stage<'db, E>>, S>
+    where
+        S: Stage<'db, E> + 'static,
+    {
+        let Some(index) = self.stage_index(stage.id()) else {
+            return Err(stage);
+        };
+
+        Ok(std::mem::replace(
+            &mut self.stages[index].stage,
+            Box::new(stage),
+        ))
+    }
+
+    /// Ids of the queued stages in execution order.
+    pub fn stage_ids(&self) -> Vec<StageId> {
+        self.stages.iter().map(|s| s.stage.id()).collect()
+    }
+
+    fn stage_index(&self, id: StageId) -> Option<usize> {
+        self.stages.iter().position(|s| s.stage.id() == id)
+    }
+
     pub fn set_pruning_interval(&mut self, v: u64) -> &mut Self {
         self.pruning_interval = v;
         self
@@ -153,6 +246,27 @@ where
         self
     }
 
+    /// Token which, when cancelled, makes [`StagedSync::run`] return at the next stage boundary.
+    ///
+    /// Cancelled between stages, everything done so far is committed. A stage invocation that
+    /// is in progress at the time of cancellation is aborted along with all changes since the
+    /// last commit, which includes the progress of stages completed earlier in the cycle with
+    /// less than [`StagedSync::set_min_progress_to_commit_after_stage`] blocks. They are done
+    /// again by the next run.
+    pub fn set_shutdown_token(&mut self, v: Option<CancellationToken>) -> &mut Self {
+        self.shutdown_token = v;
+        self
+    }
+
+    /// Observer notified with duration and progress of every stage invocation.
+    pub fn set_metrics_observer(
+        &mut self,
+        observer: impl StageMetricsObserver + 'static,
+    ) -> &mut Self {
+        self.metrics_observer = Box::new(observer);
+        self
+    }
+
     pub fn current_stage(&self) -> WatchReceiver<Option<StageId>> {
         self.current_stage_receiver.clone()
     }
@@ -160,7 +274,8 @@ where
     /// Run staged sync loop.
     /// Invokes each loaded stage, and does unwinds if necessary.
     ///
-    /// NOTE: it should never return, except if the loop or any stage fails with error.
+    /// NOTE: it should never return, except if the loop or any stage fails with error,
+    /// or if shutdown was requested via [`StagedSync::set_shutdown_token`].
     pub async fn run(&mut self, db: &'db MdbxEnvironment<E>) -> anyhow::Result<()> {
         let num_stages = self.stages.len();
 
@@ -233,7 +348,7 @@ where
                     ))
                     .await;
 
-                    res?;
+                    res.map_err(|source| StageFailure { stage_id, source })?;
                 }
 
                 bad_block = None;
@@ -253,7 +368,11 @@ where
                 for (
                     stage_index,
                     QueuedStage {
-                        stage, require_tip, ..
+                        stage,
+                        require_tip,
+                        rebuild,
+                        restarts_left,
+                        ..
                     },
                 ) in self.stages.iter_mut().enumerate()
                 {
@@ -261,6 +380,17 @@ where
 
                     let stage_id = stage.id();
 
+                    if self
+                        .shutdown_token
+                        .as_ref()
+                        .map(|token| token.is_cancelled())
+                        .unwrap_or(false)
+                    {
+                        info!("Shutdown requested, exiting before stage {stage_id}");
+                        tx.commit()?;
+                        return Ok(());
+                    }
+
                     self.current_stage_sender.send(Some(stage.id())).unwrap();
 
                     let start_time = Instant::now();
@@ -271,7 +401,7 @@ where
 
                         let stage_id = stage.id();
 
-                        let exec_output: Result<_, StageError> = async {
+                        let exec_future = async {
                             if restarted {
                                 debug!(
                                     "Invoking stage @ {}",
@@ -367,7 +497,7 @@ where
                                 }
                             }
 
-                            Ok(output)
+                            Ok::<_, StageError>(output)
                         }
                         .instrument(span!(
                             Level::INFO,
@@ -376,8 +506,22 @@ where
                             stage_index + 1,
                             num_stages,
                             AsRef::<str>::as_ref(&stage_id)
-                        ))
-                        .await;
+                        ));
+
+                        let invocation_start_time = Instant::now();
+                        let exec_output: Result<_, StageError> =
+                            if let Some(shutdown_token) = &self.shutdown_token {
+                                tokio::select! {
+                                    biased;
+                                    _ = shutdown_token.cancelled() => {
+                                        info!("Shutdown requested, aborting stage {stage_id}");
+                                        return Ok(());
+                                    }
+                                    res = exec_future => res,
+                                }
+                            } else {
+                                exec_future.await
+                            };
 
                         // Check how stage run went.
                         match exec_output {
@@ -388,6 +532,14 @@ where
                             }) => {
                                 stage_id.save_progress(&tx, stage_progress)?;
 
+                                self.metrics_observer.on_stage_tick(
+                                    stage_id,
+                                    Instant::now() - invocation_start_time,
+                                    stage_progress
+                                        .saturating_sub(prev_progress.map(|v| v.0).unwrap_or(0))
+                                        .0,
+                                );
+
                                 macro_rules! record_outliers {
                                     ($f:expr, $v:expr) => {
                                         if let Some(m) = $v {
@@ -404,6 +556,7 @@ where
                                 // Check if we should commit now.
                                 if stage_progress
                                     .saturating_sub(start_progress.map(|v| v.0).unwrap_or(0))
+                                    .0
                                     >= self.min_progress_to_commit_after_stage
                                 {
                                     // Commit and restart transaction.
@@ -438,9 +591,23 @@ where
                                 unwind_to = Some(prev_progress.unwrap_or_default());
                                 continue 'run_loop;
                             }
-                            Err(StageError::Internal(e)) => {
-                                return Err(e);
+                            Err(StageError::Internal(source)) => {
+                                return Err(StageFailure { stage_id, source }.into());
                             }
+                            Err(StageError::Recoverable(source)) => match rebuild {
+                                Some(rebuild) if *restarts_left > 0 => {
+                                    // Changes since the last commit are dropped with the
+                                    // transaction, and the stages before this one redo them.
+                                    *restarts_left -= 1;
+                                    warn!(
+                                        "Stage {stage_id} failed, rebuilding it and restarting the cycle ({restarts_left} restarts left): {source:?}"
+                                    );
+                                    *stage = rebuild()
+                                        .map_err(|source| StageFailure { stage_id, source })?;
+                                    continue 'run_loop;
+                                }
+                                _ => return Err(StageFailure { stage_id, source }.into()),
+                            },
                         }
                     };
                     receipts.push(StageExecutionReceipt {
@@ -546,12 +713,23 @@ where
                     }
                 }
 
+                if self.is_shutdown_requested() {
+                    return Ok(());
+                }
+
                 if let Some(delay_after_sync) = self.delay_after_sync {
                     tokio::time::sleep(delay_after_sync).await
                 }
             }
         }
     }
+
+    fn is_shutdown_requested(&self) -> bool {
+        self.shutdown_token
+            .as_ref()
+            .map(|token| token.is_cancelled())
+            .unwrap_or(false)
+    }
 }
 
 pub fn format_duration(dur: Duration, subsec_millis: bool) -> String {
@@ -573,3 +751,139 @@ pub fn format_duration(dur: Duration, subsec_millis: bool) -> String {
         }
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        kv::new_mem_chaindata,
+        stages::{Finish, TotalGasIndex, TotalTxIndex, FINISH, TOTAL_GAS_INDEX, TOTAL_TX_INDEX},
+    };
+    use anyhow::format_err;
+    use async_trait::async_trait;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    const FLAKY: StageId = StageId("Flaky");
+
+    /// Fails its first invocations as if saving hit a transient database error.
+    #[derive(Debug)]
+    struct Flaky {
+        failures_left: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl<'db, E> Stage<'db, E> for Flaky
+    where
+        E: EnvironmentKind,
+    {
+        fn id(&self) -> StageId {
+            FLAKY
+        }
+        async fn execute<'tx>(
+            &mut self,
+            _: &'tx mut MdbxTransaction<'db, RW, E>,
+            _: StageInput,
+        ) -> Result<ExecOutput, StageError>
+        where
+            'db: 'tx,
+        {
+            if self
+                .failures_left
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                    left.checked_sub(1)
+                })
+                .is_ok()
+            {
+                return Err(StageError::Recoverable(format_err!("transient save error")));
+            }
+
+            Ok(ExecOutput::Progress {
+                stage_progress: BlockNumber(10),
+                done: true,
+                reached_tip: true,
+            })
+        }
+        async fn unwind<'tx>(
+            &mut self,
+            _: &'tx mut MdbxTransaction<'db, RW, E>,
+            input: UnwindInput,
+        ) -> anyhow::Result<UnwindOutput>
+        where
+            'db: 'tx,
+        {
+            Ok(UnwindOutput {
+                stage_progress: input.unwind_to,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn restarts_recoverable_stages() {
+        let db = new_mem_chaindata().unwrap();
+        let failures_left = Arc::new(AtomicUsize::new(2));
+        let built = Arc::new(AtomicUsize::new(0));
+
+        let mut staged_sync = StagedSync::<WriteMap>::new();
+        staged_sync
+            .push_restartable(
+                {
+                    let failures_left = failures_left.clone();
+                    let built = built.clone();
+                    move || {
+                        built.fetch_add(1, Ordering::SeqCst);
+                        Ok(Flaky {
+                            failures_left: failures_left.clone(),
+                        })
+                    }
+                },
+                false,
+                2,
+            )
+            .unwrap();
+        staged_sync.push(Finish, false);
+        staged_sync.set_max_block(Some(BlockNumber(10)));
+
+        // Each failure rebuilds the stage, and the run completes once it recovers.
+        staged_sync.run(&db).await.unwrap();
+        assert_eq!(built.load(Ordering::SeqCst), 3);
+        let tx = db.begin().unwrap();
+        assert_eq!(FLAKY.get_progress(&tx).unwrap(), Some(BlockNumber(10)));
+        assert_eq!(FINISH.get_progress(&tx).unwrap(), Some(BlockNumber(10)));
+        drop(tx);
+
+        // Once the budget is spent the failure is fatal.
+        failures_left.store(1, Ordering::SeqCst);
+        staged_sync.set_max_block(Some(BlockNumber(20)));
+        let e = staged_sync.run(&db).await.unwrap_err();
+        assert_eq!(e.downcast_ref::<StageFailure>().unwrap().stage_id, FLAKY);
+        assert_eq!(built.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn insert_and_replace_stages() {
+        let mut staged_sync = StagedSync::<WriteMap>::new();
+        staged_sync.push(TotalGasIndex, false);
+        staged_sync.push(Finish, false);
+
+        assert!(staged_sync
+            .insert_after(StageId("Missing"), TotalTxIndex, false)
+            .is_err());
+        staged_sync
+            .insert_after(TOTAL_GAS_INDEX, TotalTxIndex, false)
+            .unwrap();
+        assert_eq!(
+            staged_sync.stage_ids(),
+            vec![TOTAL_GAS_INDEX, TOTAL_TX_INDEX, FINISH]
+        );
+
+        let replaced = staged_sync.replace(Finish).unwrap();
+        assert_eq!(replaced.id(), FINISH);
+        assert_eq!(staged_sync.stage_ids().len(), 3);
+
+        let mut other = StagedSync::<WriteMap>::new();
+        assert!(other.replace(Finish).is_err());
+    }
+}
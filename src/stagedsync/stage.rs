@@ -9,6 +9,7 @@ use std::{
     fmt::{Debug, Display},
     time::Instant,
 };
+use thiserror::Error;
 use tracing::*;
 
 #[derive(Clone, Copy, Debug)]
@@ -57,6 +58,10 @@ pub enum StageError {
         error: ValidationError,
     },
     Internal(anyhow::Error),
+    /// Transient failure, e.g. a database hiccup while saving. Stages queued with
+    /// [`StagedSync::push_restartable`](super::StagedSync::push_restartable) are rebuilt and
+    /// the cycle is restarted, others fail like with [`StageError::Internal`].
+    Recoverable(anyhow::Error),
 }
 
 impl From<anyhow::Error> for StageError {
@@ -65,6 +70,26 @@ impl From<anyhow::Error> for StageError {
     }
 }
 
+/// Error returned by [`StagedSync::run`](super::StagedSync::run) when a stage fails with an internal error.
+#[derive(Debug, Error)]
+#[error("Stage {stage_id} failed")]
+pub struct StageFailure {
+    pub stage_id: StageId,
+    pub source: anyhow::Error,
+}
+
+/// A step of [`StagedSync`](super::StagedSync), run for all stages in order on every cycle.
+///
+/// Stages are stored as trait objects, so custom ones can be queued next to the built-in ones
+/// or replace them by id, see [`StagedSync::insert_after`](super::StagedSync::insert_after)
+/// and [`StagedSync::replace`](super::StagedSync::replace). Implementations must uphold:
+///
+/// - Progress: [`ExecOutput::Progress::stage_progress`] is what the stage has written up to
+///   in the transaction it was given, and is saved as its progress in the same transaction.
+///   It must not go beyond what the previous stage has reached.
+/// - Cancellation safety: on shutdown the future returned by [`Stage::execute`] is dropped at
+///   whatever point it is awaiting, and the transaction is discarded. State kept outside the
+///   database must stay consistent with the last committed progress.
 #[async_trait]
 #[auto_impl(&mut, Box)]
 pub trait Stage<'db, E>: Send + Sync + Debug
@@ -102,7 +127,7 @@ where
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct StageId(pub &'static str);
 
 impl AsRef<str> for StageId {
@@ -206,6 +206,11 @@ struct DownloadSession {
 }
 
 impl BodyDownload {
+    #[instrument(
+        level = "debug",
+        skip_all,
+        fields(starting_block = %starting_block, target = %target)
+    )]
     async fn download_bodies<E: EnvironmentKind>(
         &mut self,
         stream: &mut NodeStream,
@@ -365,7 +370,7 @@ impl BodyDownload {
                 let mut pending_bodies = Vec::with_capacity(batch_size);
 
                 let s = stream.filter_map(|msg| match msg.msg {
-                    Message::BlockBodies(msg) => Some(msg),
+                    Message::BlockBodies(bodies) => Some((msg.peer_id, bodies)),
                     _ => None,
                 });
                 tokio::pin!(s);
@@ -379,7 +384,12 @@ impl BodyDownload {
                 loop {
                     select! {
                         res = s.next() => {
-                            if let Some(BlockBodies { request_id, bodies }) = res {
+                            if let Some((peer_id, BlockBodies { request_id, bodies })) = res {
+                                let bytes = bodies
+                                    .iter()
+                                    .map(|body| fastrlp::Encodable::length(body) as u64)
+                                    .sum();
+                                session.handler.peer_counters.record_response(peer_id, bytes);
                                 let mut pending_responses = session.pending_responses.lock();
                                 pending_responses.remove(request_id);
                                 debug!("Accepted block bodies with id {request_id}");
@@ -489,9 +499,7 @@ impl BodyDownload {
 
         for block_number in starting_block..=target {
             if undownloaded_bodies.contains(&block_number) {
-                return Err(DownloadError::ExitEarly(BlockNumber(
-                    block_number.saturating_sub(1),
-                )));
+                return Err(DownloadError::ExitEarly(block_number.saturating_sub(1)));
             }
 
             let (hash, body) = bodies.remove(&block_number).unwrap_or_else(|| {
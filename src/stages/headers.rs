@@ -1,816 +1,5851 @@
 #![allow(unreachable_code)]
 
 use crate::{
-    consensus::{fork_choice_graph::ForkChoiceGraph, Consensus, ForkChoiceMode},
-    kv::{mdbx::*, tables},
-    models::{BlockHeader, BlockNumber, H256},
+    accessors::chain,
+    consensus::{
+        fork_choice_graph::ForkChoiceGraph, Consensus, DuoError, ExtraDataFormat, ForkChoiceMode,
+        ValidationError,
+    },
+    crypto::keccak256,
+    kv::{
+        mdbx::*,
+        tables,
+        traits::{Table, TryGenIter},
+    },
+    models::{
+        align_block_num_to_slice_start, BlockHeader, BlockNumber, BlockNumberRange, ChainConfig,
+        ChainSpec, ConsensusMode, H256, U256,
+    },
     p2p::{
-        node::{Node, NodeStream},
+        node::{Node, NodeStream, PenaltyReason, RequestId},
         types::{BlockHeaders, BlockId, HeaderRequest, Message, Status},
     },
     stagedsync::stage::*,
     StageId, TaskGuard,
 };
-use anyhow::format_err;
+use anyhow::{ensure, format_err};
 use async_trait::async_trait;
 use dashmap::DashMap;
 use ethereum_types::H512;
+use lru::LruCache;
 use parking_lot::Mutex;
 use rand::prelude::*;
 use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    num::NonZeroU64,
+    path::PathBuf,
+    str::FromStr,
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+use tokio::{
+    sync::{broadcast, mpsc, watch},
+    time::Instant,
 };
-use tokio::time::Instant;
 use tokio_stream::StreamExt;
 use tracing::*;
 
 const HEADERS_UPPER_BOUND: usize = 1 << 10;
+const SLICE_SIZE: NonZeroU64 = match NonZeroU64::new(HEADERS_UPPER_BOUND as u64) {
+    Some(size) => size,
+    None => unreachable!(),
+};
 
-const STAGE_UPPER_BOUND: BlockNumber = BlockNumber(90_000);
 const REQUEST_INTERVAL: Duration = Duration::from_secs(10);
 
+// Parts of the stage log under targets nested in the module one, so that e.g.
+// `RUST_LOG=akula::stages::headers=warn,akula::stages::headers::requests=debug` shows only
+// the request scheduling in detail.
+const REQUESTS_TARGET: &str = "akula::stages::headers::requests";
+const RESPONSES_TARGET: &str = "akula::stages::headers::responses";
+const VERIFICATION_TARGET: &str = "akula::stages::headers::verification";
+const AUDIT_TARGET: &str = "akula::stages::headers::audit";
+
 pub const HEADERS: StageId = StageId("Headers");
 
 #[derive(Debug)]
 pub struct HeaderDownload {
     pub node: Arc<Node>,
     pub consensus: Arc<dyn Consensus>,
+    pub options: HeaderDownloadOptions,
+    /// Optional sink for the number of the highest saved header. Updated each time the stage
+    /// writes new headers; they become durable once staged sync commits the transaction.
+    pub saved_progress_sender: Option<watch::Sender<BlockNumber>>,
+    /// Optional sink for the [`HeaderDownloadFrontier`], updated when a download session
+    /// starts and when headers are saved or unwound.
+    pub frontier_sender: Option<watch::Sender<HeaderDownloadFrontier>>,
+    /// [`HeaderDownloadOptions::size_limits`], or the defaults for the chain.
+    pub size_limits: HeaderSizeLimits,
+    /// Headers held back by [`HeaderDownloadOptions::commit_every`].
+    pub unsaved: UnsavedHeaders,
+    /// Download rate and ETA published with [`HeaderDownloadProgress`].
+    pub download_rate: DownloadRateEstimator,
+}
+
+/// Settings of a [`HeaderDownload`], checked by [`HeaderDownload::new`].
+#[derive(Clone, Debug)]
+pub struct HeaderDownloadOptions {
     pub max_block: BlockNumber,
     pub increment: Option<BlockNumber>,
+    /// Maximum number of headers downloaded in a single stage invocation.
+    ///
+    /// Downloaded headers are buffered in the fork choice graph until the batch is complete,
+    /// so this bounds the memory used by the stage, see
+    /// [`HeaderDownloadProgress::buffered_bytes`]. Must be non-zero.
+    pub upper_bound: BlockNumber,
+    /// Optional sink for download progress events, see [`HeaderDownloadProgress`].
+    pub progress_sender: Option<broadcast::Sender<HeaderDownloadProgress>>,
+    /// Delay between re-sending header requests that have not been answered yet.
+    pub retry_backoff: RetryBackoff,
+    /// Maximum number of header requests sent out at once, lowest block numbers first.
+    /// The rest are sent as earlier ones get answered. Must be non-zero.
+    pub max_in_flight_requests: usize,
+    /// Maximum number of distinct peers that forward download requests are spread over,
+    /// preferring the most reputable ones. Other peers stay connected, but are not asked for
+    /// headers. Every request goes to every peer if unset. Must be non-zero.
+    pub max_peers: Option<usize>,
+    /// What to do when downloaded headers do not attach to the stored chain head, which
+    /// usually means the canonical chain has reorganized.
+    pub fork_policy: ForkPolicy,
+    /// How long to wait for a response to a header request when downloading towards an
+    /// external fork choice head, until the peers asked have enough recorded latencies for
+    /// [`PeerCounters::response_timeout`]. During forward download unanswered requests are
+    /// instead re-sent according to [`HeaderDownloadOptions::retry_backoff`], starting from the
+    /// same adaptive timeout.
+    ///
+    /// [`PeerCounters::response_timeout`]: crate::p2p::node::PeerCounters::response_timeout
+    pub request_timeout: Duration,
+    /// Minimum number of connected peers required before downloading starts.
+    pub min_peers: usize,
+    /// Wall-clock budget for the forward download sessions of a single stage invocation.
+    /// Once it runs out the headers downloaded so far are saved, and the stage reports
+    /// that it has not reached the tip.
+    pub max_duration: Option<Duration>,
+    /// Fail a forward download session with [`StalledNoProgress`] once the lowest pending
+    /// block has not advanced for this long, instead of re-requesting slices forever. No
+    /// limit if unset.
+    pub stall_timeout: Option<Duration>,
+    /// Thread pool for seal verification, which is done for all headers of a response at
+    /// once. Uses the global rayon pool, with one thread per core, if unset.
+    pub verification_pool: Option<Arc<rayon::ThreadPool>>,
+    /// Block hashes known in advance to be canonical. Headers up to the highest checkpoint
+    /// are only checked for linkage and against these hashes, skipping consensus and seal
+    /// verification. Empty to verify everything.
+    pub trusted_checkpoints: BTreeMap<BlockNumber, H256>,
+    /// What to do about misbehaving peers, see [`FaultClass`] for where each kind of fault
+    /// is detected.
+    pub fault_policy: FaultPolicy,
+    /// Optional on-disk copy of downloaded headers that are not saved yet, so that they are
+    /// not downloaded again if the node restarts mid-session. See [`HeaderSpill`].
+    pub spill: Option<Arc<HeaderSpill>>,
+    /// Optional local file to take headers from before asking peers, see [`HeaderFile`].
+    pub header_file: Option<Arc<HeaderFile>>,
+    /// Byte limits on forward download responses. [`HeaderSizeLimits::for_chain`] if unset.
+    pub size_limits: Option<HeaderSizeLimits>,
+    /// Switch for suspending forward download without stopping the stage, see
+    /// [`DownloadPause`].
+    pub pause: DownloadPause,
+    /// Accept a short answer to the last slice of a download that ends at the known chain
+    /// tip, taking the chain to end at the last header received. Announced tips can be ahead
+    /// of what peers serve, while short answers to slices below the tip always mean the peer
+    /// held back headers, and are re-requested.
+    pub partial_final_slice: bool,
+    /// Read every saved header back within the same transaction and check that it still
+    /// hashes to the hash it was saved under, failing the stage otherwise. Catches encoding
+    /// and storage bugs at the cost of a lookup and a hash per header.
+    pub verify_after_write: bool,
+    /// Optional record of slice status transitions, see [`SliceAuditLog`]. Nothing is
+    /// recorded if unset.
+    pub slice_audit: Option<Arc<SliceAuditLog>>,
+    /// Fetch a skeleton, the last header of every slice, from a single peer before a forward
+    /// download session, and reject slices that do not end with the skeleton header. Catches
+    /// slices from a different chain before they are buffered, at the cost of one request.
+    pub skeleton: bool,
+    /// Save a downloaded batch in parts, holding the rest back for the following invocations.
+    /// The stage reports that it is not done in between, so that staged sync commits each part
+    /// once [`StagedSync::set_min_progress_to_commit_after_stage`] is reached, instead of the
+    /// whole batch at once. Everything is saved by a single invocation if unset.
+    ///
+    /// [`StagedSync::set_min_progress_to_commit_after_stage`]: crate::stagedsync::StagedSync::set_min_progress_to_commit_after_stage
+    pub commit_every: Option<CommitCadence>,
+    /// Optional record of seal verification outcomes, see [`SealVerificationCache`].
+    pub seal_cache: Option<Arc<SealVerificationCache>>,
+    /// Number of headers whose entries are buffered per table while saving, before each table
+    /// is written in one sorted pass. Must be positive.
+    pub save_batch: usize,
 }
 
-#[async_trait]
-impl<'db, E> Stage<'db, E> for HeaderDownload
-where
-    E: EnvironmentKind,
-{
-    fn id(&self) -> crate::StageId {
-        HEADERS
+impl Default for HeaderDownloadOptions {
+    fn default() -> Self {
+        Self {
+            max_block: u64::MAX.into(),
+            increment: None,
+            upper_bound: HeaderDownload::DEFAULT_UPPER_BOUND,
+            progress_sender: None,
+            retry_backoff: Default::default(),
+            max_in_flight_requests: HeaderDownload::DEFAULT_MAX_IN_FLIGHT_REQUESTS,
+            max_peers: None,
+            fork_policy: Default::default(),
+            request_timeout: HeaderDownload::DEFAULT_REQUEST_TIMEOUT,
+            min_peers: HeaderDownload::DEFAULT_MIN_PEERS,
+            max_duration: None,
+            stall_timeout: None,
+            verification_pool: None,
+            trusted_checkpoints: Default::default(),
+            fault_policy: Default::default(),
+            spill: None,
+            header_file: None,
+            size_limits: None,
+            pause: Default::default(),
+            partial_final_slice: true,
+            verify_after_write: false,
+            slice_audit: None,
+            skeleton: false,
+            commit_every: None,
+            seal_cache: None,
+            save_batch: HeaderDownload::DEFAULT_SAVE_BATCH,
+        }
     }
+}
 
-    async fn execute<'tx>(
-        &mut self,
-        txn: &'tx mut MdbxTransaction<'db, RW, E>,
-        input: StageInput,
-    ) -> Result<ExecOutput, StageError>
-    where
-        'db: 'tx,
-    {
-        let prev_progress = input.stage_progress.unwrap_or_default();
-        self.update_head(txn, prev_progress).await?;
+/// Moving average of the forward download rate, kept across download sessions.
+///
+/// Samples are blended in with a weight that grows with the time since the previous one,
+/// so that the estimate follows changes over about [`DownloadRateEstimator::TIME_CONSTANT`]
+/// however often responses arrive. Nothing is estimated during the first
+/// [`DownloadRateEstimator::WARM_UP`] of downloading, which is averaged as a whole instead.
+#[derive(Debug, Default)]
+pub struct DownloadRateEstimator(Mutex<Option<RateState>>);
 
-        let mut stage_progress = prev_progress;
-        let mut reached_tip = true;
+#[derive(Clone, Copy, Debug)]
+struct RateState {
+    started_at: Instant,
+    sampled_at: Instant,
+    unsampled_blocks: u64,
+    total_blocks: u64,
+    blocks_per_sec: f64,
+}
 
-        if prev_progress < self.max_block {
-            let prev_progress_hash = txn
-                .get(tables::CanonicalHeader, prev_progress)?
-                .ok_or_else(|| {
-                    StageError::Internal(format_err!(
-                        "no canonical hash for block #{prev_progress}"
-                    ))
-                })?;
-            let prev_progress_header = txn
-                .get(tables::Header, (prev_progress, prev_progress_hash))?
-                .ok_or_else(|| {
-                    StageError::Internal(format_err!(
-                        "no canonical header for block #{prev_progress}:{prev_progress_hash:?}"
-                    ))
-                })?;
+impl DownloadRateEstimator {
+    pub const WARM_UP: Duration = Duration::from_secs(5);
+    pub const TIME_CONSTANT: Duration = Duration::from_secs(30);
 
-            let headers;
-            (headers, reached_tip) = match self.consensus.fork_choice_mode() {
-                ForkChoiceMode::External(mut chain_tip_watch) => {
-                    // Reverse download mode
+    /// Records `blocks` downloaded since the previous call and returns the estimated
+    /// rate in blocks per second, `None` while warming up.
+    pub fn record_at(&self, now: Instant, blocks: u64) -> Option<f64> {
+        let mut state = self.0.lock();
+        let state = state.get_or_insert(RateState {
+            started_at: now,
+            sampled_at: now,
+            unsampled_blocks: 0,
+            total_blocks: 0,
+            blocks_per_sec: 0.0,
+        });
+        state.unsampled_blocks += blocks;
 
-                    let prev_progress_block = txn
-                        .get(tables::Header, (prev_progress, prev_progress_hash))?
-                        .ok_or_else(|| {
-                            StageError::Internal(format_err!(
-                                "no header for block #{prev_progress}"
-                            ))
-                        })?;
+        let dt = now
+            .saturating_duration_since(state.sampled_at)
+            .as_secs_f64();
+        if dt > 0.0 {
+            let elapsed = now.saturating_duration_since(state.started_at);
+            state.total_blocks += state.unsampled_blocks;
+            state.blocks_per_sec = if elapsed < Self::WARM_UP {
+                state.total_blocks as f64 / elapsed.as_secs_f64()
+            } else {
+                let weight = 1.0 - (-dt / Self::TIME_CONSTANT.as_secs_f64()).exp();
+                weight * (state.unsampled_blocks as f64 / dt)
+                    + (1.0 - weight) * state.blocks_per_sec
+            };
+            state.sampled_at = now;
+            state.unsampled_blocks = 0;
+        }
 
-                    info!("Awaiting chain tip...");
+        (now.saturating_duration_since(state.started_at) >= Self::WARM_UP)
+            .then_some(state.blocks_per_sec)
+    }
 
-                    let (chain_tip_hash, chain_finalized_hash) = loop {
-                        let _ = chain_tip_watch.changed().await;
+    /// Time to download `remaining` blocks at `blocks_per_sec`, `None` if nothing is being
+    /// downloaded.
+    pub fn eta(blocks_per_sec: f64, remaining: u64) -> Option<Duration> {
+        (blocks_per_sec > 0.0).then(|| Duration::from_secs_f64(remaining as f64 / blocks_per_sec))
+    }
+}
 
-                        let fork_choice = *chain_tip_watch.borrow();
-                        if !fork_choice.head_block.is_zero()
-                            && fork_choice.head_block != prev_progress_hash
-                        {
-                            break (fork_choice.head_block, fork_choice.finalized_block);
-                        }
-                    };
-                    let _ = chain_finalized_hash;
+/// Outcomes of seal verification by slice content, so that a slice that comes in again
+/// unchanged, e.g. after a retry or a discarded session, is not verified again.
+///
+/// Verified headers are grouped into slices by aligned ranges of 1024 block numbers, each keyed
+/// by the hash of its header hashes. The least recently used slices are forgotten once
+/// `capacity` is reached.
+#[derive(Debug)]
+pub struct SealVerificationCache(Mutex<LruCache<H256, Option<usize>>>);
 
-                    info!("Received chain tip hash: {chain_tip_hash}, starting_download");
+impl SealVerificationCache {
+    /// Creates a cache remembering up to `capacity` slices. Must be non-zero.
+    pub fn new(capacity: usize) -> Self {
+        Self(Mutex::new(LruCache::new(capacity)))
+    }
 
-                    let mut stream = self.node.stream_headers().await;
+    fn key(slice: &[(H256, BlockHeader)]) -> H256 {
+        keccak256(
+            slice
+                .iter()
+                .flat_map(|(hash, _)| hash.0)
+                .collect::<Vec<_>>(),
+        )
+    }
 
-                    match self
-                        .reverse_download_linear(
-                            &mut stream,
-                            prev_progress_hash,
-                            &prev_progress_block,
-                            chain_tip_hash,
-                            chain_finalized_hash,
-                        )
-                        .await
-                    {
-                        LinearDownloadResult::Done(buffered_headers) => (
-                            Box::new(buffered_headers.into_values())
-                                as Box<dyn Iterator<Item = (H256, BlockHeader)> + Send>,
-                            true,
-                        ),
-                        LinearDownloadResult::DoesNotAttach => {
-                            return Ok(ExecOutput::Unwind {
-                                unwind_to: prev_progress
-                                    .checked_sub(1)
-                                    .ok_or_else(|| format_err!("Attempting to reorg past genesis"))?
-                                    .into(),
-                            })
-                        }
-                        LinearDownloadResult::NoResponse => {
-                            return Ok(ExecOutput::Progress {
-                                stage_progress: prev_progress,
-                                done: false,
-                                reached_tip: false,
-                            });
-                        }
-                    }
-                }
-                ForkChoiceMode::Difficulty(fork_choice_graph) => {
-                    // Forward download mode
-                    let mut chain_tip = self.node.chain_tip.clone();
-                    let current_chain_tip = loop {
-                        let _ = chain_tip.changed().await;
-                        let (n, _) = *chain_tip.borrow();
-                        if n > prev_progress {
-                            break n;
-                        }
-                    };
+    /// Offset of the first header with an invalid seal in the slice, computed with `verify`
+    /// unless known.
+    fn get_or_verify(
+        &self,
+        slice: &[(H256, BlockHeader)],
+        verify: impl FnOnce(&[(H256, BlockHeader)]) -> Option<usize>,
+    ) -> Option<usize> {
+        let key = Self::key(slice);
+        if let Some(&first_invalid) = self.0.lock().get(&key) {
+            debug!(
+                target: VERIFICATION_TARGET,
+                "Seals of {} headers from #{} already verified",
+                slice.len(),
+                slice[0].1.number
+            );
+            return first_invalid;
+        }
 
-                    debug!("Chain tip={}", current_chain_tip);
+        let first_invalid = verify(slice);
+        self.0.lock().put(key, first_invalid);
+        first_invalid
+    }
+}
 
-                    let (mut target_block, mut reached_tip) = Self::forward_set_target_block(
-                        prev_progress,
-                        self.increment,
-                        current_chain_tip,
-                    );
+/// How much of a downloaded batch is saved per stage invocation, see
+/// [`HeaderDownloadOptions::commit_every`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommitCadence {
+    /// This many slices of 1024 headers. Must be non-zero.
+    Slices(usize),
+    /// Headers up to this many bytes of RLP, rounded up to a whole header.
+    Bytes(u64),
+}
 
-                    let starting_block: BlockNumber = prev_progress + 1;
+impl CommitCadence {
+    /// Number of headers to save now.
+    fn split_point(self, headers: &[(H256, BlockHeader)]) -> usize {
+        match self {
+            Self::Slices(slices) => std::cmp::min(
+                headers.len(),
+                slices.max(1).saturating_mul(HEADERS_UPPER_BOUND),
+            ),
+            Self::Bytes(max_bytes) => {
+                let mut bytes = 0_u64;
+                headers
+                    .iter()
+                    .position(|(_, header)| {
+                        bytes += fastrlp::Encodable::length(header) as u64;
+                        bytes >= max_bytes
+                    })
+                    .map_or(headers.len(), |last| last + 1)
+            }
+        }
+    }
+}
 
-                    if target_block >= self.max_block {
-                        target_block = self.max_block;
-                        reached_tip = true;
-                    }
+/// Downloaded and verified headers not saved yet, see [`HeaderDownloadOptions::commit_every`].
+#[derive(Debug, Default)]
+pub struct UnsavedHeaders {
+    headers: Vec<(H256, BlockHeader)>,
+    reached_tip: bool,
+}
 
-                    info!(
-                        "Target block for download: {target_block}{}",
-                        if reached_tip { ", will reach tip" } else { "" }
-                    );
+impl UnsavedHeaders {
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
 
-                    let headers_cap = (target_block.0 - starting_block.0 + 1) as usize;
-                    let mut headers = Vec::<(H256, BlockHeader)>::with_capacity(headers_cap);
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
 
-                    while headers.len() < headers_cap {
-                        let starting_block = if let Some((_, last_buffered_header)) = headers.last()
-                        {
-                            last_buffered_header.number + 1
-                        } else {
-                            starting_block
-                        };
+    fn attaches_to(&self, hash: H256) -> bool {
+        self.headers
+            .first()
+            .map_or(false, |(_, header)| header.parent_hash == hash)
+    }
 
-                        info!("Download session {starting_block} to {target_block}");
+    fn take(&mut self) -> (Vec<(H256, BlockHeader)>, bool) {
+        (std::mem::take(&mut self.headers), self.reached_tip)
+    }
 
-                        if let Some(mut downloaded) = self
-                            .download_headers(
-                                fork_choice_graph.clone(),
-                                &prev_progress_header,
-                                starting_block,
-                                target_block,
-                            )
-                            .await?
-                        {
-                            // Check that downloaded headers attach to present chain
-                            if let Some((_, first_downloaded)) = downloaded.first() {
-                                if let Some((_, last_buffered)) = headers.last() {
-                                    if last_buffered.hash() != first_downloaded.parent_hash {
-                                        // Does not attach to buffered chain, just pop last header and download again
-                                        headers.pop();
-                                        continue;
-                                    }
-                                } else if prev_progress_hash != first_downloaded.parent_hash {
-                                    // Does not attach to chain in database, unwind and start over
-                                    return Ok(ExecOutput::Unwind {
-                                        unwind_to: BlockNumber(prev_progress.saturating_sub(1)),
-                                    });
-                                }
-                            }
+    fn hold_back(&mut self, headers: Vec<(H256, BlockHeader)>, reached_tip: bool) {
+        self.headers = headers;
+        self.reached_tip = reached_tip;
+    }
 
-                            headers.append(&mut downloaded);
-                        } else {
-                            return Ok(ExecOutput::Unwind {
-                                unwind_to: BlockNumber(prev_progress.saturating_sub(1)),
-                            });
-                        }
-                    }
+    fn clear(&mut self) {
+        self.headers.clear();
+    }
+}
 
-                    (
-                        Box::new(headers.into_iter())
-                            as Box<dyn Iterator<Item = (H256, BlockHeader)> + Send>,
-                        reached_tip,
-                    )
-                }
-            };
+/// Shared switch pausing forward header download, e.g. while the database is being worked
+/// on or to throttle the node. Clones control the same download.
+///
+/// While paused no header requests are sent and no responses are handled; they are picked
+/// up where they were once the download is resumed. The stage keeps its transaction open,
+/// so nothing is saved in the meantime.
+#[derive(Clone, Debug)]
+pub struct DownloadPause(Arc<watch::Sender<bool>>);
 
-            let mut cursor_header_number = txn.cursor(tables::HeaderNumber)?;
-            let mut cursor_header = txn.cursor(tables::Header)?;
-            let mut cursor_canonical = txn.cursor(tables::CanonicalHeader)?;
-            let mut cursor_td = txn.cursor(tables::HeadersTotalDifficulty)?;
-            let mut td = cursor_td.last()?.map(|((_, _), v)| v).unwrap();
+impl Default for DownloadPause {
+    fn default() -> Self {
+        Self(Arc::new(watch::channel(false).0))
+    }
+}
 
-            for (hash, header) in headers {
-                if header.number == 0 {
-                    continue;
-                }
-                if header.number > self.max_block {
-                    break;
-                }
+impl DownloadPause {
+    pub fn pause(&self) {
+        self.0.send_replace(true);
+    }
 
-                let block_number = header.number;
-                td += header.difficulty;
+    pub fn resume(&self) {
+        self.0.send_replace(false);
+    }
 
-                cursor_header_number.put(hash, block_number)?;
-                cursor_header.put((block_number, hash), header)?;
-                cursor_canonical.put(block_number, hash)?;
-                cursor_td.put((block_number, hash), td)?;
+    pub fn is_paused(&self) -> bool {
+        *self.0.borrow()
+    }
 
-                stage_progress = block_number;
-            }
+    /// Returns once the download is not paused.
+    pub async fn wait_resumed(&self) {
+        let mut paused = self.0.subscribe();
+        while *paused.borrow_and_update() {
+            // Cannot fail, the sender is owned by `self`.
+            let _ = paused.changed().await;
         }
+    }
+}
 
-        Ok(ExecOutput::Progress {
-            stage_progress,
-            done: self.increment.is_some() || reached_tip,
-            reached_tip,
-        })
+/// Exponential backoff with jitter for re-sending unanswered requests.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryBackoff {
+    /// Delay before the first re-send.
+    pub base: Duration,
+    /// Upper bound for the delay, before jitter is applied.
+    pub max: Duration,
+    /// Factor by which the delay grows with each attempt.
+    pub multiplier: u32,
+    /// Fraction of the delay, in `[0; 1]`, that is randomly subtracted from it.
+    pub jitter: f64,
+    /// Number of re-sends in a row without any request being answered, after which the peers
+    /// still holding unanswered requests are penalized for timing out and the delay starts
+    /// over from `base`. Re-sends go on at `max` forever if unset.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for RetryBackoff {
+    fn default() -> Self {
+        Self {
+            base: Duration::from_secs(5),
+            max: Duration::from_secs(60),
+            multiplier: 2,
+            jitter: 0.2,
+            max_attempts: Some(10),
+        }
     }
+}
 
-    async fn unwind<'tx>(
-        &mut self,
-        txn: &'tx mut MdbxTransaction<'db, RW, E>,
-        input: UnwindInput,
-    ) -> anyhow::Result<UnwindOutput>
-    where
-        'db: 'tx,
-    {
-        if let ForkChoiceMode::Difficulty(graph) = self.consensus.fork_choice_mode() {
-            graph.lock().clear();
+impl RetryBackoff {
+    pub fn delay(&self, attempt: u32, rng: &mut impl Rng) -> Duration {
+        let delay = std::cmp::min(
+            self.base
+                .saturating_mul(self.multiplier.saturating_pow(attempt)),
+            self.max,
+        );
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter > 0.0 {
+            delay.mul_f64(1.0 - rng.gen_range(0.0..jitter))
+        } else {
+            delay
         }
-        let mut cur = txn.cursor(tables::CanonicalHeader)?;
+    }
+}
 
-        if let Some(bad_block) = input.bad_block {
-            if let Some((_, hash)) = cur.seek_exact(bad_block)? {
-                self.node.mark_bad_block(hash);
-            }
+/// Attempt counter for [`RetryBackoff`], reset whenever the download makes progress.
+#[derive(Debug)]
+struct RetryAttempts {
+    attempt: u32,
+    remaining: u64,
+}
+
+impl RetryAttempts {
+    fn new(remaining: u64) -> Self {
+        Self {
+            attempt: 0,
+            remaining,
         }
+    }
 
-        let mut stage_progress = BlockNumber(0);
-        while let Some((number, _)) = cur.last()? {
-            if number <= input.unwind_to {
-                stage_progress = number;
-                break;
-            }
+    fn reset(&mut self) {
+        self.attempt = 0;
+    }
 
-            cur.delete_current()?;
+    /// Records the number of blocks still pending after a round of requests. Returns `true`
+    /// once `max_attempts` rounds in a row brought no progress, counting starts over then.
+    fn advance(&mut self, remaining: u64, max_attempts: Option<u32>) -> bool {
+        let progressed = remaining < self.remaining;
+        self.remaining = remaining;
+        if progressed {
+            self.attempt = 0;
+            return false;
         }
 
-        Ok(UnwindOutput { stage_progress })
+        self.attempt = self.attempt.saturating_add(1);
+        if max_attempts.map_or(false, |max_attempts| self.attempt >= max_attempts) {
+            self.attempt = 0;
+            true
+        } else {
+            false
+        }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-enum LinearDownloadResult {
-    Done(BTreeMap<BlockNumber, (H256, BlockHeader)>),
-    DoesNotAttach,
-    NoResponse,
+/// Kind of fault in a peer's answer to a header request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FaultClass {
+    /// Request to the external fork choice head went unanswered, the peer may just be slow.
+    /// During forward download unanswered requests are always re-sent.
+    Timeout,
+    /// Forward download response does not fit the request: discontiguous, oversized or
+    /// answering another request.
+    MalformedResponse,
+    /// Downloaded header has an invalid seal or extra data.
+    InvalidSeal,
+    /// Downloaded header breaks the consensus rules it is checked against its parent with,
+    /// e.g. on difficulty, gas limit, timestamp or base fee.
+    InvalidHeader,
+    /// Downloaded header does not attach to its parent or contradicts a trusted checkpoint.
+    BadLinkage,
 }
 
-impl HeaderDownload {
-    const BACK_OFF: Duration = Duration::from_secs(5);
+impl FaultClass {
+    const fn penalty(self) -> PenaltyReason {
+        match self {
+            FaultClass::Timeout => PenaltyReason::Timeout,
+            FaultClass::MalformedResponse
+            | FaultClass::InvalidSeal
+            | FaultClass::InvalidHeader
+            | FaultClass::BadLinkage => PenaltyReason::BadHeader,
+        }
+    }
 
-    async fn reverse_download_linear(
-        &self,
-        stream: &mut NodeStream,
-        head: H256,
-        head_block: &BlockHeader,
-        chain_tip: H256,
-        finalized: H256,
-    ) -> LinearDownloadResult {
-        // TODO: BTreeMap keyed by block number for quick and dirty sorting - need to tweak downloader logic and replace with simple Vec
-        let mut buffered_headers = BTreeMap::<BlockNumber, (H256, BlockHeader)>::new();
-        let mut remaining_attempts = Some(10_usize);
-        loop {
-            let limit = 1024;
-            let (request_start_block, request_start) = buffered_headers
-                .iter()
-                .next()
-                .map(|(_, (_, block))| (Some(block.number), block.parent_hash))
-                .unwrap_or((None, chain_tip));
+    /// Class of a header rejected by [`validate_header`]. Clique keeps its signer list and
+    /// seal in extra data, so its errors all count as seal faults.
+    fn of_rejection(error: &DuoError) -> Self {
+        match error {
+            DuoError::Validation(
+                ValidationError::InvalidSeal
+                | ValidationError::WrongHeaderNonce { .. }
+                | ValidationError::ExtraDataTooLong
+                | ValidationError::WrongDaoExtraData
+                | ValidationError::CliqueError(_),
+            ) => FaultClass::InvalidSeal,
+            _ => FaultClass::InvalidHeader,
+        }
+    }
 
-            info!(
-                "Requesting {limit} headers ending at {}/{request_start:?}",
-                if let Some(block_number) = request_start_block {
-                    format!("#{block_number}")
-                } else {
-                    "the tip".to_string()
-                }
-            );
+    fn abort_error(self) -> anyhow::Error {
+        format_err!("Header download aborted after {self:?} fault")
+    }
+}
 
-            let mut success = true;
-            let sent_request_id = rand::thread_rng().gen();
-            if let Ok(sent) = tokio::time::timeout(
-                Duration::from_secs(5),
-                self.node.send_header_request(
-                    Some(sent_request_id),
-                    HeaderRequest {
-                        start: BlockId::Hash(request_start),
-                        limit,
-                        reverse: true,
-                        ..Default::default()
-                    },
-                    request_start_block,
-                ),
-            )
-            .await
-            {
-                let timeout = tokio::time::sleep(Duration::from_secs(5));
-                tokio::pin!(timeout);
+/// Reaction to a [`FaultClass`]. Peers are picked by the sentries, so a request can
+/// only be steered away from a peer by penalizing it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultAction {
+    /// Request the headers again without holding it against the peer.
+    Retry,
+    /// Penalize the peer and request the headers again.
+    PenalizeAndRetry,
+    /// Fail the stage.
+    Abort,
+}
 
-                let headers = loop {
-                    tokio::select! {
-                        msg = stream.next() => {
-                            if let Some(msg) = msg {
-                                if sent.contains(&(msg.sentry_id, msg.peer_id)) {
-                                    if let Message::BlockHeaders(BlockHeaders { request_id, headers }) = msg.msg.clone() {
-                                        if sent_request_id == request_id && !headers.is_empty() {
-                                            info!("Received {} headers from peer {}/{}", headers.len(), msg.sentry_id, msg.peer_id);
+/// Maps each [`FaultClass`] to a [`FaultAction`]. The default only lets timeouts go
+/// unpunished.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FaultPolicy {
+    pub timeout: FaultAction,
+    pub malformed_response: FaultAction,
+    pub invalid_seal: FaultAction,
+    pub invalid_header: FaultAction,
+    pub bad_linkage: FaultAction,
+}
 
-                                            break Some(headers);
-                                        }
-                                    }
-                                }
-                                debug!("Ignoring unsolicited message from {}/{}: {:?}", msg.sentry_id, msg.peer_id, msg);
-                            }
-                        }
-                        _ = &mut timeout => {
-                            break None;
-                        }
-                    }
-                };
+impl Default for FaultPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: FaultAction::Retry,
+            malformed_response: FaultAction::PenalizeAndRetry,
+            invalid_seal: FaultAction::PenalizeAndRetry,
+            invalid_header: FaultAction::PenalizeAndRetry,
+            bad_linkage: FaultAction::PenalizeAndRetry,
+        }
+    }
+}
 
-                if let Some(mut headers) = headers {
-                    headers.sort_unstable_by_key(|h| h.number);
+impl FaultPolicy {
+    pub const fn action(&self, class: FaultClass) -> FaultAction {
+        match class {
+            FaultClass::Timeout => self.timeout,
+            FaultClass::MalformedResponse => self.malformed_response,
+            FaultClass::InvalidSeal => self.invalid_seal,
+            FaultClass::InvalidHeader => self.invalid_header,
+            FaultClass::BadLinkage => self.bad_linkage,
+        }
+    }
+}
 
-                    let num_headers = headers.len();
+/// Reaction of forward download to headers that do not attach to the stored chain head.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ForkPolicy {
+    /// Fail the stage with a [`ForkDetected`] error. Nothing is unwound, so the stored head
+    /// and [`HeaderDownloadFrontier::saved_up_to`] stay where they are for inspection.
+    FailFast,
+    /// Unwind `max_rewind` blocks, at least one, and download again from there.
+    /// [`HeaderDownloadFrontier::saved_up_to`] moves down to the unwind point.
+    ReAnchor { max_rewind: u64 },
+}
 
-                    // Headers batch received in reverse order, let's unreverse to check sequentially
-                    for header in headers.into_iter().rev() {
-                        let header_hash = header.hash();
+impl Default for ForkPolicy {
+    fn default() -> Self {
+        Self::ReAnchor {
+            max_rewind: HeaderDownload::DEFAULT_REORG_REWIND_DEPTH,
+        }
+    }
+}
 
-                        debug!("Checking header #{}/{header_hash:?}", header.number);
+/// Error failing the headers stage under [`ForkPolicy::FailFast`], reported as the source of
+/// the [`StageFailure`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error)]
+#[error("Downloaded headers do not attach to stored head #{number} {hash:?}")]
+pub struct ForkDetected {
+    /// Stored chain head.
+    pub number: BlockNumber,
+    pub hash: H256,
+}
 
-                        if head == header_hash {
-                            return LinearDownloadResult::Done(buffered_headers);
-                        } else if head_block.number == header.number {
-                            return LinearDownloadResult::DoesNotAttach;
-                        }
+/// Error failing a forward download session that made no progress for
+/// [`HeaderDownloadOptions::stall_timeout`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error)]
+#[error("Header download made no progress past #{block} for {stalled_for:?} with {peers} peers")]
+pub struct StalledNoProgress {
+    /// Lowest block not downloaded yet.
+    pub block: BlockNumber,
+    /// Peers connected when the session gave up.
+    pub peers: usize,
+    pub stalled_for: Duration,
+}
 
-                        // Take earliest block from buffered batch
-                        if let Some((_, (_, earliest_block))) = buffered_headers.iter().next() {
-                            let mut is_parent_of_batch = false;
-                            if let Some(b) = header.number.0.checked_add(1) {
-                                if earliest_block.number.0 == b
-                                    && earliest_block.parent_hash == header_hash
-                                {
-                                    is_parent_of_batch = true;
-                                }
-                            }
+/// Downloaded header that failed verification.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeaderRejection {
+    pub number: BlockNumber,
+    pub hash: H256,
+    pub class: FaultClass,
+    /// Peer the header came from, if known.
+    pub peer: Option<H512>,
+}
 
-                            if !is_parent_of_batch {
-                                // Block does not attach to batch, discard
-                                break;
-                            }
+impl std::fmt::Display for HeaderRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}/{:?} ({:?})", self.number, self.hash, self.class)?;
+        if let Some(peer) = self.peer {
+            write!(f, " from {peer:?}")?;
+        }
+        Ok(())
+    }
+}
 
-                            if let Err(e) =
-                                self.consensus
-                                    .validate_block_header(earliest_block, &header, true)
-                            {
-                                warn!(
-                                    "Failed to validate block header #{}/{header_hash:?}: {e:?}",
-                                    header.number
-                                );
-                                return LinearDownloadResult::NoResponse;
-                            }
-                        } else if chain_tip != header_hash {
-                            // If we don't have buffered batch, then the first block in response (latest block) must be chain tip else is bogus.
-                            break;
-                        }
+/// Outcome of a [`HeaderDownload::dry_run`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DryRunReport {
+    pub range: BlockNumberRange,
+    /// Headers that passed verification.
+    pub verified: usize,
+    pub rejections: Vec<HeaderRejection>,
+    /// Slices whose downloaded headers did not attach to their saved parent, meaning that
+    /// peers follow a different chain there.
+    pub unattached: Vec<BlockNumberRange>,
+}
 
-                        if header_hash == finalized {
-                            remaining_attempts = None;
-                        }
+impl DryRunReport {
+    /// Whether every header in the range was downloaded and passed verification.
+    pub fn is_clean(&self) -> bool {
+        self.rejections.is_empty()
+            && self.unattached.is_empty()
+            && self.verified as u64 == self.range.len()
+    }
+}
 
-                        buffered_headers.insert(header.number, (header_hash, header));
-                    }
+impl std::fmt::Display for DryRunReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Dry run of #{}..=#{} verified {} headers",
+            self.range.start, self.range.end, self.verified
+        )?;
+        if !self.rejections.is_empty() {
+            write!(
+                f,
+                ", rejected {}: {}",
+                self.rejections.len(),
+                self.rejections
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )?;
+        }
+        for slice in &self.unattached {
+            write!(f, ", #{}..=#{} does not attach", slice.start, slice.end)?;
+        }
+        Ok(())
+    }
+}
 
-                    info!(
-                        "Buffered {} (+{num_headers}) headers",
-                        buffered_headers.len()
-                    );
-                } else {
-                    success = false;
+/// Byte limits on forward download responses, checked before any of their headers are
+/// buffered. Exceeding one is a [`FaultClass::MalformedResponse`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeaderSizeLimits {
+    /// Maximum encoded size of a single header.
+    pub max_header_bytes: usize,
+    /// Maximum encoded size of all headers in a response.
+    pub max_response_bytes: usize,
+    /// Maximum length of header extra data, `None` if unbounded.
+    pub max_extra_data: Option<usize>,
+}
+
+impl Default for HeaderSizeLimits {
+    fn default() -> Self {
+        Self {
+            max_header_bytes: Self::DEFAULT_MAX_HEADER_BYTES,
+            max_response_bytes: Self::DEFAULT_MAX_RESPONSE_BYTES,
+            max_extra_data: None,
+        }
+    }
+}
+
+impl HeaderSizeLimits {
+    pub const DEFAULT_MAX_HEADER_BYTES: usize = 8 * 1024;
+    pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 2 * 1024 * 1024;
+
+    /// Default limits with the extra data cap of the chain's seal engine.
+    pub fn for_chain(config: &ChainConfig) -> Self {
+        Self {
+            max_extra_data: config.max_extra_data_length(),
+            ..Default::default()
+        }
+    }
+
+    fn check(&self, headers: &[BlockHeader]) -> anyhow::Result<()> {
+        let mut total = 0;
+        for header in headers {
+            if let Some(max_extra_data) = self.max_extra_data {
+                if header.extra_data.len() > max_extra_data {
+                    return Err(format_err!(
+                        "extra data of #{} is {} bytes, limit is {max_extra_data}",
+                        header.number,
+                        header.extra_data.len()
+                    ));
                 }
-            } else {
-                success = false;
-                tokio::time::sleep(Duration::from_millis(250)).await;
             }
 
-            if !success {
-                if let Some(remaining_attempts) = &mut remaining_attempts {
-                    if let Some(attempts) = remaining_attempts.checked_sub(1) {
-                        *remaining_attempts = attempts;
-                    } else {
-                        return LinearDownloadResult::NoResponse;
-                    }
-                }
+            let len = fastrlp::Encodable::length(header);
+            if len > self.max_header_bytes {
+                return Err(format_err!(
+                    "header #{} is {len} bytes, limit is {}",
+                    header.number,
+                    self.max_header_bytes
+                ));
             }
+            total += len;
+        }
+
+        if total > self.max_response_bytes {
+            return Err(format_err!(
+                "response is {total} bytes, limit is {}",
+                self.max_response_bytes
+            ));
         }
+
+        Ok(())
     }
+}
 
-    pub async fn download_headers(
-        &self,
-        fork_choice_graph: Arc<Mutex<ForkChoiceGraph>>,
-        prev_progress_header: &BlockHeader,
-        start: BlockNumber,
-        end: BlockNumber,
-    ) -> anyhow::Result<Option<Vec<(H256, BlockHeader)>>> {
-        let requests = Arc::new(Self::prepare_requests(start, end));
-        let peer_map = Arc::new(DashMap::new());
+/// Snapshot of a forward download session, published once each received response is handled.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct HeaderDownloadProgress {
+    pub start: BlockNumber,
+    pub end: BlockNumber,
+    pub total_requests: usize,
+    pub pending_requests: usize,
+    /// Download rate, see [`DownloadRateEstimator`]. `None` while warming up.
+    pub blocks_per_sec: Option<f64>,
+    /// Estimated time until the headers up to [`HeaderDownloadOptions::max_block`], or the chain
+    /// tip if lower, are downloaded. `None` while warming up.
+    pub eta: Option<Duration>,
+    /// Estimated memory held by the downloaded headers that are buffered until the session
+    /// ends, see [`ForkChoiceGraph::memory_usage`].
+    pub buffered_bytes: usize,
+    /// RLP size of all headers received so far in this session, including rejected ones.
+    pub bytes_downloaded: u64,
+    /// Time since the session started.
+    pub elapsed: Duration,
+}
 
-        info!(
-            "Will download {} headers over {} requests",
-            end - start + 1,
-            requests.len()
+/// Where the headers stage currently is, for observers that only need the latest state.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeaderDownloadFrontier {
+    /// First block of the current or last download session.
+    pub min_block: BlockNumber,
+    /// Last block of the current or last download session.
+    pub max_block: BlockNumber,
+    /// Highest saved header.
+    pub saved_up_to: BlockNumber,
+}
+
+impl HeaderDownloadFrontier {
+    /// Status in the shape of `eth_syncing`, or `None` once the last download session is
+    /// saved in full.
+    pub fn sync_status(&self) -> Option<HeaderSyncStatus> {
+        if self.saved_up_to >= self.max_block {
+            return None;
+        }
+
+        Some(HeaderSyncStatus {
+            starting_block: self.min_block.saturating_sub(1),
+            current_block: self.saved_up_to,
+            highest_block: self.max_block,
+        })
+    }
+}
+
+/// Progress of a download session, see [`HeaderDownloadFrontier::sync_status`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeaderSyncStatus {
+    /// Highest saved header when the session started.
+    pub starting_block: BlockNumber,
+    /// Highest saved header.
+    pub current_block: BlockNumber,
+    /// Last block of the session.
+    pub highest_block: BlockNumber,
+}
+
+/// Scratch file with complete header segments received during forward download.
+///
+/// Segments are appended as they arrive and read back when the next download session
+/// starts, which answers the matching requests without asking peers again. Spilled headers
+/// are verified like downloaded ones. The file stops growing at `max_bytes` and is removed
+/// once headers are saved or unwound.
+#[derive(Debug)]
+pub struct HeaderSpill {
+    path: PathBuf,
+    max_bytes: u64,
+    size: Mutex<u64>,
+}
+
+impl HeaderSpill {
+    /// Opens the spill at `path`, keeping segments left there by a previous run.
+    pub fn new(path: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        let path = path.into();
+        let size = std::fs::metadata(&path)
+            .map(|metadata| metadata.len())
+            .unwrap_or(0);
+
+        Self {
+            path,
+            max_bytes,
+            size: Mutex::new(size),
+        }
+    }
+
+    /// Appends a segment, unless that would grow the spill past its size limit.
+    pub fn append(&self, headers: Vec<BlockHeader>) -> std::io::Result<()> {
+        let mut data = Vec::new();
+        fastrlp::Encodable::encode(
+            &BlockHeaders {
+                request_id: 0,
+                headers,
+            },
+            &mut data,
         );
 
-        let mut stream = self.node.stream_headers().await;
-        let is_bounded = |block_number: BlockNumber| block_number >= start && block_number <= end;
+        let mut size = self.size.lock();
+        if *size + data.len() as u64 > self.max_bytes {
+            return Ok(());
+        }
 
-        {
-            let mut tasks = Vec::new();
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(&data)?;
+        *size += data.len() as u64;
 
-            let _g = TaskGuard(tokio::task::spawn({
-                let node = self.node.clone();
-                let requests = requests.clone();
+        Ok(())
+    }
 
-                async move {
-                    loop {
-                        let reqs = requests
-                            .iter()
-                            .map(|entry_ref| *entry_ref.value())
-                            .collect::<Vec<_>>();
-                        node.clone().send_many_header_requests(reqs).await;
-                        tokio::time::sleep(Self::BACK_OFF).await;
-                    }
+    /// Reads back all spilled segments. A torn segment at the end, left by a crash in the
+    /// middle of a write, is dropped.
+    pub fn load(&self) -> std::io::Result<Vec<Vec<BlockHeader>>> {
+        let data = match std::fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        let mut buf = &data[..];
+        let mut segments = Vec::new();
+        while !buf.is_empty() {
+            let remaining = buf.len();
+            match <BlockHeaders as fastrlp::Decodable>::decode(&mut buf) {
+                Ok(BlockHeaders { headers, .. }) => segments.push(headers),
+                Err(e) => {
+                    warn!("Dropping {remaining} trailing bytes of header spill: {e}");
+                    let valid = (data.len() - remaining) as u64;
+                    OpenOptions::new()
+                        .write(true)
+                        .open(&self.path)?
+                        .set_len(valid)?;
+                    *self.size.lock() = valid;
+                    break;
                 }
-            }));
+            }
+        }
 
-            while !requests.is_empty() {
-                if let Some(msg) = stream.next().await {
-                    let peer_id = msg.peer_id;
+        Ok(segments)
+    }
 
-                    if let Message::BlockHeaders(inner) = msg.msg {
-                        if inner.headers.is_empty() {
-                            continue;
-                        }
+    /// Removes all spilled segments.
+    pub fn clear(&self) -> std::io::Result<()> {
+        let mut size = self.size.lock();
+        match std::fs::remove_file(&self.path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        *size = 0;
+
+        Ok(())
+    }
+}
+
+/// Local file of headers, e.g. exported from another node, used instead of peers.
+///
+/// The file is a plain concatenation of RLP-encoded headers in ascending block order. Every
+/// header is an RLP list that starts with its own length prefix, so the file is read one
+/// header at a time and needs no index. Headers from the file are verified like downloaded
+/// ones, and the ones it does not have are requested from peers.
+///
+/// Reads are expected to move forward through the file. A read below the last returned
+/// header starts over from the beginning of the file.
+#[derive(Debug)]
+pub struct HeaderFile {
+    path: PathBuf,
+    cursor: Mutex<Option<HeaderFileCursor>>,
+}
+
+#[derive(Debug)]
+struct HeaderFileCursor {
+    reader: BufReader<File>,
+    /// Header read past the end of the previous range.
+    peeked: Option<BlockHeader>,
+    last: Option<BlockNumber>,
+}
+
+impl HeaderFile {
+    /// Largest accepted encoded header, to fail early on a file in another format.
+    const MAX_HEADER_SIZE: usize = 1 << 20;
+
+    pub fn open(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        File::open(&path)?;
+
+        Ok(Self {
+            path,
+            cursor: Mutex::new(None),
+        })
+    }
+
+    /// Reads the headers numbered from `start` to `end` inclusive, in file order.
+    pub fn read(&self, start: BlockNumber, end: BlockNumber) -> anyhow::Result<Vec<BlockHeader>> {
+        let mut cursor = self.cursor.lock();
+        let restart = cursor.as_ref().map_or(true, |cursor| {
+            cursor.last.map_or(false, |last| last >= start)
+        });
+        if restart {
+            *cursor = Some(HeaderFileCursor {
+                reader: BufReader::new(File::open(&self.path)?),
+                peeked: None,
+                last: None,
+            });
+        }
+        let cursor = cursor.as_mut().unwrap();
+
+        let mut headers = Vec::new();
+        loop {
+            let header = if let Some(header) = cursor.peeked.take() {
+                header
+            } else if let Some(header) = Self::read_header(&mut cursor.reader)? {
+                header
+            } else {
+                break;
+            };
+
+            if header.number > end {
+                cursor.peeked = Some(header);
+                break;
+            }
+
+            cursor.last = Some(header.number);
+            if header.number >= start {
+                headers.push(header);
+            }
+        }
+
+        Ok(headers)
+    }
+
+    /// Reads the next header, or `None` at the end of the file.
+    fn read_header(reader: &mut impl BufRead) -> anyhow::Result<Option<BlockHeader>> {
+        if reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+
+        // An RLP prefix is one byte, followed by up to 8 bytes of payload length for long lists.
+        let mut data = vec![0; 1];
+        reader.read_exact(&mut data)?;
+        if data[0] > 0xf7 {
+            data.resize(1 + (data[0] - 0xf7) as usize, 0);
+            reader.read_exact(&mut data[1..])?;
+        }
+
+        let fastrlp::Header {
+            list,
+            payload_length,
+        } = fastrlp::Header::decode(&mut &data[..])
+            .map_err(|e| format_err!("malformed header prefix: {e}"))?;
+        if !list || payload_length > Self::MAX_HEADER_SIZE {
+            return Err(format_err!(
+                "not an RLP-encoded header ({payload_length} bytes)"
+            ));
+        }
+
+        let prefix_length = data.len();
+        data.resize(prefix_length + payload_length, 0);
+        reader.read_exact(&mut data[prefix_length..])?;
+
+        <BlockHeader as fastrlp::Decodable>::decode(&mut &data[..])
+            .map(Some)
+            .map_err(|e| format_err!("malformed header: {e}"))
+    }
+}
+
+/// Where a slice of headers accepted in a download session starts and ends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct SliceBoundary {
+    end: BlockNumber,
+    parent_hash: H256,
+    last_hash: H256,
+    /// Peer the slice came from, `None` for local segments.
+    peer_id: Option<H512>,
+}
+
+/// Boundaries of the slices accepted in a download session, by start block.
+///
+/// Adjacent slices have to link up, which is much cheaper to check than verifying their
+/// headers, so broken responses are caught while the rest of the batch is still downloading.
+#[derive(Debug, Default)]
+struct SliceBoundaries {
+    slices: Mutex<BTreeMap<BlockNumber, SliceBoundary>>,
+    /// Hashes slices ending at the given blocks have to end with, along with the peer that
+    /// sent them, see [`HeaderDownloadOptions::skeleton`].
+    skeleton: Mutex<BTreeMap<BlockNumber, (H256, H512)>>,
+}
+
+impl SliceBoundaries {
+    /// Records the slice starting at `start` if it links up with the adjacent accepted ones.
+    /// Otherwise returns the neighbours it contradicts, which are forgotten as well: either
+    /// side may be the bad one.
+    fn link(
+        &self,
+        start: BlockNumber,
+        boundary: SliceBoundary,
+    ) -> Vec<(BlockNumber, SliceBoundary)> {
+        let mut slices = self.slices.lock();
+
+        let mut mismatched = Vec::new();
+        if let Some((&lower_start, lower)) = slices.range(..start).next_back() {
+            if lower.end + 1 == start && lower.last_hash != boundary.parent_hash {
+                mismatched.push((lower_start, *lower));
+            }
+        }
+        if let Some(upper) = slices.get(&(boundary.end + 1)) {
+            if upper.parent_hash != boundary.last_hash {
+                mismatched.push((boundary.end + 1, *upper));
+            }
+        }
+
+        if mismatched.is_empty() {
+            slices.insert(start, boundary);
+        } else {
+            for (start, _) in &mismatched {
+                slices.remove(start);
+            }
+        }
+
+        mismatched
+    }
+
+    /// Last block of the accepted slice starting at `start`.
+    fn end_of(&self, start: BlockNumber) -> Option<BlockNumber> {
+        self.slices.lock().get(&start).map(|boundary| boundary.end)
+    }
+
+    fn set_skeleton(&self, skeleton: BTreeMap<BlockNumber, (H256, H512)>) {
+        *self.skeleton.lock() = skeleton;
+    }
+
+    /// Checks a slice ending with `last_hash` at `end` against the skeleton. On a mismatch the
+    /// skeleton header is forgotten, as either may be wrong, and the peer it came from is
+    /// returned.
+    fn check_skeleton(&self, end: BlockNumber, last_hash: H256) -> Result<(), H512> {
+        let mut skeleton = self.skeleton.lock();
+        match skeleton.get(&end) {
+            Some(&(hash, peer_id)) if hash != last_hash => {
+                skeleton.remove(&end);
+                Err(peer_id)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// How far a slice of a forward download session has got.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SliceStatus {
+    /// A request for the slice was sent, once per (re-)send.
+    Requested,
+    /// A peer answered the request and the answer was accepted.
+    Received,
+    /// The headers of the slice passed verification.
+    Verified,
+    /// The headers of the slice were written to the database.
+    Saved,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SliceTransition {
+    pub slice: BlockNumberRange,
+    pub status: SliceStatus,
+    pub at: Instant,
+}
+
+/// Bounded history of [`SliceStatus`] transitions, for finding out why a download does not
+/// advance, e.g. a slice that has been re-requested for minutes without an answer.
+///
+/// Once `capacity` transitions are held the oldest ones are dropped. Transitions are also
+/// logged under the `akula::stages::headers::audit` target at debug level.
+#[derive(Debug)]
+pub struct SliceAuditLog {
+    capacity: usize,
+    transitions: Mutex<VecDeque<SliceTransition>>,
+}
+
+impl SliceAuditLog {
+    /// Creates an empty log. `capacity` must be non-zero.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "slice audit capacity must be positive");
+
+        Self {
+            capacity,
+            transitions: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    fn record(&self, slice: BlockNumberRange, status: SliceStatus) {
+        self.record_at(slice, status, Instant::now());
+    }
+
+    fn record_at(&self, slice: BlockNumberRange, status: SliceStatus, at: Instant) {
+        debug!(target: AUDIT_TARGET, "Slice #{}..=#{} {status:?}", slice.start, slice.end);
+
+        let mut transitions = self.transitions.lock();
+        if transitions.len() == self.capacity {
+            transitions.pop_front();
+        }
+        transitions.push_back(SliceTransition { slice, status, at });
+    }
+
+    /// Records slices verified up to `saved_up_to` as saved.
+    fn record_saved(&self, saved_up_to: BlockNumber) {
+        let saved = {
+            let transitions = self.transitions.lock();
+            let mut verified = Vec::new();
+            let mut seen = HashSet::new();
+            // Latest transition of each slice first.
+            for transition in transitions.iter().rev() {
+                if seen.insert(transition.slice)
+                    && transition.status == SliceStatus::Verified
+                    && transition.slice.end <= saved_up_to
+                {
+                    verified.push(transition.slice);
+                }
+            }
+            verified
+        };
+
+        for slice in saved.into_iter().rev() {
+            self.record(slice, SliceStatus::Saved);
+        }
+    }
+
+    /// Number of slices in each status, going by the latest recorded transition of every
+    /// slice. Slices whose transitions have all been dropped are not counted. Linear in the
+    /// capacity of the log, holds its lock throughout.
+    pub fn status_counts(&self) -> HashMap<SliceStatus, usize> {
+        let transitions = self.transitions.lock();
+        let mut seen = HashSet::new();
+        let mut counts = HashMap::new();
+        for transition in transitions.iter().rev() {
+            if seen.insert(transition.slice) {
+                *counts.entry(transition.status).or_default() += 1;
+            }
+        }
+        counts
+    }
+
+    /// Recorded transitions of the slices overlapping `range`, oldest first.
+    pub fn transition_history(&self, range: BlockNumberRange) -> Vec<SliceTransition> {
+        self.transitions
+            .lock()
+            .iter()
+            .filter(|transition| {
+                transition.slice.start <= range.end && range.start <= transition.slice.end
+            })
+            .copied()
+            .collect()
+    }
+}
+
+/// Writes [`HeaderDownloadProgress`] events as newline-delimited JSON, one line per event.
+#[derive(Debug)]
+pub struct JsonProgressReporter<W> {
+    writer: W,
+}
+
+#[derive(Serialize)]
+struct JsonProgressLine<'a> {
+    phase: &'static str,
+    #[serde(flatten)]
+    progress: &'a HeaderDownloadProgress,
+    ts: u64,
+}
+
+impl<W: Write> JsonProgressReporter<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Writes a single event and flushes the writer.
+    pub fn report(&mut self, progress: &HeaderDownloadProgress) -> std::io::Result<()> {
+        let line = JsonProgressLine {
+            phase: "headers",
+            progress,
+            ts: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        serde_json::to_writer(&mut self.writer, &line)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+
+    /// Reports events until all senders are dropped. Blocks the current thread.
+    pub fn run(
+        mut self,
+        mut events: broadcast::Receiver<HeaderDownloadProgress>,
+    ) -> std::io::Result<()> {
+        loop {
+            match events.blocking_recv() {
+                Ok(progress) => self.report(&progress)?,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!("JSON progress reporter skipped {skipped} events");
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<'db, E> Stage<'db, E> for HeaderDownload
+where
+    E: EnvironmentKind,
+{
+    fn id(&self) -> crate::StageId {
+        HEADERS
+    }
+
+    async fn execute<'tx>(
+        &mut self,
+        txn: &'tx mut MdbxTransaction<'db, RW, E>,
+        input: StageInput,
+    ) -> Result<ExecOutput, StageError>
+    where
+        'db: 'tx,
+    {
+        let prev_progress = input.stage_progress.unwrap_or_default();
+        self.update_head(txn, prev_progress).await?;
+        self.update_frontier(|frontier| frontier.saved_up_to = prev_progress);
+
+        let mut stage_progress = prev_progress;
+        let mut reached_tip = true;
+
+        if prev_progress < self.options.max_block {
+            // Held back headers are saved without asking peers.
+            if self.unsaved.is_empty() {
+                if let Err(e) = self
+                    .node
+                    .wait_for_peers(self.options.min_peers, Self::PEER_WAIT_TIMEOUT)
+                    .await
+                {
+                    warn!("Not starting header download: {e}");
+                    return Ok(ExecOutput::Progress {
+                        stage_progress: prev_progress,
+                        done: false,
+                        reached_tip: false,
+                    });
+                }
+            }
+
+            let prev_progress_hash = txn
+                .get(tables::CanonicalHeader, prev_progress)?
+                .ok_or_else(|| {
+                    StageError::Internal(format_err!(
+                        "no canonical hash for block #{prev_progress}"
+                    ))
+                })?;
+            let prev_progress_header = txn
+                .get(tables::Header, (prev_progress, prev_progress_hash))?
+                .ok_or_else(|| {
+                    StageError::Internal(format_err!(
+                        "no canonical header for block #{prev_progress}:{prev_progress_hash:?}"
+                    ))
+                })?;
+
+            let headers;
+            (headers, reached_tip) = match self.consensus.fork_choice_mode() {
+                _ if self.unsaved.attaches_to(prev_progress_hash) => {
+                    let (unsaved, reached_tip) = self.unsaved.take();
+                    info!(
+                        "Saving {} headers held back from the last batch",
+                        unsaved.len()
+                    );
+                    (
+                        Box::new(unsaved.into_iter())
+                            as Box<dyn Iterator<Item = (H256, BlockHeader)> + Send>,
+                        reached_tip,
+                    )
+                }
+                ForkChoiceMode::External(mut chain_tip_watch) => {
+                    // Reverse download mode
+
+                    let prev_progress_block = txn
+                        .get(tables::Header, (prev_progress, prev_progress_hash))?
+                        .ok_or_else(|| {
+                            StageError::Internal(format_err!(
+                                "no header for block #{prev_progress}"
+                            ))
+                        })?;
+
+                    info!("Awaiting chain tip...");
+
+                    let (chain_tip_hash, chain_finalized_hash) = loop {
+                        let _ = chain_tip_watch.changed().await;
+
+                        let fork_choice = *chain_tip_watch.borrow();
+                        if !fork_choice.head_block.is_zero()
+                            && fork_choice.head_block != prev_progress_hash
+                        {
+                            break (fork_choice.head_block, fork_choice.finalized_block);
+                        }
+                    };
+                    let _ = chain_finalized_hash;
+
+                    info!("Received chain tip hash: {chain_tip_hash}, starting_download");
+
+                    let mut stream = self.node.stream_headers().await;
+
+                    match self
+                        .reverse_download_linear(
+                            &mut stream,
+                            prev_progress_hash,
+                            &prev_progress_block,
+                            chain_tip_hash,
+                            chain_finalized_hash,
+                        )
+                        .await
+                    {
+                        LinearDownloadResult::Done(buffered_headers) => (
+                            Box::new(buffered_headers.into_values())
+                                as Box<dyn Iterator<Item = (H256, BlockHeader)> + Send>,
+                            true,
+                        ),
+                        LinearDownloadResult::DoesNotAttach => {
+                            return Ok(ExecOutput::Unwind {
+                                unwind_to: prev_progress
+                                    .checked_sub(1)
+                                    .ok_or_else(|| format_err!("Attempting to reorg past genesis"))?
+                                    .into(),
+                            })
+                        }
+                        LinearDownloadResult::NoResponse => {
+                            return Ok(ExecOutput::Progress {
+                                stage_progress: prev_progress,
+                                done: false,
+                                reached_tip: false,
+                            });
+                        }
+                        LinearDownloadResult::Aborted(class) => {
+                            return Err(class.abort_error().into());
+                        }
+                    }
+                }
+                ForkChoiceMode::Difficulty(fork_choice_graph) => {
+                    // Forward download mode
+                    let mut chain_tip = self.node.chain_tip.clone();
+                    let current_chain_tip = loop {
+                        let _ = chain_tip.changed().await;
+                        let (n, _) = *chain_tip.borrow();
+                        if n > prev_progress {
+                            break n;
+                        }
+                    };
+
+                    debug!("Chain tip={}", current_chain_tip);
+
+                    let (mut target_block, mut reached_tip) = Self::forward_set_target_block(
+                        prev_progress,
+                        self.options.increment,
+                        self.options.upper_bound,
+                        current_chain_tip,
+                    );
+
+                    let starting_block: BlockNumber = prev_progress + 1;
+
+                    if target_block >= self.options.max_block {
+                        target_block = self.options.max_block;
+                        reached_tip = true;
+                    }
+
+                    info!(
+                        "Target block for download: {target_block}{}",
+                        if reached_tip { ", will reach tip" } else { "" }
+                    );
+
+                    let mut headers_cap =
+                        BlockNumberRange::new(starting_block, target_block).len() as usize;
+                    let mut headers = Vec::<(H256, BlockHeader)>::with_capacity(headers_cap);
+                    let deadline = self.options.max_duration.map(|d| Instant::now() + d);
+
+                    while headers.len() < headers_cap {
+                        let starting_block = if let Some((_, last_buffered_header)) = headers.last()
+                        {
+                            last_buffered_header.number + 1
+                        } else {
+                            starting_block
+                        };
+
+                        info!("Download session {starting_block} to {target_block}");
+
+                        // Rejected headers are already dealt with by the fault policy.
+                        let mut rejections = Vec::new();
+                        let download = self.download_session(
+                            fork_choice_graph.clone(),
+                            &prev_progress_header,
+                            starting_block,
+                            target_block,
+                            &mut rejections,
+                        );
+                        let (downloaded, chain_end) = if let Some(deadline) = deadline {
+                            if let Ok(downloaded) =
+                                tokio::time::timeout_at(deadline, download).await
+                            {
+                                downloaded?
+                            } else {
+                                warn!(
+                                    "Header download timed out, saving {} downloaded headers",
+                                    headers.len()
+                                );
+                                reached_tip = false;
+                                break;
+                            }
+                        } else {
+                            download.await?
+                        };
+
+                        if let Some(chain_end) = chain_end {
+                            info!("Peers have no headers past #{chain_end}, ending download there");
+                            target_block = chain_end;
+                            headers_cap = BlockNumberRange::new(prev_progress + 1, target_block)
+                                .len() as usize;
+                        }
+
+                        if let Some(mut downloaded) = downloaded {
+                            // Check that downloaded headers attach to present chain
+                            if let Some((_, first_downloaded)) = downloaded.first() {
+                                if let Some((_, last_buffered)) = headers.last() {
+                                    if last_buffered.hash() != first_downloaded.parent_hash {
+                                        // Does not attach to buffered chain, just pop last header and download again
+                                        headers.pop();
+                                        continue;
+                                    }
+                                } else if prev_progress_hash != first_downloaded.parent_hash {
+                                    // Does not attach to chain in database
+                                    return self.on_fork(prev_progress, prev_progress_hash);
+                                }
+                            }
+
+                            headers.append(&mut downloaded);
+                        } else {
+                            return self.on_fork(prev_progress, prev_progress_hash);
+                        }
+                    }
+
+                    (
+                        Box::new(headers.into_iter())
+                            as Box<dyn Iterator<Item = (H256, BlockHeader)> + Send>,
+                        reached_tip,
+                    )
+                }
+            };
+
+            // Headers left over from a batch that no longer attaches are downloaded again.
+            self.unsaved.clear();
+            let headers: Box<dyn Iterator<Item = (H256, BlockHeader)> + Send> =
+                if let Some(commit_every) = self.options.commit_every {
+                    let mut headers = headers.collect::<Vec<_>>();
+                    let held_back = headers.split_off(commit_every.split_point(&headers));
+                    if !held_back.is_empty() {
+                        info!(
+                            "Holding back {} headers until the next commit",
+                            held_back.len()
+                        );
+                        self.unsaved.hold_back(held_back, reached_tip);
+                        reached_tip = false;
+                    }
+                    Box::new(headers.into_iter())
+                } else {
+                    headers
+                };
+
+            stage_progress = self
+                .save_headers(txn, prev_progress, prev_progress_hash, headers)
+                .map_err(Self::save_error)?;
+
+            if stage_progress > prev_progress {
+                if let Some(saved_progress_sender) = &self.saved_progress_sender {
+                    let _ = saved_progress_sender.send(stage_progress);
+                }
+                self.update_frontier(|frontier| frontier.saved_up_to = stage_progress);
+                self.clear_spill();
+                if let Some(slice_audit) = &self.options.slice_audit {
+                    slice_audit.record_saved(stage_progress);
+                }
+            }
+        }
+
+        Ok(ExecOutput::Progress {
+            stage_progress,
+            done: self.unsaved.is_empty() && (self.options.increment.is_some() || reached_tip),
+            reached_tip,
+        })
+    }
+
+    async fn unwind<'tx>(
+        &mut self,
+        txn: &'tx mut MdbxTransaction<'db, RW, E>,
+        input: UnwindInput,
+    ) -> anyhow::Result<UnwindOutput>
+    where
+        'db: 'tx,
+    {
+        if let ForkChoiceMode::Difficulty(graph) = self.consensus.fork_choice_mode() {
+            graph.lock().clear();
+        }
+        self.unsaved.clear();
+        let mut cur = txn.cursor(tables::CanonicalHeader)?;
+
+        if let Some(bad_block) = input.bad_block {
+            if let Some((_, hash)) = cur.seek_exact(bad_block)? {
+                self.node.mark_bad_block(hash);
+            }
+        }
+
+        let mut stage_progress = BlockNumber(0);
+        while let Some((number, _)) = cur.last()? {
+            if number <= input.unwind_to {
+                stage_progress = number;
+                break;
+            }
+
+            cur.delete_current()?;
+        }
+        self.update_frontier(|frontier| frontier.saved_up_to = stage_progress);
+        self.clear_spill();
+
+        Ok(UnwindOutput { stage_progress })
+    }
+}
+
+/// Appends the entry if its key is past `last_key`, otherwise falls back to a normal put.
+fn put_or_append<T>(
+    cursor: &mut MdbxCursor<'_, RW, T>,
+    last_key: &mut Option<T::Key>,
+    key: T::Key,
+    value: T::Value,
+) -> anyhow::Result<()>
+where
+    T: Table,
+    T::Key: Copy + Ord,
+{
+    if last_key.map(|last_key| key > last_key).unwrap_or(true) {
+        cursor.append(key, value)?;
+        *last_key = Some(key);
+    } else {
+        cursor.put(key, value)?;
+    }
+
+    Ok(())
+}
+
+struct SaveCursors<'tx> {
+    header_number: MdbxCursor<'tx, RW, tables::HeaderNumber>,
+    header: MdbxCursor<'tx, RW, tables::Header>,
+    canonical: MdbxCursor<'tx, RW, tables::CanonicalHeader>,
+    td: MdbxCursor<'tx, RW, tables::HeadersTotalDifficulty>,
+    last_header: Option<(BlockNumber, H256)>,
+    last_canonical: Option<BlockNumber>,
+    last_td: Option<(BlockNumber, H256)>,
+}
+
+/// Entries of [`HeaderDownloadOptions::save_batch`] headers, buffered per table so that each table
+/// is written in one sorted pass instead of interleaving puts across tables per header.
+struct SaveBatch {
+    header_numbers: Vec<(H256, BlockNumber)>,
+    headers: Vec<((BlockNumber, H256), BlockHeader)>,
+    canonical: Vec<(BlockNumber, H256)>,
+    total_difficulties: Vec<((BlockNumber, H256), U256)>,
+}
+
+impl SaveBatch {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            header_numbers: Vec::with_capacity(capacity),
+            headers: Vec::with_capacity(capacity),
+            canonical: Vec::with_capacity(capacity),
+            total_difficulties: Vec::with_capacity(capacity),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    fn push(&mut self, hash: H256, header: BlockHeader, td: U256) {
+        let key = (header.number, hash);
+        self.header_numbers.push((hash, header.number));
+        self.headers.push((key, header));
+        self.canonical.push(key);
+        self.total_difficulties.push((key, td));
+    }
+
+    /// Writes out and clears the buffered entries. Headers are pushed in ascending order, so
+    /// only the hash index needs sorting.
+    fn flush<E: EnvironmentKind>(
+        &mut self,
+        txn: &MdbxTransaction<'_, RW, E>,
+        cursors: &mut SaveCursors<'_>,
+        verify_after_write: bool,
+    ) -> anyhow::Result<()> {
+        self.header_numbers.sort_unstable_by_key(|(hash, _)| *hash);
+        for (hash, block_number) in self.header_numbers.drain(..) {
+            cursors.header_number.put(hash, block_number)?;
+        }
+
+        for (key, header) in self.headers.drain(..) {
+            put_or_append(&mut cursors.header, &mut cursors.last_header, key, header)?;
+            if verify_after_write {
+                let (block_number, hash) = key;
+                let stored = txn.get(tables::Header, key)?;
+                if stored.map(|header| header.hash()) != Some(hash) {
+                    return Err(format_err!(
+                        "header #{block_number}/{hash:?} does not hash to its key after write"
+                    ));
+                }
+            }
+        }
+
+        for (block_number, hash) in self.canonical.drain(..) {
+            put_or_append(
+                &mut cursors.canonical,
+                &mut cursors.last_canonical,
+                block_number,
+                hash,
+            )?;
+        }
+
+        for (key, td) in self.total_difficulties.drain(..) {
+            put_or_append(&mut cursors.td, &mut cursors.last_td, key, td)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// State of a forward download session, shared by the tasks handling its responses, see
+/// [`HeaderDownload::handle_response`].
+#[derive(Clone)]
+struct SessionState {
+    node: Arc<Node>,
+    /// Slices not received yet, by first block.
+    requests: Arc<DashMap<BlockNumber, HeaderRequest>>,
+    request_ids: Arc<DashMap<RequestId, BlockNumber>>,
+    graph: Arc<Mutex<ForkChoiceGraph>>,
+    /// Peer each buffered header came from.
+    peer_map: Arc<DashMap<H256, H512>>,
+    bytes_downloaded: Arc<AtomicU64>,
+    slices: Arc<SliceBoundaries>,
+    /// Slice that may be answered short, see [`HeaderDownloadOptions::partial_final_slice`].
+    final_slice: Option<BlockNumber>,
+    fault_policy: FaultPolicy,
+    spill: Option<Arc<HeaderSpill>>,
+    size_limits: HeaderSizeLimits,
+    slice_audit: Option<Arc<SliceAuditLog>>,
+}
+
+impl SessionState {
+    /// Starts a session of `stage` downloading `requests` into `graph`.
+    fn new(
+        stage: &HeaderDownload,
+        requests: DashMap<BlockNumber, HeaderRequest>,
+        graph: Arc<Mutex<ForkChoiceGraph>>,
+    ) -> Self {
+        Self {
+            node: stage.node.clone(),
+            requests: Arc::new(requests),
+            request_ids: Default::default(),
+            graph,
+            peer_map: Default::default(),
+            bytes_downloaded: Default::default(),
+            slices: Default::default(),
+            final_slice: None,
+            fault_policy: stage.options.fault_policy,
+            spill: stage.options.spill.clone(),
+            size_limits: stage.size_limits,
+            slice_audit: stage.options.slice_audit.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum LinearDownloadResult {
+    Done(BTreeMap<BlockNumber, (H256, BlockHeader)>),
+    DoesNotAttach,
+    NoResponse,
+    Aborted(FaultClass),
+}
+
+impl HeaderDownload {
+    /// Default for [`HeaderDownloadOptions::upper_bound`], about 12.5 days of blocks at the
+    /// [`ChainConfig::block_time`] of the live networks.
+    pub const DEFAULT_UPPER_BOUND: BlockNumber = BlockNumber(90_000);
+    /// Default for [`HeaderDownloadOptions::max_in_flight_requests`].
+    pub const DEFAULT_MAX_IN_FLIGHT_REQUESTS: usize = 128;
+    /// Default for [`ForkPolicy::ReAnchor::max_rewind`].
+    pub const DEFAULT_REORG_REWIND_DEPTH: u64 = 1;
+    /// Default for [`HeaderDownloadOptions::request_timeout`].
+    pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+    /// Default for [`HeaderDownloadOptions::min_peers`].
+    pub const DEFAULT_MIN_PEERS: usize = 1;
+    /// Default for [`HeaderDownloadOptions::save_batch`], a slice's worth.
+    pub const DEFAULT_SAVE_BATCH: usize = HEADERS_UPPER_BOUND;
+    const PEER_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+    const SKELETON_ATTEMPTS: usize = 3;
+
+    /// Fails if `options` are outside the bounds documented on its fields.
+    pub fn new(
+        node: Arc<Node>,
+        consensus: Arc<dyn Consensus>,
+        options: HeaderDownloadOptions,
+    ) -> anyhow::Result<Self> {
+        ensure!(options.upper_bound.0 > 0, "upper bound must be non-zero");
+        ensure!(
+            options.max_in_flight_requests > 0,
+            "maximum number of in-flight requests must be non-zero"
+        );
+        ensure!(
+            options.max_peers != Some(0),
+            "maximum number of peers must be non-zero"
+        );
+        ensure!(options.save_batch > 0, "save batch must be positive");
+
+        Ok(Self {
+            size_limits: options
+                .size_limits
+                .unwrap_or_else(|| HeaderSizeLimits::for_chain(&node.config)),
+            node,
+            consensus,
+            options,
+            saved_progress_sender: None,
+            frontier_sender: None,
+            unsaved: Default::default(),
+            download_rate: Default::default(),
+        })
+    }
+
+    async fn reverse_download_linear(
+        &self,
+        stream: &mut NodeStream,
+        head: H256,
+        head_block: &BlockHeader,
+        chain_tip: H256,
+        finalized: H256,
+    ) -> LinearDownloadResult {
+        // TODO: BTreeMap keyed by block number for quick and dirty sorting - need to tweak downloader logic and replace with simple Vec
+        let mut buffered_headers = BTreeMap::<BlockNumber, (H256, BlockHeader)>::new();
+        let mut remaining_attempts = Some(10_usize);
+        loop {
+            let limit = 1024;
+            let (request_start_block, request_start) = buffered_headers
+                .iter()
+                .next()
+                .map(|(_, (_, block))| (Some(block.number), block.parent_hash))
+                .unwrap_or((None, chain_tip));
+
+            info!(
+                "Requesting {limit} headers ending at {}/{request_start:?}",
+                if let Some(block_number) = request_start_block {
+                    format!("#{block_number}")
+                } else {
+                    "the tip".to_string()
+                }
+            );
+
+            let mut success = true;
+            let sent_request_id = rand::thread_rng().gen();
+            if let Ok(Ok(sent)) = tokio::time::timeout(
+                Duration::from_secs(5),
+                self.node.send_header_request(
+                    Some(sent_request_id),
+                    HeaderRequest {
+                        start: BlockId::Hash(request_start),
+                        limit,
+                        reverse: true,
+                        ..Default::default()
+                    },
+                    request_start_block,
+                ),
+            )
+            .await
+            {
+                let timeout = tokio::time::sleep(self.node.peer_counters.response_timeout(
+                    sent.iter().map(|&(_, peer_id)| peer_id),
+                    self.options.request_timeout,
+                ));
+                tokio::pin!(timeout);
+
+                let headers = loop {
+                    tokio::select! {
+                        msg = stream.next() => {
+                            if let Some(msg) = msg {
+                                if sent.contains(&(msg.sentry_id, msg.peer_id)) && self.node.reputation.is_reputable(msg.peer_id) {
+                                    if let Message::BlockHeaders(BlockHeaders { request_id, headers }) = msg.msg.clone() {
+                                        if sent_request_id == request_id && !headers.is_empty() {
+                                            info!(target: RESPONSES_TARGET, "Received {} headers from peer {}/{}", headers.len(), msg.sentry_id, msg.peer_id);
+
+                                            break Some(headers);
+                                        }
+                                    }
+                                }
+                                debug!(target: RESPONSES_TARGET, "Ignoring unsolicited message from {}/{}: {:?}", msg.sentry_id, msg.peer_id, msg);
+                            }
+                        }
+                        _ = &mut timeout => {
+                            break None;
+                        }
+                    }
+                };
+
+                if let Some(mut headers) = headers {
+                    headers.sort_unstable_by_key(|h| h.number);
+
+                    let num_headers = headers.len();
+
+                    // Headers batch received in reverse order, let's unreverse to check sequentially
+                    for header in headers.into_iter().rev() {
+                        let header_hash = header.hash();
+
+                        debug!("Checking header #{}/{header_hash:?}", header.number);
+
+                        if head == header_hash {
+                            return LinearDownloadResult::Done(buffered_headers);
+                        } else if head_block.number == header.number {
+                            return LinearDownloadResult::DoesNotAttach;
+                        }
+
+                        // Take earliest block from buffered batch
+                        if let Some((_, (_, earliest_block))) = buffered_headers.iter().next() {
+                            let mut is_parent_of_batch = false;
+                            if let Some(b) = header.number.0.checked_add(1) {
+                                if earliest_block.number.0 == b
+                                    && earliest_block.parent_hash == header_hash
+                                {
+                                    is_parent_of_batch = true;
+                                }
+                            }
+
+                            if !is_parent_of_batch {
+                                // Block does not attach to batch, discard
+                                break;
+                            }
+
+                            if let Err(e) =
+                                self.consensus
+                                    .validate_block_header(earliest_block, &header, true)
+                            {
+                                warn!(
+                                    "Failed to validate block header #{}/{header_hash:?}: {e:?}",
+                                    header.number
+                                );
+                                return LinearDownloadResult::NoResponse;
+                            }
+                        } else if chain_tip != header_hash {
+                            // If we don't have buffered batch, then the first block in response (latest block) must be chain tip else is bogus.
+                            break;
+                        }
+
+                        if header_hash == finalized {
+                            remaining_attempts = None;
+                        }
+
+                        buffered_headers.insert(header.number, (header_hash, header));
+                    }
+
+                    info!(
+                        "Buffered {} (+{num_headers}) headers",
+                        buffered_headers.len()
+                    );
+                } else if !self.node.is_sentry_connected() {
+                    // Peers are not to blame, wait for the sentry without using up an attempt.
+                    self.node.wait_for_sentry_connection().await;
+                } else {
+                    success = false;
+
+                    if let Err(class) = Self::on_fault(
+                        &self.node,
+                        self.options.fault_policy,
+                        FaultClass::Timeout,
+                        sent.iter().map(|(_, peer_id)| *peer_id),
+                    )
+                    .await
+                    {
+                        return LinearDownloadResult::Aborted(class);
+                    }
+                }
+            } else {
+                // The send timed out on our side, the peers are not to blame.
+                success = false;
+                tokio::time::sleep(Duration::from_millis(250)).await;
+            }
+
+            if !success {
+                if let Some(remaining_attempts) = &mut remaining_attempts {
+                    if let Some(attempts) = remaining_attempts.checked_sub(1) {
+                        *remaining_attempts = attempts;
+                    } else {
+                        return LinearDownloadResult::NoResponse;
+                    }
+                }
+            }
+        }
+    }
+
+    pub async fn download_headers(
+        &self,
+        fork_choice_graph: Arc<Mutex<ForkChoiceGraph>>,
+        prev_progress_header: &BlockHeader,
+        start: BlockNumber,
+        end: BlockNumber,
+    ) -> anyhow::Result<Option<Vec<(H256, BlockHeader)>>> {
+        self.download_session(
+            fork_choice_graph,
+            prev_progress_header,
+            start,
+            end,
+            &mut Vec::new(),
+        )
+        .await
+        .map(|(headers, _)| headers)
+    }
+
+    /// Downloads the saved headers of `range` again and verifies them on top of the saved
+    /// parent of `range.start`, without saving anything or touching the stage progress.
+    ///
+    /// Headers are only taken from peers, so neither [`HeaderDownloadOptions::spill`] nor
+    /// [`HeaderDownloadOptions::header_file`] may be set.
+    pub async fn dry_run<K: TransactionKind, E: EnvironmentKind>(
+        &self,
+        txn: &MdbxTransaction<'_, K, E>,
+        range: BlockNumberRange,
+    ) -> anyhow::Result<DryRunReport> {
+        if self.options.spill.is_some() || self.options.header_file.is_some() {
+            return Err(format_err!(
+                "dry run downloads headers from peers, not from a spill or header file"
+            ));
+        }
+        if range.start == BlockNumber(0) || range.is_empty() {
+            return Err(format_err!(
+                "cannot dry run #{}..=#{}: range must be non-empty and above genesis",
+                range.start,
+                range.end
+            ));
+        }
+        let slice_size = NonZeroU64::new(self.options.upper_bound.0)
+            .ok_or_else(|| format_err!("upper bound must be non-zero"))?;
+
+        self.node
+            .wait_for_peers(self.options.min_peers, Self::PEER_WAIT_TIMEOUT)
+            .await?;
+
+        let mut report = DryRunReport {
+            range,
+            verified: 0,
+            rejections: Vec::new(),
+            unattached: Vec::new(),
+        };
+        let mut last_verified = None;
+        for slice in range.iter_slices(slice_size) {
+            // Slices are anchored at the saved chain wherever it exists, so that one rejected
+            // header does not keep the rest of the range from being checked.
+            let parent_number = BlockNumber(slice.start.0 - 1);
+            let saved_parent = match txn.get(tables::CanonicalHeader, parent_number)? {
+                Some(hash) => txn.get(tables::Header, (parent_number, hash))?,
+                None => None,
+            };
+            let Some(parent) = saved_parent.or_else(|| {
+                last_verified
+                    .take()
+                    .filter(|header: &BlockHeader| header.number == parent_number)
+            }) else {
+                if slice.start == range.start {
+                    return Err(format_err!(
+                        "no saved header #{parent_number} to start from"
+                    ));
+                }
+                warn!("No verified parent for #{}, ending dry run", slice.start);
+                break;
+            };
+
+            let (headers, _) = self
+                .download_session(
+                    Default::default(),
+                    &parent,
+                    slice.start,
+                    slice.end,
+                    &mut report.rejections,
+                )
+                .await?;
+            match headers {
+                Some(mut headers) => {
+                    report.verified += headers.len();
+                    last_verified = headers.pop().map(|(_, header)| header);
+                }
+                None => report.unattached.push(slice),
+            }
+        }
+
+        info!("{report}");
+        Ok(report)
+    }
+
+    /// Same as [`HeaderDownload::download_headers`], also returning where the chain ended if
+    /// the last slice was answered short, see [`HeaderDownloadOptions::partial_final_slice`].
+    /// Headers failing verification are added to `rejections`.
+    #[instrument(level = "debug", skip_all, fields(start = %start, end = %end))]
+    async fn download_session(
+        &self,
+        fork_choice_graph: Arc<Mutex<ForkChoiceGraph>>,
+        prev_progress_header: &BlockHeader,
+        start: BlockNumber,
+        end: BlockNumber,
+        rejections: &mut Vec<HeaderRejection>,
+    ) -> anyhow::Result<(Option<Vec<(H256, BlockHeader)>>, Option<BlockNumber>)> {
+        let mut session = SessionState::new(
+            self,
+            Self::prepare_requests(start, end),
+            fork_choice_graph.clone(),
+        );
+        // Only a slice reaching the known tip may run past the end of the chain.
+        if self.options.partial_final_slice && end >= self.node.chain_tip.borrow().0 {
+            session.final_slice = session.requests.iter().map(|entry| *entry.key()).max();
+        }
+        let SessionState {
+            requests,
+            request_ids,
+            peer_map,
+            bytes_downloaded,
+            slices,
+            final_slice,
+            ..
+        } = session.clone();
+        let aborted = Arc::new(Mutex::new(None));
+        let started_at = Instant::now();
+
+        if let Some(spill) = &self.options.spill {
+            match spill.load() {
+                Ok(segments) => {
+                    let rehydrated =
+                        Self::fill_requests(segments, &requests, &fork_choice_graph, &slices);
+                    if rehydrated > 0 {
+                        info!("Took {rehydrated} headers from the spill");
+                    }
+                }
+                Err(e) => warn!("Failed to read header spill: {e}"),
+            }
+        }
+
+        if let Some(header_file) = &self.options.header_file {
+            match header_file.read(start, end) {
+                Ok(headers) => {
+                    let segments = Self::split_for_requests(&requests, headers);
+                    let filled =
+                        Self::fill_requests(segments, &requests, &fork_choice_graph, &slices);
+                    if filled > 0 {
+                        info!("Took {filled} headers from the header file");
+                    }
+                }
+                Err(e) => warn!("Failed to read header file: {e}"),
+            }
+        }
+
+        info!(
+            "Will download {} headers over {} requests",
+            end - start + 1,
+            requests.len()
+        );
+
+        self.update_frontier(|frontier| {
+            frontier.min_block = start;
+            frontier.max_block = end;
+        });
+
+        let mut stream = self.node.stream_headers().await;
+        if self.options.skeleton {
+            slices.set_skeleton(self.download_skeleton(&mut stream, start, end).await?);
+        }
+
+        let is_bounded = |block_number: BlockNumber| block_number >= start && block_number <= end;
+        let total_requests = requests.len();
+        let remaining_blocks = |requests: &DashMap<BlockNumber, HeaderRequest>| {
+            requests.iter().map(|entry| entry.limit).sum::<u64>()
+        };
+        let downloaded_blocks = |requests: &DashMap<BlockNumber, HeaderRequest>| {
+            (end.0 - start.0 + 1).saturating_sub(remaining_blocks(requests))
+        };
+        let mut prev_downloaded = downloaded_blocks(&requests);
+        let mut blocks_per_sec = None;
+        let min_block_num = |requests: &DashMap<BlockNumber, HeaderRequest>| {
+            requests.iter().map(|entry| *entry.key()).min()
+        };
+        let mut stall = self
+            .options
+            .stall_timeout
+            .map(|timeout| (min_block_num(&requests), Instant::now() + timeout));
+        // Progress is reported once a response has been handled, with the number of requests
+        // still pending right after.
+        let (handled_sender, mut handled) = mpsc::unbounded_channel();
+        let mut last_reported = None;
+        let mut report_progress = |pending_requests: usize| {
+            let downloaded = downloaded_blocks(&requests);
+            blocks_per_sec = self
+                .download_rate
+                .record_at(Instant::now(), downloaded.saturating_sub(prev_downloaded));
+            prev_downloaded = downloaded;
+
+            if let Some(progress_sender) = &self.options.progress_sender {
+                let target = std::cmp::min(self.options.max_block, self.node.chain_tip.borrow().0);
+                let remaining = target
+                    .0
+                    .saturating_add(1)
+                    .saturating_sub(start.0 + downloaded);
+                let _ = progress_sender.send(HeaderDownloadProgress {
+                    start,
+                    end,
+                    total_requests,
+                    pending_requests,
+                    blocks_per_sec,
+                    eta: blocks_per_sec.and_then(|blocks_per_sec| {
+                        DownloadRateEstimator::eta(blocks_per_sec, remaining)
+                    }),
+                    buffered_bytes: fork_choice_graph.lock().memory_usage(),
+                    bytes_downloaded: bytes_downloaded.load(Ordering::Relaxed),
+                    elapsed: started_at.elapsed(),
+                });
+            }
+        };
+
+        {
+            let mut tasks = Vec::new();
+
+            let _g = TaskGuard(tokio::task::spawn({
+                let node = self.node.clone();
+                let requests = requests.clone();
+                let request_ids = request_ids.clone();
+                let retry_backoff = self.options.retry_backoff;
+                let max_in_flight_requests = self.options.max_in_flight_requests;
+                let max_peers = self.options.max_peers;
+                let pause = self.options.pause.clone();
+                let slice_audit = self.options.slice_audit.clone();
+
+                async move {
+                    let mut attempts = RetryAttempts::new(remaining_blocks(&requests));
+                    let mut peers = Vec::new();
+                    loop {
+                        if !node.is_sentry_connected() {
+                            info!(
+                                target: REQUESTS_TARGET,
+                                "Lost connection to sentries, pausing header requests"
+                            );
+                            node.wait_for_sentry_connection().await;
+                            attempts.reset();
+                        }
+
+                        if pause.is_paused() {
+                            info!(target: REQUESTS_TARGET, "Header download paused");
+                            pause.wait_resumed().await;
+                            info!(target: REQUESTS_TARGET, "Header download resumed");
+                            attempts.reset();
+                        }
+
+                        // Forget ids of requests that are already answered.
+                        request_ids.retain(|_, start| requests.contains_key(start));
+
+                        let reqs = Self::next_requests(&requests, max_in_flight_requests)
+                            .into_iter()
+                            .map(|request| {
+                                let request_id = rand::thread_rng().gen::<RequestId>();
+                                if let BlockId::Number(start) = request.start {
+                                    request_ids.insert(request_id, start);
+                                    if let Some(slice_audit) = &slice_audit {
+                                        slice_audit.record(
+                                            BlockNumberRange::new(
+                                                start,
+                                                start + request.limit.saturating_sub(1),
+                                            ),
+                                            SliceStatus::Requested,
+                                        );
+                                    }
+                                }
+                                (request_id, request)
+                            })
+                            .collect::<Vec<_>>();
+                        let sent = if let Some(max_peers) = max_peers {
+                            node.send_header_requests_to_peers(reqs, &mut peers, max_peers)
+                                .await
+                        } else {
+                            node.clone().send_many_header_requests(reqs).await
+                        };
+                        let sent = sent.unwrap_or_else(|e| {
+                            // Our sentry is stuck, not the peers: nobody is penalized and the
+                            // requests stay pending until the next round.
+                            warn!(target: REQUESTS_TARGET, "Failed to send header requests: {e}");
+                            HashSet::new()
+                        });
+
+                        // Back off from about as long as the peers asked usually take to answer.
+                        let delay = RetryBackoff {
+                            base: node.peer_counters.response_timeout(
+                                sent.iter().map(|&(_, peer_id)| peer_id),
+                                retry_backoff.base,
+                            ),
+                            ..retry_backoff
+                        }
+                        .delay(attempts.attempt, &mut rand::thread_rng());
+                        debug!(
+                            target: REQUESTS_TARGET,
+                            "Will re-send pending header requests in {delay:?}"
+                        );
+                        tokio::time::sleep(delay).await;
+
+                        if attempts.advance(remaining_blocks(&requests), retry_backoff.max_attempts)
+                        {
+                            let unanswered = sent
+                                .iter()
+                                .filter(|(request_id, _)| {
+                                    request_ids
+                                        .get(request_id)
+                                        .map_or(false, |start| requests.contains_key(&*start))
+                                })
+                                .map(|&(_, peer_id)| peer_id)
+                                .collect::<HashSet<_>>();
+                            warn!(
+                                target: REQUESTS_TARGET,
+                                "Header requests went unanswered for too long, penalizing {} peers",
+                                unanswered.len()
+                            );
+                            for peer_id in unanswered {
+                                node.penalize_peer(peer_id, PenaltyReason::Timeout).await;
+                            }
+                        }
+                    }
+                }
+            }));
+
+            while !requests.is_empty() {
+                let aborted_by = *aborted.lock();
+                if let Some(class) = aborted_by {
+                    return Err(class.abort_error());
+                }
+
+                if let (Some(timeout), Some((stalled_at, deadline))) =
+                    (self.options.stall_timeout, &mut stall)
+                {
+                    let current = min_block_num(&requests);
+                    if current > *stalled_at {
+                        *stalled_at = current;
+                        *deadline = Instant::now() + timeout;
+                    } else if Instant::now() >= *deadline {
+                        return Err(StalledNoProgress {
+                            block: stalled_at.unwrap_or(end),
+                            peers: self.node.total_peers().await,
+                            stalled_for: timeout,
+                        }
+                        .into());
+                    }
+                }
+
+                if self.options.pause.is_paused() {
+                    self.options.pause.wait_resumed().await;
+                    // Time spent paused is not a stall.
+                    if let (Some(timeout), Some((_, deadline))) =
+                        (self.options.stall_timeout, &mut stall)
+                    {
+                        *deadline = Instant::now() + timeout;
+                    }
+                }
+
+                let next_msg = async {
+                    if let Some((_, deadline)) = stall {
+                        tokio::time::timeout_at(deadline, stream.next()).await.ok()
+                    } else {
+                        Some(stream.next().await)
+                    }
+                };
+                let msg = tokio::select! {
+                    msg = next_msg => {
+                        let Some(msg) = msg else {
+                            continue;
+                        };
+                        msg
+                    }
+                    Some(pending_requests) = handled.recv() => {
+                        last_reported = Some(pending_requests);
+                        report_progress(pending_requests);
+                        continue;
+                    }
+                };
+
+                if let Some(msg) = msg {
+                    let peer_id = msg.peer_id;
+
+                    if !self.node.reputation.is_reputable(peer_id) {
+                        debug!(
+                            target: RESPONSES_TARGET,
+                            "Ignoring message from disreputable peer {peer_id}"
+                        );
+                        continue;
+                    }
+
+                    if let Message::BlockHeaders(inner) = msg.msg {
+                        if inner.headers.is_empty() {
+                            continue;
+                        }
+
+                        info!(
+                            target: RESPONSES_TARGET,
+                            "Received {} headers from peer {peer_id}",
+                            inner.headers.len()
+                        );
+
+                        if is_bounded(inner.headers[0].number) {
+                            tasks.push(TaskGuard(tokio::task::spawn({
+                                let session = session.clone();
+                                let aborted = aborted.clone();
+                                let handled_sender = handled_sender.clone();
+
+                                async move {
+                                    if let Err(class) =
+                                        Self::handle_response(&session, peer_id, inner).await
+                                    {
+                                        *aborted.lock() = Some(class);
+                                    }
+                                    let _ = handled_sender.send(session.requests.len());
+                                }
+                            })));
+                        }
+                    }
+                }
+            }
+
+            // The response completing the download may not have been reported before the
+            // loop noticed.
+            if last_reported != Some(0) {
+                report_progress(0);
+            }
+        }
+
+        info!(
+            "Downloaded {} of headers in {:?}, {} buffered{}",
+            bytesize::ByteSize::b(bytes_downloaded.load(Ordering::Relaxed)),
+            started_at.elapsed(),
+            bytesize::ByteSize::b(fork_choice_graph.lock().memory_usage() as u64),
+            blocks_per_sec
+                .map(|blocks_per_sec| format!(", at {blocks_per_sec:.0} blocks/s"))
+                .unwrap_or_default()
+        );
+
+        let chain_end = final_slice
+            .and_then(|key| slices.end_of(key))
+            .filter(|&last| last < end);
+
+        let took = Instant::now();
+        let mut headers = {
+            let mut graph = fork_choice_graph.lock();
+            let tail = if let Some(v) = graph.chain_head() {
+                v
+            } else {
+                info!("Difficulty graph failure, will unwind");
+                return Ok((None, chain_end));
+            };
+            let headers = graph.backtrack(&tail);
+            // Whatever is left below the head are forks that lost, the next session starts above it.
+            if let Some((_, last)) = headers.last() {
+                graph.prune_below(last.number + 1);
+            }
+            headers
+        };
+
+        if let Some(first) = headers.first() {
+            if prev_progress_header.hash() != first.1.parent_hash {
+                return Ok((None, chain_end));
+            }
+        }
+
+        info!(
+            "Built canonical chain with={} headers, elapsed={:?}",
+            headers.len(),
+            took.elapsed()
+        );
+
+        if let Some(trusted_till) = self.trusted_till() {
+            if prev_progress_header.number < trusted_till {
+                warn!(
+                    "Trusted checkpoints enabled: not verifying consensus rules of headers up to #{trusted_till}"
+                );
+            }
+        }
+
+        let cur_size = headers.len();
+        let took = Instant::now();
+
+        if let Err((last_valid, invalid_hash, class)) =
+            self.validate_sequentially(prev_progress_header, &headers)
+        {
+            let peer_id = peer_map.get(&invalid_hash).map(|e| *e);
+            rejections.extend(Self::rejection(&headers, invalid_hash, class, peer_id));
+            headers.truncate(last_valid);
+
+            Self::on_fault(&self.node, self.options.fault_policy, class, peer_id)
+                .await
+                .map_err(FaultClass::abort_error)?;
+        }
+
+        if self.consensus.needs_parallel_validation() {
+            if let Err((last_valid, invalid_hash)) = self.validate_parallel(&headers) {
+                let peer_id = peer_map.get(&invalid_hash).map(|e| *e);
+                rejections.extend(Self::rejection(
+                    &headers,
+                    invalid_hash,
+                    FaultClass::InvalidSeal,
+                    peer_id,
+                ));
+                headers.truncate(last_valid);
+
+                Self::on_fault(
+                    &self.node,
+                    self.options.fault_policy,
+                    FaultClass::InvalidSeal,
+                    peer_id,
+                )
+                .await
+                .map_err(FaultClass::abort_error)?;
+            }
+        }
+
+        if cur_size == headers.len() {
+            info!(
+                target: VERIFICATION_TARGET,
+                "Seal verification took={:?} all headers are valid.",
+                took.elapsed()
+            );
+        } else {
+            info!(
+                target: VERIFICATION_TARGET,
+                "Seal verification took={:?} {} headers are invalidated.",
+                took.elapsed(),
+                cur_size - headers.len()
+            );
+        }
+
+        if let (Some(slice_audit), Some((_, last))) = (&self.options.slice_audit, headers.last()) {
+            for slice in BlockNumberRange::new(start, end).iter_slices(SLICE_SIZE) {
+                if slice.end <= last.number || chain_end == Some(last.number) {
+                    slice_audit.record(slice, SliceStatus::Verified);
+                }
+            }
+        }
+
+        Ok((Some(headers), chain_end))
+    }
+
+    #[instrument(
+        level = "debug",
+        skip_all,
+        fields(
+            peer_id = %peer_id,
+            first_block = ?response.headers.first().map(|header| header.number),
+            len = response.headers.len(),
+        )
+    )]
+    async fn handle_response(
+        session: &SessionState,
+        peer_id: H512,
+        response: BlockHeaders,
+    ) -> Result<(), FaultClass> {
+        let SessionState {
+            node,
+            requests,
+            request_ids,
+            graph,
+            peer_map,
+            bytes_downloaded,
+            slices,
+            final_slice,
+            fault_policy,
+            spill,
+            size_limits,
+            slice_audit,
+        } = session;
+        let cur_size = response.headers.len();
+        debug!(
+            target: RESPONSES_TARGET,
+            "Handling response from {peer_id} with {cur_size} headers"
+        );
+
+        let bytes = response
+            .headers
+            .iter()
+            .map(|header| fastrlp::Encodable::length(header) as u64)
+            .sum();
+        bytes_downloaded.fetch_add(bytes, Ordering::Relaxed);
+
+        if let Err(e) = size_limits.check(&response.headers) {
+            warn!(
+                target: RESPONSES_TARGET,
+                "Rejected oversized response from {peer_id}: {e}"
+            );
+            return Self::on_fault(
+                node,
+                *fault_policy,
+                FaultClass::MalformedResponse,
+                Some(peer_id),
+            )
+            .await;
+        }
+
+        let request_id = response.request_id;
+        match Self::check_contiguous(response.headers) {
+            Ok(headers) => {
+                // A consistent segment shifted off the requested range would only fail to link
+                // up much later, so every header must be where the request asked for it.
+                if let Some(requested) = request_ids.get(&request_id).map(|entry| *entry) {
+                    if let Err(e) = Self::check_numbers(&headers, requested) {
+                        warn!(
+                            target: RESPONSES_TARGET,
+                            "Rejected header segment from {peer_id} for #{requested}: {e}"
+                        );
+                        return Self::on_fault(
+                            node,
+                            *fault_policy,
+                            FaultClass::MalformedResponse,
+                            Some(peer_id),
+                        )
+                        .await;
+                    }
+                }
+
+                let first = headers[0].1.number;
+                let Some(key) = Self::request_key(request_ids, request_id, first) else {
+                    warn!(
+                        target: RESPONSES_TARGET,
+                        "Rejected header segment from {peer_id} for another request"
+                    );
+                    return Self::on_fault(
+                        node,
+                        *fault_policy,
+                        FaultClass::MalformedResponse,
+                        Some(peer_id),
+                    )
+                    .await;
+                };
+                let last_hash = headers[headers.len() - 1].0;
+                let mut complete = None;
+                let mut mismatched = Vec::new();
+                let mut contradicted = None;
+
+                let oversized = {
+                    let mut graph = graph.lock();
+
+                    if let dashmap::mapref::entry::Entry::Occupied(entry) = requests.entry(key) {
+                        let limit = entry.get().limit as usize;
+                        // A short final slice means the chain ends there, see
+                        // `HeaderDownloadOptions::partial_final_slice`.
+                        let accepted = headers.len() == limit
+                            || (headers.len() < limit && *final_slice == Some(key));
+
+                        if accepted {
+                            let end = headers[headers.len() - 1].1.number;
+                            if let Err(skeleton_peer) = slices.check_skeleton(end, last_hash) {
+                                contradicted = Some(skeleton_peer);
+                            } else {
+                                mismatched = slices.link(
+                                    key,
+                                    SliceBoundary {
+                                        end,
+                                        parent_hash: headers[0].1.parent_hash,
+                                        last_hash,
+                                        peer_id: Some(peer_id),
+                                    },
+                                );
+                            }
+                        }
+
+                        if contradicted.is_some() {
+                            // Request stays pending and will be re-sent.
+                            false
+                        } else if !mismatched.is_empty() {
+                            // Request stays pending and will be re-sent, as will the
+                            // neighbours it does not link up with.
+                            drop(entry);
+                            Self::requeue_slices(requests, &mismatched);
+                            false
+                        } else if accepted {
+                            entry.remove();
+                            if let Some(slice_audit) = slice_audit {
+                                slice_audit.record(
+                                    BlockNumberRange::new(key, key + (limit as u64 - 1)),
+                                    SliceStatus::Received,
+                                );
+                            }
+
+                            if headers.len() < limit {
+                                debug!(
+                                    target: RESPONSES_TARGET,
+                                    "Final segment from {peer_id} for #{key} ends at #{}: {} of {limit} headers",
+                                    headers[headers.len() - 1].1.number,
+                                    headers.len()
+                                );
+                            }
+
+                            if spill.is_some() {
+                                complete = Some(
+                                    headers
+                                        .iter()
+                                        .map(|(_, header)| header.clone())
+                                        .collect::<Vec<_>>(),
+                                );
+                            }
+                            for (hash, header) in headers {
+                                graph.insert_with_hash(hash, header);
+                                peer_map.insert(hash, peer_id);
+                            }
+                            false
+                        } else if headers.len() < limit {
+                            // Request stays pending and will be re-sent.
+                            debug!(
+                                target: RESPONSES_TARGET,
+                                "Dropping short response from {peer_id} for #{key}: {} of {limit} headers",
+                                headers.len()
+                            );
+                            false
+                        } else {
+                            true
+                        }
+                    } else {
+                        if !graph.contains(last_hash) {
+                            for (hash, header) in headers {
+                                graph.insert_with_hash(hash, header);
+                                peer_map.insert(hash, peer_id);
+                            }
+                        }
+                        false
+                    }
+                };
+
+                if let (Some(spill), Some(headers)) = (spill, complete) {
+                    if let Err(e) = spill.append(headers) {
+                        warn!("Failed to spill header segment for #{key}: {e}");
+                    }
+                }
+
+                if let Some(skeleton_peer) = contradicted {
+                    if skeleton_peer == peer_id {
+                        warn!(
+                            target: RESPONSES_TARGET,
+                            "Rejected header segment from {peer_id} for #{key}: does not match its own skeleton"
+                        );
+                        return Self::on_fault(
+                            node,
+                            *fault_policy,
+                            FaultClass::MalformedResponse,
+                            Some(peer_id),
+                        )
+                        .await;
+                    }
+
+                    debug!(
+                        target: RESPONSES_TARGET,
+                        "Header segment from {peer_id} for #{key} does not match the skeleton from {skeleton_peer}, re-requesting"
+                    );
+                }
+
+                if !mismatched.is_empty() {
+                    // Two answers from the same peer that do not link up cannot both be right.
+                    if mismatched
+                        .iter()
+                        .any(|(_, boundary)| boundary.peer_id == Some(peer_id))
+                    {
+                        warn!(
+                            target: RESPONSES_TARGET,
+                            "Rejected header segment from {peer_id} for #{key}: does not link up with its own adjacent segment"
+                        );
+                        return Self::on_fault(
+                            node,
+                            *fault_policy,
+                            FaultClass::MalformedResponse,
+                            Some(peer_id),
+                        )
+                        .await;
+                    }
+
+                    debug!(
+                        target: RESPONSES_TARGET,
+                        "Header segment from {peer_id} for #{key} does not link up with an adjacent segment, re-requesting both"
+                    );
+                }
+
+                if oversized {
+                    warn!(
+                        target: RESPONSES_TARGET,
+                        "Rejected oversized header segment from {peer_id} for #{key}"
+                    );
+                    return Self::on_fault(
+                        node,
+                        *fault_policy,
+                        FaultClass::MalformedResponse,
+                        Some(peer_id),
+                    )
+                    .await;
+                }
+
+                node.peer_counters.record_response(peer_id, bytes);
+                node.peer_counters.record_answer(peer_id, request_id);
+                Ok(())
+            }
+            Err(()) => {
+                warn!(
+                    target: RESPONSES_TARGET,
+                    "Rejected discontiguous header segment from {peer_id}"
+                );
+                Self::on_fault(
+                    node,
+                    *fault_policy,
+                    FaultClass::MalformedResponse,
+                    Some(peer_id),
+                )
+                .await
+            }
+        }
+    }
+
+    /// Answers pending requests with local segments of the exact requested length, putting
+    /// them into the graph. Returns the number of headers taken.
+    fn fill_requests(
+        segments: Vec<Vec<BlockHeader>>,
+        requests: &DashMap<BlockNumber, HeaderRequest>,
+        graph: &Mutex<ForkChoiceGraph>,
+        slices: &SliceBoundaries,
+    ) -> usize {
+        let mut graph = graph.lock();
+        let mut filled = 0;
+        for segment in segments {
+            let Ok(headers) = Self::check_contiguous(segment) else {
+                continue;
+            };
+            let Some((_, first)) = headers.first() else {
+                continue;
+            };
+
+            if let dashmap::mapref::entry::Entry::Occupied(entry) = requests.entry(first.number) {
+                if entry.get().limit as usize == headers.len() {
+                    let mismatched = slices.link(
+                        first.number,
+                        SliceBoundary {
+                            end: headers[headers.len() - 1].1.number,
+                            parent_hash: first.parent_hash,
+                            last_hash: headers[headers.len() - 1].0,
+                            peer_id: None,
+                        },
+                    );
+                    if !mismatched.is_empty() {
+                        drop(entry);
+                        Self::requeue_slices(requests, &mismatched);
+                        continue;
+                    }
+
+                    entry.remove();
+
+                    filled += headers.len();
+                    for (hash, header) in headers {
+                        graph.insert_with_hash(hash, header);
+                    }
+                }
+            }
+        }
+
+        filled
+    }
+
+    /// Puts the requests for forgotten slices back into the pending set.
+    fn requeue_slices(
+        requests: &DashMap<BlockNumber, HeaderRequest>,
+        slices: &[(BlockNumber, SliceBoundary)],
+    ) {
+        for &(start, boundary) in slices {
+            requests.insert(
+                start,
+                HeaderRequest {
+                    start: BlockId::Number(start),
+                    limit: BlockNumberRange::new(start, boundary.end).len(),
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    /// Cuts consecutive headers into segments lined up with pending requests.
+    fn split_for_requests(
+        requests: &DashMap<BlockNumber, HeaderRequest>,
+        headers: Vec<BlockHeader>,
+    ) -> Vec<Vec<BlockHeader>> {
+        let Some(first) = headers.first().map(|header| header.number) else {
+            return vec![];
+        };
+
+        requests
+            .iter()
+            .filter_map(|entry| {
+                let offset = entry.key().checked_sub(first.0)?.0 as usize;
+                headers
+                    .get(offset..offset + entry.value().limit as usize)
+                    .map(<[_]>::to_vec)
+            })
+            .collect()
+    }
+
+    fn clear_spill(&self) {
+        if let Some(spill) = &self.options.spill {
+            if let Err(e) = spill.clear() {
+                warn!("Failed to clear header spill: {e}");
+            }
+        }
+    }
+
+    /// Describes the rejected header `hash` among `headers`.
+    fn rejection(
+        headers: &[(H256, BlockHeader)],
+        hash: H256,
+        class: FaultClass,
+        peer: Option<H512>,
+    ) -> Option<HeaderRejection> {
+        headers
+            .iter()
+            .find(|(h, _)| *h == hash)
+            .map(|(_, header)| HeaderRejection {
+                number: header.number,
+                hash,
+                class,
+                peer,
+            })
+    }
+
+    /// Reacts to a fault blamed on `peers` according to `policy`. Returns the fault back if
+    /// the download should be aborted.
+    async fn on_fault(
+        node: &Node,
+        policy: FaultPolicy,
+        class: FaultClass,
+        peers: impl IntoIterator<Item = H512>,
+    ) -> Result<(), FaultClass> {
+        let peers = peers.into_iter().collect::<Vec<_>>();
+        if class == FaultClass::Timeout {
+            for &peer_id in &peers {
+                node.peer_counters.record_timeout(peer_id);
+            }
+        }
+
+        match policy.action(class) {
+            FaultAction::Retry => Ok(()),
+            FaultAction::PenalizeAndRetry => {
+                for peer_id in peers {
+                    node.penalize_peer(peer_id, class.penalty()).await;
+                }
+                Ok(())
+            }
+            FaultAction::Abort => Err(class),
+        }
+    }
+
+    fn update_frontier(&self, update: impl FnOnce(&mut HeaderDownloadFrontier)) {
+        if let Some(frontier_sender) = &self.frontier_sender {
+            let mut frontier = *frontier_sender.borrow();
+            update(&mut frontier);
+            frontier_sender.send_replace(frontier);
+        }
+    }
+
+    /// Classifies a failure of [`HeaderDownload::save_headers`]. The database being busy or
+    /// out of reader slots is [`StageError::Recoverable`], as it goes away by itself, while
+    /// anything else fails the stage.
+    fn save_error(e: anyhow::Error) -> StageError {
+        match e.downcast_ref::<::mdbx::Error>() {
+            Some(::mdbx::Error::Busy | ::mdbx::Error::ReadersFull) => StageError::Recoverable(e),
+            _ => StageError::Internal(e),
+        }
+    }
+
+    /// Writes headers descending from `parent` as the canonical chain, along with their
+    /// total difficulty. Returns the number of the last written header.
+    #[instrument(level = "debug", skip_all, fields(parent = %parent))]
+    fn save_headers<E: EnvironmentKind>(
+        &self,
+        txn: &MdbxTransaction<'_, RW, E>,
+        parent: BlockNumber,
+        parent_hash: H256,
+        headers: impl IntoIterator<Item = (H256, BlockHeader)>,
+    ) -> anyhow::Result<BlockNumber> {
+        assert!(self.options.save_batch > 0, "save batch must be positive");
+
+        let mut cursors = SaveCursors {
+            header_number: txn.cursor(tables::HeaderNumber)?,
+            header: txn.cursor(tables::Header)?,
+            canonical: txn.cursor(tables::CanonicalHeader)?,
+            td: txn.cursor(tables::HeadersTotalDifficulty)?,
+            last_header: None,
+            last_canonical: None,
+            last_td: None,
+        };
+        // Accumulate on top of the parent rather than the last table entry, which may belong to
+        // a fork left behind by an unwind.
+        let mut td = txn
+            .get(tables::HeadersTotalDifficulty, (parent, parent_hash))?
+            .ok_or_else(|| {
+                format_err!("no total difficulty for block #{parent}/{parent_hash:?}")
+            })?;
+
+        // Headers come in ascending order, so most of them can be appended to the end of the
+        // tables. Keys at or below the last one (e.g. stale fork entries) are put normally.
+        cursors.last_header = cursors.header.last()?.map(|(k, _)| k);
+        cursors.last_canonical = cursors.canonical.last()?.map(|(k, _)| k);
+        cursors.last_td = cursors.td.last()?.map(|(k, _)| k);
+
+        let mut batch = SaveBatch::with_capacity(self.options.save_batch);
+        let mut saved = parent;
+        for (hash, header) in headers {
+            if header.number == 0 {
+                continue;
+            }
+            if header.number > self.options.max_block {
+                break;
+            }
+
+            let block_number = header.number;
+            td += header.difficulty;
+            batch.push(hash, header, td);
+            saved = block_number;
+
+            if batch.len() == self.options.save_batch {
+                batch.flush(txn, &mut cursors, self.options.verify_after_write)?;
+            }
+        }
+        batch.flush(txn, &mut cursors, self.options.verify_after_write)?;
+
+        Ok(saved)
+    }
+
+    async fn update_head<'tx, E: EnvironmentKind>(
+        &self,
+        txn: &'tx mut MdbxTransaction<'_, RW, E>,
+        height: BlockNumber,
+    ) -> anyhow::Result<()> {
+        let hash = txn.get(tables::CanonicalHeader, height)?.unwrap();
+        let td = txn
+            .get(tables::HeadersTotalDifficulty, (height, hash))?
+            .unwrap();
+        let status = Status::new(height, hash, td);
+        self.node.update_chain_head(Some(status)).await;
+        Ok(())
+    }
+
+    fn on_fork(
+        &self,
+        prev_progress: BlockNumber,
+        prev_progress_hash: H256,
+    ) -> Result<ExecOutput, StageError> {
+        match self.options.fork_policy {
+            ForkPolicy::FailFast => Err(StageError::Internal(
+                ForkDetected {
+                    number: prev_progress,
+                    hash: prev_progress_hash,
+                }
+                .into(),
+            )),
+            ForkPolicy::ReAnchor { max_rewind } => {
+                let unwind_to = Self::reorg_unwind_point(prev_progress, max_rewind);
+                info!(
+                    "Downloaded headers do not attach to #{prev_progress}, possible reorg, rewinding {} blocks",
+                    prev_progress.0 - unwind_to.0
+                );
+                Ok(ExecOutput::Unwind { unwind_to })
+            }
+        }
+    }
+
+    fn reorg_unwind_point(prev_progress: BlockNumber, rewind_depth: u64) -> BlockNumber {
+        prev_progress.saturating_sub(std::cmp::max(rewind_depth, 1))
+    }
+
+    fn forward_set_target_block(
+        prev_progress: BlockNumber,
+        increment: Option<BlockNumber>,
+        upper_bound: BlockNumber,
+        chain_tip: BlockNumber,
+    ) -> (BlockNumber, bool) {
+        let max_increment = std::cmp::max(
+            BlockNumber(1),
+            increment
+                .map(|v| std::cmp::min(v, upper_bound))
+                .unwrap_or(upper_bound),
+        );
+        let max_incremented_from_start = prev_progress.saturating_add(max_increment.0);
+
+        if max_incremented_from_start > chain_tip {
+            (chain_tip, true)
+        } else {
+            (max_incremented_from_start, false)
+        }
+    }
+
+    /// Request for the last header of every full slice from `starting_block` to `target`, if
+    /// there are any.
+    fn skeleton_request(starting_block: BlockNumber, target: BlockNumber) -> Option<HeaderRequest> {
+        let slice_size = HEADERS_UPPER_BOUND as u64;
+        let full_slices = BlockNumberRange::new(starting_block, target).len() / slice_size;
+        if full_slices == 0 {
+            return None;
+        }
+
+        Some(HeaderRequest {
+            start: BlockId::Number(starting_block + (slice_size - 1)),
+            limit: std::cmp::min(full_slices, slice_size),
+            skip: slice_size - 1,
+            reverse: false,
+        })
+    }
+
+    /// Lines up the answer to a request that may skip headers or go backwards with the
+    /// numbers it asked for. Peers may answer with fewer headers than asked for, but not with
+    /// others.
+    fn place_sparse(
+        request: &HeaderRequest,
+        headers: Vec<BlockHeader>,
+    ) -> Option<Vec<(H256, BlockHeader)>> {
+        let mut numbers = request.block_numbers()?;
+        headers
+            .into_iter()
+            .map(|header| (numbers.next() == Some(header.number)).then(|| (header.hash(), header)))
+            .collect()
+    }
+
+    /// Fetches the skeleton of a forward download session from a single peer, see
+    /// [`HeaderDownloadOptions::skeleton`]. The skeleton is empty if no peer sends one.
+    async fn download_skeleton(
+        &self,
+        stream: &mut NodeStream,
+        start: BlockNumber,
+        end: BlockNumber,
+    ) -> anyhow::Result<BTreeMap<BlockNumber, (H256, H512)>> {
+        let Some(request) = Self::skeleton_request(start, end) else {
+            return Ok(BTreeMap::new());
+        };
+
+        for _ in 0..Self::SKELETON_ATTEMPTS {
+            let request_id = rand::thread_rng().gen::<RequestId>();
+            debug!(
+                target: REQUESTS_TARGET,
+                "Requesting skeleton of {} headers from #{start} to #{end}", request.limit
+            );
+            let sent = match self
+                .node
+                .send_header_request(Some(request_id), request, None)
+                .await
+            {
+                Ok(sent) => sent,
+                Err(e) => {
+                    warn!(target: REQUESTS_TARGET, "Failed to send skeleton request: {e}");
+                    continue;
+                }
+            };
+
+            let timeout = tokio::time::sleep(self.node.peer_counters.response_timeout(
+                sent.iter().map(|&(_, peer_id)| peer_id),
+                self.options.request_timeout,
+            ));
+            tokio::pin!(timeout);
+            let answer = loop {
+                tokio::select! {
+                    msg = stream.next() => {
+                        let Some(msg) = msg else {
+                            break None;
+                        };
+                        if let Message::BlockHeaders(headers) = msg.msg {
+                            if headers.request_id == request_id
+                                && !headers.headers.is_empty()
+                                && self.node.reputation.is_reputable(msg.peer_id)
+                            {
+                                break Some((msg.peer_id, headers.headers));
+                            }
+                        }
+                    }
+                    _ = &mut timeout => {
+                        break None;
+                    }
+                }
+            };
+
+            let Some((peer_id, headers)) = answer else {
+                debug!(target: RESPONSES_TARGET, "No answer to skeleton request");
+                continue;
+            };
+
+            if let Some(skeleton) = Self::place_sparse(&request, headers) {
+                info!(
+                    target: RESPONSES_TARGET,
+                    "Received skeleton of {} headers from peer {peer_id}",
+                    skeleton.len()
+                );
+                return Ok(skeleton
+                    .into_iter()
+                    .map(|(hash, header)| (header.number, (hash, peer_id)))
+                    .collect());
+            }
+
+            warn!(
+                target: RESPONSES_TARGET,
+                "Rejected skeleton from {peer_id} with headers that were not asked for"
+            );
+            Self::on_fault(
+                &self.node,
+                self.options.fault_policy,
+                FaultClass::MalformedResponse,
+                Some(peer_id),
+            )
+            .await
+            .map_err(FaultClass::abort_error)?;
+        }
+
+        warn!("No header skeleton received, downloading without it");
+        Ok(BTreeMap::new())
+    }
+
+    fn prepare_requests(
+        starting_block: BlockNumber,
+        target: BlockNumber,
+    ) -> DashMap<BlockNumber, HeaderRequest> {
+        BlockNumberRange::new(starting_block, target)
+            .iter_slices(SLICE_SIZE)
+            .map(|slice| {
+                let request = HeaderRequest {
+                    start: BlockId::Number(slice.start),
+                    limit: slice.len(),
+                    ..Default::default()
+                };
+
+                (slice.start, request)
+            })
+            .collect()
+    }
+
+    fn next_requests(
+        requests: &DashMap<BlockNumber, HeaderRequest>,
+        limit: usize,
+    ) -> Vec<HeaderRequest> {
+        let mut reqs = requests
+            .iter()
+            .map(|entry_ref| (*entry_ref.key(), *entry_ref.value()))
+            .collect::<Vec<_>>();
+        reqs.sort_unstable_by_key(|(block_number, _)| *block_number);
+        reqs.into_iter()
+            .take(limit)
+            .map(|(_, request)| request)
+            .collect()
+    }
+
+    /// Finds the request a response belongs to by its echoed request id, falling back to
+    /// the first header number for responses to requests this session did not send.
+    fn request_key(
+        request_ids: &DashMap<RequestId, BlockNumber>,
+        request_id: RequestId,
+        first: BlockNumber,
+    ) -> Option<BlockNumber> {
+        match request_ids.get(&request_id).map(|entry| *entry) {
+            Some(start) if start == first => Some(start),
+            Some(_) => None,
+            None => Some(first),
+        }
+    }
+
+    #[inline]
+    fn check_contiguous(headers: Vec<BlockHeader>) -> Result<Vec<(H256, BlockHeader)>, ()> {
+        let headers = headers
+            .into_iter()
+            .map(|h| (h.hash(), h))
+            .collect::<Vec<_>>();
+        if headers.iter().skip(1).enumerate().any(|(i, (_, header))| {
+            header.parent_hash != headers[i].0 || header.number != headers[i].1.number + 1u8
+        }) {
+            return Err(());
+        }
+
+        Ok(headers)
+    }
+
+    /// Checks that the segment starts at `first` and each header declares the number of its
+    /// position.
+    fn check_numbers(headers: &[(H256, BlockHeader)], first: BlockNumber) -> anyhow::Result<()> {
+        for (i, (hash, header)) in headers.iter().enumerate() {
+            let expected = first + i as u64;
+            if header.number != expected {
+                return Err(format_err!(
+                    "header {i} of the segment, {hash:?}, declares #{} instead of #{expected}",
+                    header.number
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn trusted_till(&self) -> Option<BlockNumber> {
+        self.options.trusted_checkpoints.keys().next_back().copied()
+    }
+
+    /// Whether the header is covered by trusted checkpoints and needs no consensus checks.
+    fn is_trusted(&self, number: BlockNumber) -> bool {
+        self.trusted_till()
+            .map_or(false, |trusted_till| number <= trusted_till)
+    }
+
+    /// Checks that `headers` descend from `parent_header` and pass consensus rules, one by
+    /// one. On failure returns how many headers to keep and the hash of the rejected one.
+    ///
+    /// Extra data is checked against the [`ExtraDataFormat`] of the chain first, which needs
+    /// no parent and no seal verification.
+    ///
+    /// Engines pick the rules for each header by its own number, so a batch spanning a fork
+    /// activation (e.g. the difficulty bomb or London) needs no special treatment, and
+    /// batches are not aligned to fork blocks.
+    #[instrument(
+        level = "debug",
+        skip_all,
+        fields(
+            first_block = ?headers.first().map(|(_, header)| header.number),
+            len = headers.len(),
+        )
+    )]
+    fn validate_sequentially<'a>(
+        &self,
+        mut parent_header: &'a BlockHeader,
+        headers: &'a [(H256, BlockHeader)],
+    ) -> Result<(), (usize, H256, FaultClass)> {
+        let verify_seals = self.node.config.consensus_mode() != ConsensusMode::NoSealVerification;
+        for (i, (hash, header)) in headers.iter().enumerate() {
+            let parent_hash = parent_header.hash();
+            if header.parent_hash != parent_hash || header.number != parent_header.number + 1_u8 {
+                warn!(target: VERIFICATION_TARGET, "Rejected bad block header ({hash:?}) because it doesn't attach to parent ({parent_hash:?}): {header:?} => {parent_header:?}");
+                return Err((i.saturating_sub(1), *hash, FaultClass::BadLinkage));
+            }
+
+            if let Some(checkpoint) = self.options.trusted_checkpoints.get(&header.number) {
+                if hash != checkpoint {
+                    warn!(target: VERIFICATION_TARGET, "Rejected bad block header ({hash:?}) because it does not match trusted checkpoint #{}/{checkpoint:?}", header.number);
+                    return Err((i.saturating_sub(1), *hash, FaultClass::BadLinkage));
+                }
+            }
+
+            if self.is_trusted(header.number) {
+                parent_header = header;
+                continue;
+            }
+
+            if let Err(e) = validate_header(
+                &*self.consensus,
+                &self.node.config.chain_spec,
+                verify_seals,
+                header,
+                Some(parent_header),
+            ) {
+                warn!(
+                    target: VERIFICATION_TARGET,
+                    "Rejected bad block header ({hash:?}) for reason {e:?}: {header:?}"
+                );
+                return Err((i.saturating_sub(1), *hash, FaultClass::of_rejection(&e)));
+            }
+            parent_header = header;
+        }
+
+        Ok(())
+    }
+
+    fn validate_parallel(&self, headers: &[(H256, BlockHeader)]) -> Result<(), (usize, H256)> {
+        if self.node.config.consensus_mode() == ConsensusMode::NoSealVerification {
+            return Ok(());
+        }
+
+        let first_invalid = if let Some(seal_cache) = &self.options.seal_cache {
+            let mut offset = 0;
+            let mut first_invalid = None;
+            while offset < headers.len() {
+                let slice_end =
+                    align_block_num_to_slice_start(headers[offset].1.number, SLICE_SIZE)
+                        + SLICE_SIZE.get();
+                let len = headers[offset..]
+                    .iter()
+                    .position(|(_, header)| header.number >= slice_end)
+                    .unwrap_or(headers.len() - offset);
+
+                let slice = &headers[offset..offset + len];
+                if let Some(i) =
+                    seal_cache.get_or_verify(slice, |slice| self.first_invalid_seal(slice))
+                {
+                    first_invalid = Some(offset + i);
+                    break;
+                }
+                offset += len;
+            }
+            first_invalid
+        } else {
+            self.first_invalid_seal(headers)
+        };
+
+        match first_invalid {
+            None => Ok(()),
+            Some(i) => Err((i.saturating_sub(1), headers[i].0)),
+        }
+    }
+
+    #[instrument(
+        level = "debug",
+        skip_all,
+        fields(
+            first_block = ?headers.first().map(|(_, header)| header.number),
+            len = headers.len(),
+        )
+    )]
+    fn first_invalid_seal(&self, headers: &[(H256, BlockHeader)]) -> Option<usize> {
+        let first_invalid = AtomicUsize::new(usize::MAX);
+
+        let validate = || {
+            headers
+                .par_iter()
+                .enumerate()
+                .for_each(|(i, (hash, header))| {
+                    // Everything after the first invalid header is dropped anyway.
+                    if i > first_invalid.load(Ordering::Relaxed) || self.is_trusted(header.number) {
+                        return;
+                    }
+
+                    if let Err(e) = self.consensus.validate_header_parallel(header) {
+                        warn!(
+                            target: VERIFICATION_TARGET,
+                            "Rejected bad block header #{} ({hash:?}) for reason {e:?}",
+                            header.number
+                        );
+                        first_invalid.fetch_min(i, Ordering::SeqCst);
+                    }
+                })
+        };
+        if let Some(pool) = &self.options.verification_pool {
+            pool.install(validate);
+        } else {
+            validate();
+        }
+
+        match first_invalid.into_inner() {
+            usize::MAX => None,
+            i => Some(i),
+        }
+    }
+}
+
+/// Outcome of re-verifying a single saved block number.
+#[derive(Debug)]
+pub enum ReplayedHeader {
+    Valid {
+        number: BlockNumber,
+        hash: H256,
+    },
+    Invalid {
+        number: BlockNumber,
+        hash: H256,
+        error: DuoError,
+    },
+    /// No canonical header is saved for this block number.
+    Missing(BlockNumber),
+}
+
+/// Checks extra data against the [`ExtraDataFormat`] of the chain, then the consensus rules
+/// against `parent`. Without a parent only the extra data is checked.
+fn validate_header(
+    consensus: &dyn Consensus,
+    chain_spec: &ChainSpec,
+    verify_seals: bool,
+    header: &BlockHeader,
+    parent: Option<&BlockHeader>,
+) -> Result<(), DuoError> {
+    let extra_data_format = ExtraDataFormat::for_block(chain_spec, header.number);
+    if verify_seals {
+        extra_data_format.validate(header)?;
+        if let Some(parent) = parent {
+            consensus.validate_block_header(header, parent, true)?;
+        }
+    } else {
+        // Clique extra data carries the seal, so it goes unchecked along with it.
+        match extra_data_format {
+            ExtraDataFormat::Clique { .. } => {}
+            format => format.validate(header)?,
+        }
+        if let Some(parent) = parent {
+            consensus.validate_block_header_without_seal(header, parent, true)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Re-verifies headers that are already saved in the database, e.g. after a fix to the
+/// verification rules, without downloading them again.
+///
+/// Every header goes through the checks of a freshly downloaded one, apart from trusted
+/// checkpoints: size limits, linkage to its parent, extra data, consensus rules, and
+/// parallel consensus checks where the engine has them.
+#[derive(Debug)]
+pub struct HeaderReplayer {
+    pub consensus: Arc<dyn Consensus>,
+    pub config: ChainConfig,
+    pub size_limits: HeaderSizeLimits,
+}
+
+impl HeaderReplayer {
+    /// Yields one result per block number in `range`. The header after a missing one has
+    /// no parent to check against, so only its parent-independent checks are run.
+    pub fn replay<'db, 'tx, K: TransactionKind, E: EnvironmentKind>(
+        &'tx self,
+        tx: &'tx MdbxTransaction<'db, K, E>,
+        range: BlockNumberRange,
+    ) -> impl Iterator<Item = anyhow::Result<ReplayedHeader>> + 'tx
+    where
+        'db: 'tx,
+    {
+        TryGenIter::from(move || {
+            let mut parent = match range.start.checked_sub(1) {
+                Some(number) => match chain::canonical_hash::read(tx, number)? {
+                    Some(hash) => chain::header::read(tx, hash, number)?,
+                    None => None,
+                },
+                None => None,
+            };
+
+            let mut next = Some(range.start);
+            for res in chain::header::walk_range(tx, range.start, range.end, true) {
+                let (number, header) = res?;
+                while let Some(missing) = next.filter(|&n| n < number) {
+                    parent = None;
+                    next = missing.0.checked_add(1).map(BlockNumber);
+                    yield ReplayedHeader::Missing(missing);
+                }
+                next = number.0.checked_add(1).map(BlockNumber);
+
+                let hash = header.hash();
+                yield match self.verify(&header, parent.as_ref()) {
+                    Ok(()) => ReplayedHeader::Valid { number, hash },
+                    Err(error) => ReplayedHeader::Invalid {
+                        number,
+                        hash,
+                        error,
+                    },
+                };
+                parent = Some(header);
+            }
+            while let Some(missing) = next.filter(|&n| n <= range.end) {
+                next = missing.0.checked_add(1).map(BlockNumber);
+                yield ReplayedHeader::Missing(missing);
+            }
+
+            Ok(())
+        })
+    }
+
+    fn verify(&self, header: &BlockHeader, parent: Option<&BlockHeader>) -> Result<(), DuoError> {
+        self.size_limits
+            .check(std::slice::from_ref(header))
+            .map_err(DuoError::Internal)?;
+
+        if let Some(parent) = parent {
+            if header.parent_hash != parent.hash() {
+                return Err(ValidationError::UnknownParent {
+                    number: header.number,
+                    parent_hash: header.parent_hash,
+                }
+                .into());
+            }
+        }
+
+        validate_header(
+            &*self.consensus,
+            &self.config.chain_spec,
+            self.config.consensus_mode() != ConsensusMode::NoSealVerification,
+            header,
+            parent,
+        )?;
+
+        if self.consensus.needs_parallel_validation() {
+            self.consensus.validate_header_parallel(header)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Output format of [`export_headers`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeaderExportFormat {
+    /// Concatenated RLP-encoded headers, readable as a [`HeaderFile`].
+    Rlp,
+    /// One JSON object per line, with the hash and fields of a header.
+    Json,
+}
+
+impl FromStr for HeaderExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rlp" => Ok(Self::Rlp),
+            "json" => Ok(Self::Json),
+            other => Err(format_err!(
+                "unknown header export format {other:?}, expected rlp or json"
+            )),
+        }
+    }
+}
+
+/// Writes the canonical headers in `range` to `writer` in ascending order, and returns how
+/// many were written. A block without a saved canonical header fails the export, unless
+/// `skip_gaps` is set, in which case it is left out.
+pub fn export_headers<K: TransactionKind, E: EnvironmentKind>(
+    tx: &MdbxTransaction<'_, K, E>,
+    range: BlockNumberRange,
+    format: HeaderExportFormat,
+    skip_gaps: bool,
+    mut writer: impl Write,
+) -> anyhow::Result<u64> {
+    let missing = |number, exported| {
+        format_err!(
+            "no canonical header saved for block #{number}, after exporting {exported} headers"
+        )
+    };
+
+    let mut exported = 0;
+    let mut next = Some(range.start);
+    let mut data = Vec::new();
+    // Gaps are skipped by the walk and found here, to report how far the export got.
+    for entry in chain::header::walk_range(tx, range.start, range.end, true) {
+        let (number, header) = entry?;
+        if let Some(expected) = next.filter(|expected| !skip_gaps && *expected != number) {
+            return Err(missing(expected, exported));
+        }
+        next = number.0.checked_add(1).map(BlockNumber);
+        let hash = header.hash();
+
+        match format {
+            HeaderExportFormat::Rlp => {
+                data.clear();
+                fastrlp::Encodable::encode(&header, &mut data);
+                writer.write_all(&data)?;
+            }
+            HeaderExportFormat::Json => {
+                serde_json::to_writer(
+                    &mut writer,
+                    &serde_json::json!({
+                        "hash": hash,
+                        "parentHash": header.parent_hash,
+                        "sha3Uncles": header.ommers_hash,
+                        "miner": header.beneficiary,
+                        "stateRoot": header.state_root,
+                        "transactionsRoot": header.transactions_root,
+                        "receiptsRoot": header.receipts_root,
+                        "logsBloom": header.logs_bloom,
+                        "difficulty": header.difficulty,
+                        "number": header.number,
+                        "gasLimit": header.gas_limit,
+                        "gasUsed": header.gas_used,
+                        "timestamp": header.timestamp,
+                        "extraData": format!("0x{}", hex::encode(&header.extra_data)),
+                        "mixHash": header.mix_hash,
+                        "nonce": header.nonce,
+                        "baseFeePerGas": header.base_fee_per_gas,
+                    }),
+                )?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        exported += 1;
+    }
+    if let Some(expected) = next.filter(|expected| !skip_gaps && *expected <= range.end) {
+        return Err(missing(expected, exported));
+    }
+    writer.flush()?;
+
+    Ok(exported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        accessors::chain::td,
+        consensus::{engine_factory, ConsensusEngineBase, FinalizationChange},
+        models::{ChainId, U256},
+        p2p::node::mock::MockSentry,
+        res::chainspec::{GOERLI, MAINNET, RINKEBY},
+    };
+
+    #[test]
+    fn prepare_requests() {
+        for (from, to, requests) in [
+            (1000, 1000, vec![(1000, 1)]),
+            (1000, 3047, vec![(1000, 1024), (2024, 1024)]),
+            (1000, 3048, vec![(1000, 1024), (2024, 1024), (3048, 1)]),
+            (
+                1000,
+                5000,
+                vec![(1000, 1024), (2024, 1024), (3048, 1024), (4072, 929)],
+            ),
+        ] {
+            assert_eq!(
+                HeaderDownload::prepare_requests(from.into(), to.into())
+                    .into_iter()
+                    .collect::<BTreeMap<_, _>>(),
+                requests
+                    .into_iter()
+                    .map(|(start, limit)| {
+                        let start = BlockNumber(start);
+                        (
+                            start,
+                            HeaderRequest {
+                                start: BlockId::Number(start),
+                                limit,
+                                ..Default::default()
+                            },
+                        )
+                    })
+                    .collect()
+            )
+        }
+    }
+
+    fn header_chain(numbers: impl IntoIterator<Item = u64>) -> Vec<BlockHeader> {
+        let mut parent_hash = H256::zero();
+        numbers
+            .into_iter()
+            .map(|number| {
+                let header = BlockHeader {
+                    parent_hash,
+                    number: BlockNumber(number),
+                    ..Default::default()
+                };
+                parent_hash = header.hash();
+                header
+            })
+            .collect()
+    }
+
+    #[test]
+    fn check_contiguous() {
+        let headers = header_chain(10..15);
+        assert_eq!(
+            HeaderDownload::check_contiguous(headers.clone())
+                .unwrap()
+                .into_iter()
+                .map(|(_, header)| header)
+                .collect::<Vec<_>>(),
+            headers
+        );
+
+        // Gap in block numbers.
+        assert!(HeaderDownload::check_contiguous(header_chain([10, 11, 13, 14])).is_err());
+
+        // Duplicate block number.
+        assert!(HeaderDownload::check_contiguous(header_chain([10, 11, 11, 12])).is_err());
+
+        // Block numbers are consecutive, but parent hash does not match.
+        let mut headers = header_chain(10..15);
+        headers[2].parent_hash = H256::repeat_byte(0xff);
+        assert!(HeaderDownload::check_contiguous(headers).is_err());
+    }
+
+    #[test]
+    fn reorg_unwind_point() {
+        for (prev_progress, rewind_depth, unwind_to) in
+            [(100, 1, 99), (100, 10, 90), (100, 0, 99), (5, 10, 0), (0, 1, 0)]
+        {
+            assert_eq!(
+                HeaderDownload::reorg_unwind_point(BlockNumber(prev_progress), rewind_depth),
+                BlockNumber(unwind_to)
+            );
+        }
+    }
+
+    #[test]
+    fn request_key() {
+        let requests = HeaderDownload::prepare_requests(BlockNumber(1), BlockNumber(3000));
+        let request_ids = DashMap::new();
+        request_ids.insert(7, BlockNumber(1));
+        // Same request re-sent under a new id.
+        request_ids.insert(3, BlockNumber(1025));
+        request_ids.insert(5, BlockNumber(1025));
+
+        // Responses arrive out of order, each resolves to the request that asked for it.
+        for (request_id, first) in [(5, 1025), (7, 1), (3, 1025)] {
+            let key = HeaderDownload::request_key(&request_ids, request_id, BlockNumber(first));
+            assert_eq!(key, Some(BlockNumber(first)));
+            assert!(requests.contains_key(&key.unwrap()));
+        }
+
+        // Echoing the id of a different request is rejected.
+        assert_eq!(
+            HeaderDownload::request_key(&request_ids, 7, BlockNumber(1025)),
+            None
+        );
+
+        // Unknown ids fall back to matching the first header number.
+        assert_eq!(
+            HeaderDownload::request_key(&request_ids, 42, BlockNumber(2049)),
+            Some(BlockNumber(2049))
+        );
+    }
+
+    #[test]
+    fn next_requests_limit() {
+        let requests = HeaderDownload::prepare_requests(BlockNumber(1), BlockNumber(10_000));
+        assert_eq!(requests.len(), 10);
+
+        let mut sent = Vec::new();
+        while !requests.is_empty() {
+            let in_flight = HeaderDownload::next_requests(&requests, 3);
+            assert!(in_flight.len() <= 3);
+
+            // Answer the first request of the batch.
+            let BlockId::Number(start) = in_flight[0].start else { unreachable!() };
+            requests.remove(&start);
+            sent.push(start);
+        }
+
+        assert_eq!(
+            sent,
+            (1..=10_000)
+                .step_by(HEADERS_UPPER_BOUND)
+                .map(BlockNumber)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn put_or_append_out_of_order() {
+        let db = crate::kv::new_mem_chaindata().unwrap();
+        let tx = db.begin_mutable().unwrap();
+
+        let mut cursor = tx.cursor(tables::CanonicalHeader).unwrap();
+        let mut last_key = cursor.last().unwrap().map(|(k, _)| k);
+        for n in [1, 2, 3, 7, 5, 8, 8] {
+            put_or_append(
+                &mut cursor,
+                &mut last_key,
+                BlockNumber(n),
+                H256::repeat_byte(n as u8),
+            )
+            .unwrap();
+        }
+        assert_eq!(last_key, Some(BlockNumber(8)));
+
+        assert_eq!(
+            cursor
+                .walk(None)
+                .map(|res| res.unwrap().0 .0)
+                .collect::<Vec<_>>(),
+            vec![1, 2, 3, 5, 7, 8]
+        );
+    }
+
+    #[derive(Debug)]
+    struct RejectingConsensus;
+
+    impl Consensus for RejectingConsensus {
+        fn fork_choice_mode(&self) -> ForkChoiceMode {
+            ForkChoiceMode::Difficulty(Default::default())
+        }
+
+        fn pre_validate_block(
+            &self,
+            _: &crate::models::Block,
+            _: &dyn crate::BlockReader,
+        ) -> Result<(), DuoError> {
+            Ok(())
+        }
+
+        fn validate_block_header(
+            &self,
+            _: &BlockHeader,
+            _: &BlockHeader,
+            _: bool,
+        ) -> Result<(), DuoError> {
+            Err(ValidationError::WrongDifficulty.into())
+        }
+
+        fn finalize(
+            &self,
+            _: &BlockHeader,
+            _: &[BlockHeader],
+        ) -> anyhow::Result<Vec<FinalizationChange>> {
+            Ok(vec![])
+        }
+    }
+
+    /// [`test_stage`] on a mainnet node connected to `sentry`.
+    async fn mainnet_stage(sentry: &Arc<MockSentry>) -> HeaderDownload {
+        test_stage(
+            sentry
+                .node(ChainConfig::from(MAINNET.clone()))
+                .await
+                .unwrap(),
+        )
+    }
+
+    fn test_stage(node: Arc<Node>) -> HeaderDownload {
+        HeaderDownload::new(
+            node,
+            Arc::new(RejectingConsensus),
+            HeaderDownloadOptions {
+                partial_final_slice: false,
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn checks_options() {
+        let sentry = MockSentry::new([]);
+        let node = sentry
+            .node(ChainConfig::from(MAINNET.clone()))
+            .await
+            .unwrap();
+        let new = |options: HeaderDownloadOptions| {
+            HeaderDownload::new(node.clone(), Arc::new(RejectingConsensus), options)
+        };
+
+        let stage = new(Default::default()).unwrap();
+        assert_eq!(stage.size_limits, HeaderSizeLimits::for_chain(&node.config));
+
+        for options in [
+            HeaderDownloadOptions {
+                upper_bound: BlockNumber(0),
+                ..Default::default()
+            },
+            HeaderDownloadOptions {
+                max_in_flight_requests: 0,
+                ..Default::default()
+            },
+            HeaderDownloadOptions {
+                max_peers: Some(0),
+                ..Default::default()
+            },
+            HeaderDownloadOptions {
+                save_batch: 0,
+                ..Default::default()
+            },
+        ] {
+            assert!(new(options).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn trusted_checkpoints() {
+        let mut stage = mainnet_stage(&MockSentry::new([])).await;
+
+        let mut chain = header_chain(0..=5).into_iter();
+        let genesis = chain.next().unwrap();
+        let headers = chain
+            .map(|header| (header.hash(), header))
+            .collect::<Vec<_>>();
+
+        // Without checkpoints every header goes through consensus, which rejects it.
+        assert_eq!(
+            stage.validate_sequentially(&genesis, &headers),
+            Err((0, headers[0].0, FaultClass::InvalidHeader))
+        );
+
+        // Headers up to the checkpoint are only checked for linkage.
+        stage
+            .options
+            .trusted_checkpoints
+            .insert(BlockNumber(3), headers[2].0);
+        assert_eq!(
+            stage.validate_sequentially(&genesis, &headers),
+            Err((2, headers[3].0, FaultClass::InvalidHeader))
+        );
+
+        // A header that does not match its checkpoint is rejected.
+        stage
+            .options
+            .trusted_checkpoints
+            .insert(BlockNumber(3), H256::repeat_byte(0xab));
+        assert_eq!(
+            stage.validate_sequentially(&genesis, &headers),
+            Err((1, headers[2].0, FaultClass::BadLinkage))
+        );
+    }
+
+    #[test]
+    fn json_progress_reporter() {
+        let mut reporter = JsonProgressReporter::new(Vec::new());
+        for pending_requests in [2, 1] {
+            reporter
+                .report(&HeaderDownloadProgress {
+                    start: BlockNumber(1),
+                    end: BlockNumber(2048),
+                    total_requests: 2,
+                    pending_requests,
+                    blocks_per_sec: None,
+                    eta: None,
+                    buffered_bytes: 1024,
+                    bytes_downloaded: 4096,
+                    elapsed: Duration::from_secs(3),
+                })
+                .unwrap();
+        }
+
+        let output = String::from_utf8(reporter.writer).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        for (line, pending_requests) in lines.into_iter().zip([2, 1]) {
+            let v = serde_json::from_str::<serde_json::Value>(line).unwrap();
+            assert_eq!(v["phase"], "headers");
+            assert_eq!(v["start"], 1);
+            assert_eq!(v["end"], 2048);
+            assert_eq!(v["total_requests"], 2);
+            assert_eq!(v["pending_requests"], pending_requests);
+            assert_eq!(v["buffered_bytes"], 1024);
+            assert_eq!(v["bytes_downloaded"], 4096);
+            assert_eq!(v["elapsed"]["secs"], 3);
+            assert!(v["ts"].as_u64().unwrap() > 0);
+        }
+    }
+
+    #[test]
+    fn download_rate_estimate() {
+        let estimator = DownloadRateEstimator::default();
+        let started_at = Instant::now();
+        let at = |secs| started_at + Duration::from_secs(secs);
+
+        // Warming up at 1000 blocks per second.
+        assert_eq!(estimator.record_at(at(0), 0), None);
+        for secs in 1..5 {
+            assert_eq!(estimator.record_at(at(secs), 1000), None);
+        }
+        let rate = estimator.record_at(at(5), 1000).unwrap();
+        assert!((rate - 1000.0).abs() < 1e-9, "{rate}");
+
+        // The estimate follows a slowdown gradually.
+        let mut prev = rate;
+        for secs in 6..=300 {
+            let rate = estimator.record_at(at(secs), 100).unwrap();
+            assert!(rate < prev && rate > 100.0, "{rate} after {prev}");
+            prev = rate;
+        }
+        assert!(prev < 101.0);
+
+        // Responses in the same instant count towards the next sample.
+        assert_eq!(estimator.record_at(at(300), 100), Some(prev));
+        assert!(estimator.record_at(at(301), 100).unwrap() > prev);
+
+        assert_eq!(
+            DownloadRateEstimator::eta(100.0, 1000),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(DownloadRateEstimator::eta(0.0, 1000), None);
+    }
+
+    #[test]
+    fn retry_backoff_grows_geometrically() {
+        let backoff = RetryBackoff {
+            base: Duration::from_secs(1),
+            max: Duration::from_secs(30),
+            multiplier: 2,
+            jitter: 0.0,
+            max_attempts: None,
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+
+        assert_eq!(
+            (0..8)
+                .map(|attempt| backoff.delay(attempt, &mut rng).as_secs())
+                .collect::<Vec<_>>(),
+            vec![1, 2, 4, 8, 16, 30, 30, 30]
+        );
+        assert_eq!(backoff.delay(u32::MAX, &mut rng), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn retry_backoff_jitter() {
+        let backoff = RetryBackoff {
+            base: Duration::from_secs(10),
+            max: Duration::from_secs(100),
+            multiplier: 3,
+            jitter: 0.5,
+            max_attempts: None,
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for attempt in 0..5 {
+            let unjittered = std::cmp::min(
+                Duration::from_secs(10 * 3_u64.pow(attempt)),
+                Duration::from_secs(100),
+            );
+            for _ in 0..100 {
+                let delay = backoff.delay(attempt, &mut rng);
+                assert!(delay <= unjittered);
+                assert!(delay >= unjittered / 2);
+            }
+        }
+    }
+
+    #[test]
+    fn retry_attempts_reset_on_progress() {
+        let mut attempts = RetryAttempts::new(3000);
+
+        // No answers, the delay grows.
+        assert!(!attempts.advance(3000, Some(3)));
+        assert!(!attempts.advance(3000, Some(3)));
+        assert_eq!(attempts.attempt, 2);
+
+        // Any answered request starts over from the base delay.
+        assert!(!attempts.advance(2000, Some(3)));
+        assert_eq!(attempts.attempt, 0);
+
+        // Too many rounds without progress.
+        assert!(!attempts.advance(2000, Some(3)));
+        assert!(!attempts.advance(2000, Some(3)));
+        assert!(attempts.advance(2000, Some(3)));
+        assert_eq!(attempts.attempt, 0);
+
+        // Unlimited.
+        for attempt in 1..=100 {
+            assert!(!attempts.advance(2000, None));
+            assert_eq!(attempts.attempt, attempt);
+        }
+    }
+
+    #[test]
+    fn forward_set_target_block() {
+        for (
+            (prev_progress, increment, upper_bound, chain_tip),
+            (expected_target, expected_reached_tip),
+        ) in [
+            (
+                (10_000, Some(1_000_000), 90_000, 2_000_000),
+                (100_000, false),
+            ),
+            ((10_000, Some(10_000), 90_000, 2_000_000), (20_000, false)),
+            ((10_000, Some(10_000), 90_000, 15_000), (15_000, true)),
+            ((10_000, None, 90_000, 2_000_000), (100_000, false)),
+            ((10_000, None, 90_000, 30_000), (30_000, true)),
+            ((10_000, None, 200_000, 2_000_000), (210_000, false)),
+            ((10_000, Some(1_000_000), 5_000, 2_000_000), (15_000, false)),
+            ((10_000, None, 0, 2_000_000), (10_001, false)),
+        ] {
+            assert_eq!(
+                HeaderDownload::forward_set_target_block(
+                    BlockNumber(prev_progress),
+                    increment.map(BlockNumber),
+                    BlockNumber(upper_bound),
+                    BlockNumber(chain_tip)
+                ),
+                (BlockNumber(expected_target), expected_reached_tip)
+            );
+        }
+    }
+
+    #[test]
+    fn replay_saved_headers() {
+        let db = crate::kv::new_mem_chaindata().unwrap();
+        let tx = db.begin_mutable().unwrap();
+
+        let mut chain = header_chain(0..=5);
+        // Block #2 no longer attaches to its parent.
+        chain[2].parent_hash = H256::repeat_byte(0xff);
+        for header in chain {
+            // Block #3 is not saved.
+            if header.number == 3 {
+                continue;
+            }
+            let hash = header.hash();
+            tx.set(tables::CanonicalHeader, header.number, hash)
+                .unwrap();
+            tx.set(tables::Header, (header.number, hash), header)
+                .unwrap();
+        }
+
+        let config = ChainConfig::from(MAINNET.clone());
+        let replayer = HeaderReplayer {
+            consensus: Arc::new(RejectingConsensus),
+            size_limits: HeaderSizeLimits::for_chain(&config),
+            config,
+        };
+        let results = replayer
+            .replay(&tx, BlockNumberRange::new(BlockNumber(0), BlockNumber(6)))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(results.len(), 7);
+        // Genesis and the header after the gap have no parent to be checked against.
+        assert!(matches!(results[0], ReplayedHeader::Valid { number, .. } if number == 0));
+        assert!(matches!(
+            results[1],
+            ReplayedHeader::Invalid {
+                error: DuoError::Validation(ValidationError::WrongDifficulty),
+                ..
+            }
+        ));
+        assert!(matches!(
+            results[2],
+            ReplayedHeader::Invalid {
+                error: DuoError::Validation(ValidationError::UnknownParent { .. }),
+                ..
+            }
+        ));
+        assert!(matches!(results[3], ReplayedHeader::Missing(number) if number == 3));
+        assert!(matches!(results[4], ReplayedHeader::Valid { number, .. } if number == 4));
+        assert!(matches!(
+            results[5],
+            ReplayedHeader::Invalid {
+                error: DuoError::Validation(ValidationError::WrongDifficulty),
+                ..
+            }
+        ));
+        assert!(matches!(results[6], ReplayedHeader::Missing(number) if number == 6));
+    }
+
+    #[test]
+    fn default_fault_policy() {
+        let policy = FaultPolicy::default();
+        assert_eq!(policy.action(FaultClass::Timeout), FaultAction::Retry);
+        for class in [
+            FaultClass::MalformedResponse,
+            FaultClass::InvalidSeal,
+            FaultClass::InvalidHeader,
+            FaultClass::BadLinkage,
+        ] {
+            assert_eq!(policy.action(class), FaultAction::PenalizeAndRetry);
+            assert_eq!(class.penalty(), PenaltyReason::BadHeader);
+        }
+    }
+
+    #[test]
+    fn frontier_sync_status() {
+        let frontier = |min_block, max_block, saved_up_to| HeaderDownloadFrontier {
+            min_block: BlockNumber(min_block),
+            max_block: BlockNumber(max_block),
+            saved_up_to: BlockNumber(saved_up_to),
+        };
+
+        assert_eq!(
+            frontier(101, 200, 100).sync_status(),
+            Some(HeaderSyncStatus {
+                starting_block: BlockNumber(100),
+                current_block: BlockNumber(100),
+                highest_block: BlockNumber(200),
+            })
+        );
+        assert_eq!(frontier(101, 200, 200).sync_status(), None);
+        assert_eq!(HeaderDownloadFrontier::default().sync_status(), None);
+    }
+
+    #[tokio::test]
+    async fn rehydrates_spilled_headers() {
+        let chain = header_chain(0..=1500);
+        let sentry = MockSentry::new([H512::repeat_byte(1)]);
+        sentry.serve_chain(chain.clone());
+
+        // Left behind by a run that crashed after answering the first request, in the middle
+        // of spilling the second one.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("headers-spill");
+        HeaderSpill::new(&path, u64::MAX)
+            .append(chain[1..=1024].to_vec())
+            .unwrap();
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&[0xf9, 0xff])
+            .unwrap();
+
+        let spill = Arc::new(HeaderSpill::new(&path, u64::MAX));
+        let mut stage = mainnet_stage(&sentry).await;
+        stage
+            .options
+            .trusted_checkpoints
+            .insert(BlockNumber(1500), chain[1500].hash());
+        stage.options.spill = Some(spill.clone());
+
+        let headers = stage
+            .download_headers(
+                Default::default(),
+                &chain[0],
+                BlockNumber(1),
+                BlockNumber(1500),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            headers
+                .into_iter()
+                .map(|(_, header)| header)
+                .collect::<Vec<_>>(),
+            chain[1..].to_vec()
+        );
+
+        // Only the headers that were not spilled are requested from peers.
+        let requests = sentry.requests_received();
+        assert!(!requests.is_empty());
+        for request in requests {
+            assert_eq!(request.params.start, BlockId::Number(BlockNumber(1025)));
+        }
+
+        // The torn write is gone and the new segment is spilled after the old one.
+        assert_eq!(
+            spill.load().unwrap(),
+            vec![chain[1..=1024].to_vec(), chain[1025..].to_vec()]
+        );
+
+        stage.clear_spill();
+        assert!(spill.load().unwrap().is_empty());
+    }
+
+    fn extend_chain(
+        parent: &BlockHeader,
+        difficulties: impl IntoIterator<Item = u64>,
+    ) -> Vec<(H256, BlockHeader)> {
+        let mut parent = (parent.hash(), parent.number);
+        difficulties
+            .into_iter()
+            .map(|difficulty| {
+                let header = BlockHeader {
+                    parent_hash: parent.0,
+                    number: parent.1 + 1_u8,
+                    difficulty: U256::from(difficulty),
+                    ..Default::default()
+                };
+                let hash = header.hash();
+                parent = (hash, header.number);
+                (hash, header)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn save_total_difficulty() {
+        let stage = mainnet_stage(&MockSentry::new([])).await;
+
+        let db = crate::kv::new_mem_chaindata().unwrap();
+        let tx = db.begin_mutable().unwrap();
+        let genesis = BlockHeader {
+            difficulty: U256::from(5_u64),
+            ..Default::default()
+        };
+        tx.set(tables::CanonicalHeader, BlockNumber(0), genesis.hash())
+            .unwrap();
+        tx.set(
+            tables::HeadersTotalDifficulty,
+            (BlockNumber(0), genesis.hash()),
+            genesis.difficulty,
+        )
+        .unwrap();
+
+        // The second batch continues from where the first one ended.
+        let first = extend_chain(&genesis, [1, 2, 3]);
+        assert_eq!(
+            stage
+                .save_headers(&tx, BlockNumber(0), genesis.hash(), first.clone())
+                .unwrap(),
+            BlockNumber(3)
+        );
+        let second = extend_chain(&first[2].1, [4, 5]);
+        assert_eq!(
+            stage
+                .save_headers(&tx, BlockNumber(3), first[2].0, second)
+                .unwrap(),
+            BlockNumber(5)
+        );
+
+        let total_difficulty = |number| td::read_canonical(&tx, BlockNumber(number)).unwrap();
+        for (number, expected) in [(0, 5), (1, 6), (2, 8), (3, 11), (4, 15), (5, 20)] {
+            assert_eq!(total_difficulty(number), Some(U256::from(expected as u64)));
+        }
+
+        // After unwinding to #1 a competing branch accumulates on top of #1, not on top of the
+        // entries left behind by the abandoned one.
+        for number in 2..=5 {
+            tx.del(tables::CanonicalHeader, BlockNumber(number), None)
+                .unwrap();
+        }
+        let fork = extend_chain(&first[0].1, [100]);
+        assert_eq!(
+            stage
+                .save_headers(&tx, BlockNumber(1), first[0].0, fork)
+                .unwrap(),
+            BlockNumber(2)
+        );
+        assert_eq!(total_difficulty(2), Some(U256::from(106_u64)));
+        assert_eq!(total_difficulty(3), None);
+    }
+
+    #[tokio::test]
+    async fn save_in_batches() {
+        let mut stage = mainnet_stage(&MockSentry::new([])).await;
+        stage.options.save_batch = 2;
+
+        let db = crate::kv::new_mem_chaindata().unwrap();
+        let tx = db.begin_mutable().unwrap();
+        let genesis = BlockHeader::default();
+        tx.set(
+            tables::HeadersTotalDifficulty,
+            (BlockNumber(0), genesis.hash()),
+            genesis.difficulty,
+        )
+        .unwrap();
+
+        // Two full batches and a partial one, all within the same transaction.
+        let headers = extend_chain(&genesis, [1, 2, 3, 4, 5]);
+        assert_eq!(
+            stage
+                .save_headers(&tx, BlockNumber(0), genesis.hash(), headers.clone())
+                .unwrap(),
+            BlockNumber(5)
+        );
+        let mut td = genesis.difficulty;
+        for (hash, header) in headers {
+            td += header.difficulty;
+            assert_eq!(
+                tx.get(tables::HeaderNumber, hash).unwrap(),
+                Some(header.number)
+            );
+            assert_eq!(
+                tx.get(tables::CanonicalHeader, header.number).unwrap(),
+                Some(hash)
+            );
+            assert_eq!(
+                tx.get(tables::HeadersTotalDifficulty, (header.number, hash))
+                    .unwrap(),
+                Some(td)
+            );
+            assert_eq!(
+                tx.get(tables::Header, (header.number, hash)).unwrap(),
+                Some(header)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn verify_after_write() {
+        let mut stage = mainnet_stage(&MockSentry::new([])).await;
+        stage.options.verify_after_write = true;
+
+        let db = crate::kv::new_mem_chaindata().unwrap();
+        let tx = db.begin_mutable().unwrap();
+        let genesis = BlockHeader::default();
+        tx.set(
+            tables::HeadersTotalDifficulty,
+            (BlockNumber(0), genesis.hash()),
+            genesis.difficulty,
+        )
+        .unwrap();
+
+        let headers = extend_chain(&genesis, [1, 2, 3]);
+        assert_eq!(
+            stage
+                .save_headers(&tx, BlockNumber(0), genesis.hash(), headers.clone())
+                .unwrap(),
+            BlockNumber(3)
+        );
+
+        // Stored under a key that is not its hash, as a broken encoder would leave it.
+        let mut corrupt = extend_chain(&headers[2].1, [4, 5]);
+        corrupt[1].0 = H256::repeat_byte(0xab);
+        let e = stage
+            .save_headers(&tx, BlockNumber(3), headers[2].0, corrupt)
+            .unwrap_err();
+        assert!(e.to_string().contains("#5"), "{e}");
+        assert!(matches!(
+            HeaderDownload::save_error(e),
+            StageError::Internal(_)
+        ));
+
+        // Contention clears up, so the stage is rebuilt and tried again.
+        assert!(matches!(
+            HeaderDownload::save_error(::mdbx::Error::Busy.into()),
+            StageError::Recoverable(_)
+        ));
+    }
+
+    fn write_header_file(path: &std::path::Path, headers: &[BlockHeader]) {
+        let mut data = Vec::new();
+        for header in headers {
+            fastrlp::Encodable::encode(header, &mut data);
+        }
+        std::fs::write(path, data).unwrap();
+    }
+
+    #[test]
+    fn header_file_ranges() {
+        let chain = header_chain(0..=20);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("headers");
+        write_header_file(&path, &chain[5..]);
+
+        let file = HeaderFile::open(&path).unwrap();
+        let read = |start, end| {
+            file.read(BlockNumber(start), BlockNumber(end))
+                .unwrap()
+                .into_iter()
+                .map(|header| header.number.0)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(read(1, 7), vec![5, 6, 7]);
+        assert_eq!(read(8, 9), vec![8, 9]);
+        // Skipping ahead and starting over.
+        assert_eq!(read(15, 16), vec![15, 16]);
+        assert_eq!(read(6, 6), vec![6]);
+        assert_eq!(read(19, 30), vec![19, 20]);
+        assert_eq!(read(21, 30), Vec::<u64>::new());
+
+        std::fs::write(&path, [0x80]).unwrap();
+        assert!(HeaderFile::open(&path)
+            .unwrap()
+            .read(BlockNumber(0), BlockNumber(1))
+            .is_err());
+    }
+
+    #[test]
+    fn exports_headers() {
+        let db = crate::kv::new_mem_chaindata().unwrap();
+        let tx = db.begin_mutable().unwrap();
+        let chain = header_chain(0..=5);
+        for header in chain.iter().filter(|header| header.number != 3) {
+            let hash = header.hash();
+            tx.set(tables::CanonicalHeader, header.number, hash)
+                .unwrap();
+            tx.set(tables::Header, (header.number, hash), header.clone())
+                .unwrap();
+        }
+        let range = |start, end| BlockNumberRange::new(BlockNumber(start), BlockNumber(end));
+
+        // The RLP export reads back as a header file.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("headers");
+        let file = std::fs::File::create(&path).unwrap();
+        assert_eq!(
+            export_headers(&tx, range(0, 2), HeaderExportFormat::Rlp, false, file).unwrap(),
+            3
+        );
+        assert_eq!(
+            HeaderFile::open(&path)
+                .unwrap()
+                .read(BlockNumber(0), BlockNumber(5))
+                .unwrap(),
+            chain[..=2].to_vec()
+        );
+
+        let mut json = Vec::new();
+        assert_eq!(
+            export_headers(&tx, range(1, 5), HeaderExportFormat::Json, true, &mut json).unwrap(),
+            4
+        );
+        let lines = String::from_utf8(json)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines
+                .iter()
+                .map(|line| line["number"].clone())
+                .collect::<Vec<_>>(),
+            [1, 2, 4, 5]
+                .into_iter()
+                .map(|number| serde_json::to_value(BlockNumber(number)).unwrap())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            lines[2]["hash"],
+            serde_json::to_value(chain[4].hash()).unwrap()
+        );
+
+        // Without skipping, the gap fails the export.
+        let e = export_headers(
+            &tx,
+            range(1, 5),
+            HeaderExportFormat::Json,
+            false,
+            std::io::sink(),
+        )
+        .unwrap_err();
+        assert!(e.to_string().contains("#3"), "{e}");
+
+        // So does a gap at the end of the range.
+        let e = export_headers(
+            &tx,
+            range(4, 6),
+            HeaderExportFormat::Rlp,
+            false,
+            std::io::sink(),
+        )
+        .unwrap_err();
+        assert!(
+            e.to_string().contains("#6, after exporting 2 headers"),
+            "{e}"
+        );
+    }
+
+    #[tokio::test]
+    async fn takes_headers_from_file() {
+        let chain = header_chain(0..=1500);
+        let sentry = MockSentry::new([H512::repeat_byte(1)]);
+        sentry.serve_chain(chain.clone());
+
+        // The file ends in the middle of the second request.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("headers");
+        write_header_file(&path, &chain[..1200]);
+
+        let mut stage = mainnet_stage(&sentry).await;
+        stage
+            .options
+            .trusted_checkpoints
+            .insert(BlockNumber(1500), chain[1500].hash());
+        stage.options.header_file = Some(Arc::new(HeaderFile::open(&path).unwrap()));
+
+        let headers = stage
+            .download_headers(
+                Default::default(),
+                &chain[0],
+                BlockNumber(1),
+                BlockNumber(1500),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            headers
+                .into_iter()
+                .map(|(_, header)| header)
+                .collect::<Vec<_>>(),
+            chain[1..].to_vec()
+        );
+
+        let requests = sentry.requests_received();
+        assert!(!requests.is_empty());
+        for request in requests {
+            assert_eq!(request.params.start, BlockId::Number(BlockNumber(1025)));
+        }
+    }
+
+    /// Rules shared by all engines, which include the London base fee.
+    #[derive(Debug)]
+    struct BaseRules(ConsensusEngineBase);
+
+    impl Consensus for BaseRules {
+        fn fork_choice_mode(&self) -> ForkChoiceMode {
+            ForkChoiceMode::Difficulty(Default::default())
+        }
+
+        fn pre_validate_block(
+            &self,
+            _: &crate::models::Block,
+            _: &dyn crate::BlockReader,
+        ) -> Result<(), DuoError> {
+            Ok(())
+        }
+
+        fn validate_block_header(
+            &self,
+            header: &BlockHeader,
+            parent: &BlockHeader,
+            with_future_timestamp_check: bool,
+        ) -> Result<(), DuoError> {
+            self.0
+                .validate_block_header(header, parent, with_future_timestamp_check)
+        }
+
+        fn finalize(
+            &self,
+            _: &BlockHeader,
+            _: &[BlockHeader],
+        ) -> anyhow::Result<Vec<FinalizationChange>> {
+            Ok(vec![])
+        }
+    }
+
+    /// Counts seal checks, rejecting the seal of a single block.
+    #[derive(Debug, Default)]
+    struct CountingSeals {
+        checked: AtomicUsize,
+        bad_block: BlockNumber,
+    }
+
+    impl Consensus for CountingSeals {
+        fn fork_choice_mode(&self) -> ForkChoiceMode {
+            ForkChoiceMode::Difficulty(Default::default())
+        }
+
+        fn pre_validate_block(
+            &self,
+            _: &crate::models::Block,
+            _: &dyn crate::BlockReader,
+        ) -> Result<(), DuoError> {
+            Ok(())
+        }
+
+        fn validate_block_header(
+            &self,
+            _: &BlockHeader,
+            _: &BlockHeader,
+            _: bool,
+        ) -> Result<(), DuoError> {
+            Ok(())
+        }
+
+        fn finalize(
+            &self,
+            _: &BlockHeader,
+            _: &[BlockHeader],
+        ) -> anyhow::Result<Vec<FinalizationChange>> {
+            Ok(vec![])
+        }
+
+        fn needs_parallel_validation(&self) -> bool {
+            true
+        }
+
+        fn validate_header_parallel(&self, header: &BlockHeader) -> Result<(), DuoError> {
+            self.checked.fetch_add(1, Ordering::SeqCst);
+            if header.number == self.bad_block {
+                return Err(ValidationError::WrongDifficulty.into());
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn caches_seal_verification() {
+        let consensus = Arc::new(CountingSeals {
+            bad_block: BlockNumber(1500),
+            ..Default::default()
+        });
+        let mut stage = mainnet_stage(&MockSentry::new([])).await;
+        stage.consensus = consensus.clone();
+        stage.options.seal_cache = Some(Arc::new(SealVerificationCache::new(8)));
+
+        // Slices #1 to #1023, and #1024 to #2000 with a bad seal at #1500.
+        let headers = extend_chain(&BlockHeader::default(), 1..=2000);
+        let rejected = Err((1498, headers[1499].0));
+        assert_eq!(stage.validate_parallel(&headers), rejected);
+        let checked = consensus.checked.load(Ordering::SeqCst);
+        assert!(checked >= 1500);
+
+        // Identical slices are not checked again, including the failing one.
+        assert_eq!(stage.validate_parallel(&headers), rejected);
+        assert_eq!(stage.validate_parallel(&headers[..1023]), Ok(()));
+        assert_eq!(consensus.checked.load(Ordering::SeqCst), checked);
+
+        // A slice that differs in a single header is.
+        let mut changed = headers[..1023].to_vec();
+        changed[1022].1.timestamp += 1;
+        changed[1022].0 = changed[1022].1.hash();
+        assert_eq!(stage.validate_parallel(&changed), Ok(()));
+        assert_eq!(consensus.checked.load(Ordering::SeqCst), checked + 1023);
+    }
+
+    #[tokio::test]
+    async fn syncs_without_seal_verification() {
+        let mut chain_spec = MAINNET.clone();
+        chain_spec.params.chain_id = ChainId(1337);
+        let config = ChainConfig::from(chain_spec)
+            .without_seal_verification()
+            .unwrap();
+        let node = MockSentry::new([]).node(config).await.unwrap();
+        let consensus = Arc::new(CountingSeals {
+            bad_block: BlockNumber(1500),
+            ..Default::default()
+        });
+        let mut stage = test_stage(node);
+        stage.consensus = consensus.clone();
+
+        // Bad seals go through unchecked.
+        let genesis = BlockHeader::default();
+        let headers = extend_chain(&genesis, 1..=2000);
+        assert_eq!(stage.validate_parallel(&headers), Ok(()));
+        assert_eq!(consensus.checked.load(Ordering::SeqCst), 0);
+        assert_eq!(stage.validate_sequentially(&genesis, &headers), Ok(()));
+
+        // Linkage is still checked.
+        let mut broken = headers.clone();
+        broken.remove(1000);
+        assert_eq!(
+            stage.validate_sequentially(&genesis, &broken),
+            Err((999, broken[1000].0, FaultClass::BadLinkage))
+        );
+
+        // On clique, headers without a seal in their extra data and signed faster than the
+        // period go through too, while base rules still apply.
+        let mut chain_spec = RINKEBY.clone();
+        chain_spec.params.chain_id = ChainId(1337);
+        let genesis = BlockHeader {
+            gas_limit: 10_000_000,
+            ..Default::default()
+        };
+        let mut parent = genesis.clone();
+        let headers = (1..=10)
+            .map(|number| {
+                let header = BlockHeader {
+                    parent_hash: parent.hash(),
+                    number: BlockNumber(number),
+                    timestamp: number,
+                    ..genesis.clone()
+                };
+                parent = header.clone();
+                (header.hash(), header)
+            })
+            .collect::<Vec<_>>();
+
+        for (config, expected) in [
+            (
+                ChainConfig::from(chain_spec.clone()),
+                Err((0, headers[0].0, FaultClass::InvalidSeal)),
+            ),
+            (
+                ChainConfig::from(chain_spec.clone())
+                    .without_seal_verification()
+                    .unwrap(),
+                Ok(()),
+            ),
+        ] {
+            let node = MockSentry::new([]).node(config).await.unwrap();
+            stage = test_stage(node);
+            stage.consensus = engine_factory(None, chain_spec.clone(), None)
+                .unwrap()
+                .into();
+            assert_eq!(stage.validate_sequentially(&genesis, &headers), expected);
+        }
+
+        // Equal timestamps break the base rules.
+        let mut stuck = headers;
+        stuck[5].1.timestamp = stuck[4].1.timestamp;
+        stuck[5].0 = stuck[5].1.hash();
+        assert_eq!(
+            stage.validate_sequentially(&genesis, &stuck[..6]),
+            Err((4, stuck[5].0, FaultClass::InvalidHeader))
+        );
+    }
+
+    #[tokio::test]
+    async fn fork_activates_mid_batch() {
+        let london = BlockNumber(3);
+        let mut stage = mainnet_stage(&MockSentry::new([])).await;
+        stage.consensus = Arc::new(BaseRules(ConsensusEngineBase::new(
+            ChainId(1),
+            Some(london),
+            None,
+        )));
+
+        let genesis = BlockHeader {
+            gas_limit: 10_000_000,
+            ..Default::default()
+        };
+        let chain = |base_fees: [Option<u64>; 5]| {
+            let mut parent = genesis.clone();
+            (1..)
+                .zip(base_fees)
+                .map(|(number, base_fee)| {
+                    let header = BlockHeader {
+                        parent_hash: parent.hash(),
+                        number: BlockNumber(number),
+                        timestamp: number,
+                        // London doubles the gas limit.
+                        gas_limit: if BlockNumber(number) >= london {
+                            20_000_000
+                        } else {
+                            10_000_000
+                        },
+                        base_fee_per_gas: base_fee.map(U256::from),
+                        ..Default::default()
+                    };
+                    parent = header.clone();
+                    (header.hash(), header)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // A single batch, and request, covering blocks on both sides of the fork.
+        let headers = chain([
+            None,
+            None,
+            Some(1_000_000_000),
+            Some(875_000_000),
+            Some(765_625_000),
+        ]);
+        assert_eq!(stage.validate_sequentially(&genesis, &headers), Ok(()));
+
+        // Base fee before the fork.
+        let headers = chain([
+            None,
+            Some(1_000_000_000),
+            Some(1_000_000_000),
+            Some(875_000_000),
+            Some(765_625_000),
+        ]);
+        assert_eq!(
+            stage.validate_sequentially(&genesis, &headers),
+            Err((0, headers[1].0, FaultClass::InvalidHeader))
+        );
+
+        // No base fee after the fork.
+        let headers = chain([None; 5]);
+        assert_eq!(
+            stage.validate_sequentially(&genesis, &headers),
+            Err((1, headers[2].0, FaultClass::InvalidHeader))
+        );
+    }
+
+    #[tokio::test]
+    async fn syncs_chain_from_mock_sentry() {
+        let chain = header_chain(0..=1000);
+        let genesis = &chain[0];
+        let tip = &chain[1000];
+
+        let sentry = MockSentry::new([1, 2, 3].map(H512::repeat_byte));
+        sentry.serve_chain(chain.clone());
+        let mut stage = mainnet_stage(&sentry).await;
+        stage
+            .node
+            .chain_tip_sender
+            .send((tip.number, tip.hash()))
+            .unwrap();
+
+        let db = crate::kv::new_mem_chaindata().unwrap();
+        let mut tx = db.begin_mutable().unwrap();
+        tx.set(
+            tables::Header,
+            (genesis.number, genesis.hash()),
+            genesis.clone(),
+        )
+        .unwrap();
+        tx.set(tables::CanonicalHeader, genesis.number, genesis.hash())
+            .unwrap();
+        tx.set(
+            tables::HeadersTotalDifficulty,
+            (genesis.number, genesis.hash()),
+            genesis.difficulty,
+        )
+        .unwrap();
+
+        stage
+            .options
+            .trusted_checkpoints
+            .insert(tip.number, tip.hash());
+        let (saved_progress_sender, saved_progress) = watch::channel(BlockNumber(0));
+        stage.saved_progress_sender = Some(saved_progress_sender);
+
+        let output = stage
+            .execute(
+                &mut tx,
+                StageInput {
+                    restarted: false,
+                    first_started_at: (std::time::Instant::now(), None),
+                    previous_stage: None,
+                    stage_progress: Some(BlockNumber(0)),
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            output,
+            ExecOutput::Progress {
+                stage_progress: tip.number,
+                done: true,
+                reached_tip: true,
+            }
+        );
+        assert_eq!(*saved_progress.borrow(), tip.number);
+
+        for header in &chain[1..] {
+            let hash = header.hash();
+            assert_eq!(
+                tx.get(tables::CanonicalHeader, header.number).unwrap(),
+                Some(hash)
+            );
+            assert_eq!(
+                tx.get(tables::HeaderNumber, hash).unwrap(),
+                Some(header.number)
+            );
+            assert_eq!(
+                tx.get(tables::Header, (header.number, hash)).unwrap(),
+                Some(header.clone())
+            );
+        }
+        assert_eq!(
+            tx.cursor(tables::CanonicalHeader)
+                .unwrap()
+                .last()
+                .unwrap()
+                .map(|(number, _)| number),
+            Some(tip.number)
+        );
+    }
+
+    #[test]
+    fn commit_cadence() {
+        let headers = extend_chain(&BlockHeader::default(), 1..=3000);
+        assert_eq!(CommitCadence::Slices(1).split_point(&headers), 1024);
+        assert_eq!(CommitCadence::Slices(2).split_point(&headers), 2048);
+        assert_eq!(CommitCadence::Slices(3).split_point(&headers), 3000);
+
+        let header_size = fastrlp::Encodable::length(&headers[0].1) as u64;
+        assert_eq!(CommitCadence::Bytes(header_size).split_point(&headers), 1);
+        assert_eq!(
+            CommitCadence::Bytes(header_size * 10 + 1).split_point(&headers),
+            11
+        );
+        assert_eq!(CommitCadence::Bytes(u64::MAX).split_point(&headers), 3000);
+    }
+
+    #[tokio::test]
+    async fn commits_in_parts() {
+        let chain = header_chain(0..=2500);
+        let genesis = chain[0].clone();
+        let tip = &chain[2500];
+
+        let sentry = MockSentry::new([H512::repeat_byte(1)]);
+        sentry.serve_chain(chain.clone());
+        let mut stage = mainnet_stage(&sentry).await;
+        stage
+            .node
+            .chain_tip_sender
+            .send((tip.number, tip.hash()))
+            .unwrap();
+
+        let db = crate::kv::new_mem_chaindata().unwrap();
+        let tx = db.begin_mutable().unwrap();
+        tx.set(
+            tables::Header,
+            (genesis.number, genesis.hash()),
+            genesis.clone(),
+        )
+        .unwrap();
+        tx.set(tables::CanonicalHeader, genesis.number, genesis.hash())
+            .unwrap();
+        tx.set(
+            tables::HeadersTotalDifficulty,
+            (genesis.number, genesis.hash()),
+            genesis.difficulty,
+        )
+        .unwrap();
+        tx.commit().unwrap();
+
+        stage
+            .options
+            .trusted_checkpoints
+            .insert(tip.number, tip.hash());
+        stage.options.commit_every = Some(CommitCadence::Slices(1));
+
+        // The batch is downloaded at once and saved one slice per invocation, each of which
+        // is visible to readers once committed.
+        let mut stage_progress = BlockNumber(0);
+        for (expected_progress, done) in [(1024, false), (2048, false), (2500, true)] {
+            let mut tx = db.begin_mutable().unwrap();
+            let output = stage
+                .execute(
+                    &mut tx,
+                    StageInput {
+                        restarted: stage_progress > BlockNumber(0),
+                        first_started_at: (std::time::Instant::now(), None),
+                        previous_stage: None,
+                        stage_progress: Some(stage_progress),
+                    },
+                )
+                .await
+                .unwrap();
+            assert_eq!(
+                output,
+                ExecOutput::Progress {
+                    stage_progress: BlockNumber(expected_progress),
+                    done,
+                    reached_tip: done,
+                }
+            );
+            tx.commit().unwrap();
+            stage_progress = BlockNumber(expected_progress);
+
+            let reader = db.begin().unwrap();
+            assert_eq!(
+                reader
+                    .cursor(tables::CanonicalHeader)
+                    .unwrap()
+                    .last()
+                    .unwrap(),
+                Some((
+                    BlockNumber(expected_progress),
+                    chain[expected_progress as usize].hash()
+                ))
+            );
+        }
+        assert!(stage.unsaved.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_headers() {
+        let peer = H512::repeat_byte(1);
+        let sentry = MockSentry::new([peer]);
+        let stage = mainnet_stage(&sentry).await;
+        assert_eq!(stage.size_limits.max_extra_data, Some(32));
+        assert_eq!(
+            HeaderSizeLimits::for_chain(&ChainConfig::from(GOERLI.clone())).max_extra_data,
+            None
+        );
+
+        let mut headers = header_chain(1..=4);
+        assert!(stage.size_limits.check(&headers).is_ok());
+        headers[2].extra_data = vec![0; 33].into();
+        assert!(stage.size_limits.check(&headers).is_err());
+
+        // A clique signer list may be longer, but not longer than a whole header.
+        let unbounded = HeaderSizeLimits::default();
+        assert!(unbounded.check(&headers).is_ok());
+        headers[2].extra_data = vec![0; HeaderSizeLimits::DEFAULT_MAX_HEADER_BYTES].into();
+        assert!(unbounded.check(&headers).is_err());
+        assert!(HeaderSizeLimits {
+            max_header_bytes: usize::MAX,
+            max_response_bytes: HeaderSizeLimits::DEFAULT_MAX_HEADER_BYTES,
+            max_extra_data: None,
+        }
+        .check(&headers)
+        .is_err());
+
+        let requests = DashMap::new();
+        requests.insert(
+            BlockNumber(1),
+            HeaderRequest {
+                start: BlockId::Number(BlockNumber(1)),
+                limit: 4,
+                ..Default::default()
+            },
+        );
+        let session = SessionState::new(&stage, requests, Default::default());
+        HeaderDownload::handle_response(
+            &session,
+            peer,
+            BlockHeaders {
+                request_id: 0,
+                headers,
+            },
+        )
+        .await
+        .unwrap();
+
+        // Nothing is buffered, the request stays pending and the peer is penalized.
+        assert!(session.graph.lock().is_empty());
+        assert!(session.requests.contains_key(&BlockNumber(1)));
+        assert_eq!(sentry.penalties_issued(), vec![peer]);
+    }
+
+    #[tokio::test]
+    async fn rejects_shifted_headers() {
+        let peer = H512::repeat_byte(1);
+        let sentry = MockSentry::new([peer]);
+        let stage = mainnet_stage(&sentry).await;
+
+        // Consistent on its own, linkage and count are right, but off by one.
+        let shifted = HeaderDownload::check_contiguous(header_chain(2..=5)).unwrap();
+        assert!(HeaderDownload::check_numbers(&shifted, BlockNumber(2)).is_ok());
+        let e = HeaderDownload::check_numbers(&shifted, BlockNumber(1)).unwrap_err();
+        assert!(
+            e.to_string().starts_with("header 0 of the segment")
+                && e.to_string().ends_with("declares #2 instead of #1"),
+            "{e}"
+        );
+
+        let requests = DashMap::new();
+        for start in [1, 5] {
+            requests.insert(
+                BlockNumber(start),
+                HeaderRequest {
+                    start: BlockId::Number(BlockNumber(start)),
+                    limit: 4,
+                    ..Default::default()
+                },
+            );
+        }
+        let session = SessionState::new(&stage, requests, Default::default());
+        session.request_ids.insert(7, BlockNumber(1));
+        HeaderDownload::handle_response(
+            &session,
+            peer,
+            BlockHeaders {
+                request_id: 7,
+                headers: header_chain(2..=5),
+            },
+        )
+        .await
+        .unwrap();
+
+        // Nothing is buffered, the request stays pending and the peer is penalized.
+        assert!(session.graph.lock().is_empty());
+        assert_eq!(session.requests.len(), 2);
+        assert_eq!(sentry.penalties_issued(), vec![peer]);
+    }
+
+    #[test]
+    fn slice_boundaries() {
+        let chain = header_chain(0..=12);
+        let boundary = |headers: &[BlockHeader]| SliceBoundary {
+            end: headers[headers.len() - 1].number,
+            parent_hash: headers[0].parent_hash,
+            last_hash: headers[headers.len() - 1].hash(),
+            peer_id: None,
+        };
+
+        let slices = SliceBoundaries::default();
+        assert_eq!(slices.link(BlockNumber(1), boundary(&chain[1..=4])), vec![]);
+        assert_eq!(
+            slices.link(BlockNumber(9), boundary(&chain[9..=12])),
+            vec![]
+        );
+
+        // Fits between both neighbours.
+        assert_eq!(slices.link(BlockNumber(5), boundary(&chain[5..=8])), vec![]);
+
+        // Contradicts both neighbours, all three are forgotten.
+        let fork = header_chain(5..=8);
+        assert_eq!(
+            slices.link(BlockNumber(5), boundary(&fork)),
+            vec![
+                (BlockNumber(1), boundary(&chain[1..=4])),
+                (BlockNumber(9), boundary(&chain[9..=12]))
+            ]
+        );
+        assert!(slices.slices.lock().is_empty());
+
+        // A slice not ending with the skeleton header contradicts the skeleton peer once.
+        let peer = H512::repeat_byte(1);
+        slices.set_skeleton([(BlockNumber(8), (chain[8].hash(), peer))].into());
+        assert_eq!(
+            slices.check_skeleton(BlockNumber(8), chain[8].hash()),
+            Ok(())
+        );
+        assert_eq!(
+            slices.check_skeleton(BlockNumber(8), fork[3].hash()),
+            Err(peer)
+        );
+        assert_eq!(
+            slices.check_skeleton(BlockNumber(8), fork[3].hash()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn skeleton_requests() {
+        assert_eq!(
+            HeaderDownload::skeleton_request(BlockNumber(1), BlockNumber(3500)),
+            Some(HeaderRequest {
+                start: BlockId::Number(BlockNumber(1024)),
+                limit: 3,
+                skip: 1023,
+                reverse: false,
+            })
+        );
+        assert_eq!(
+            HeaderDownload::skeleton_request(BlockNumber(1), BlockNumber(1000)),
+            None
+        );
+
+        let chain = header_chain(0..=3072);
+        let place = |request: &HeaderRequest, numbers: &[usize]| {
+            HeaderDownload::place_sparse(
+                request,
+                numbers
+                    .iter()
+                    .map(|&number| chain[number].clone())
+                    .collect(),
+            )
+            .map(|placed| {
+                placed
+                    .into_iter()
+                    .map(|(_, header)| header.number.0)
+                    .collect::<Vec<_>>()
+            })
+        };
+        let forward = HeaderRequest {
+            start: BlockId::Number(BlockNumber(1024)),
+            limit: 3,
+            skip: 1023,
+            reverse: false,
+        };
+        let backward = HeaderRequest {
+            start: BlockId::Number(BlockNumber(3072)),
+            reverse: true,
+            ..forward
+        };
+        assert_eq!(
+            place(&forward, &[1024, 2048, 3072]),
+            Some(vec![1024, 2048, 3072])
+        );
+        assert_eq!(
+            place(&backward, &[3072, 2048, 1024]),
+            Some(vec![3072, 2048, 1024])
+        );
+        // Short answers are fine, others are not.
+        assert_eq!(place(&forward, &[1024]), Some(vec![1024]));
+        assert_eq!(place(&forward, &[1024, 2047]), None);
+        assert_eq!(place(&backward, &[1024, 2048, 3072]), None);
+        assert_eq!(
+            place(
+                &HeaderRequest {
+                    limit: 1,
+                    ..forward
+                },
+                &[1024, 2048]
+            ),
+            None
+        );
+        assert_eq!(
+            place(
+                &HeaderRequest {
+                    start: BlockId::Hash(chain[1024].hash()),
+                    ..forward
+                },
+                &[1024]
+            ),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn downloads_with_skeleton() {
+        let chain = header_chain(0..=3072);
+        let sentry = MockSentry::new([H512::repeat_byte(1)]);
+        sentry.serve_chain(chain.clone());
+        let mut stage = mainnet_stage(&sentry).await;
+        stage
+            .options
+            .trusted_checkpoints
+            .insert(BlockNumber(3072), chain[3072].hash());
+        stage.options.skeleton = true;
+
+        let headers = stage
+            .download_headers(
+                Default::default(),
+                &chain[0],
+                BlockNumber(1),
+                BlockNumber(3072),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            headers
+                .into_iter()
+                .map(|(_, header)| header)
+                .collect::<Vec<_>>(),
+            chain[1..].to_vec()
+        );
 
-                        info!(
-                            "Received {} headers from peer {peer_id}",
-                            inner.headers.len()
-                        );
+        // The skeleton is requested first.
+        let requests = sentry.requests_received();
+        assert_eq!(requests[0].params.start, BlockId::Number(BlockNumber(1024)));
+        assert_eq!(requests[0].params.skip, 1023);
+        assert_eq!(requests[0].params.limit, 3);
+    }
 
-                        if is_bounded(inner.headers[0].number) {
-                            tasks.push(TaskGuard(tokio::task::spawn({
-                                let node = self.node.clone();
-                                let requests = requests.clone();
-                                let graph = fork_choice_graph.clone();
-                                let peer_map = peer_map.clone();
+    #[test]
+    fn slice_audit_log() {
+        let range = |start, end| BlockNumberRange::new(BlockNumber(start), BlockNumber(end));
+        let log = SliceAuditLog::new(5);
+        let t0 = Instant::now();
+        let at = |secs| t0 + Duration::from_secs(secs);
 
-                                async move {
-                                    Self::handle_response(
-                                        node, requests, graph, peer_map, peer_id, inner,
-                                    )
-                                    .await
-                                }
-                            })));
-                        }
-                    }
-                }
-            }
-        }
+        log.record_at(range(1, 1024), SliceStatus::Requested, at(0));
+        log.record_at(range(1025, 2048), SliceStatus::Requested, at(0));
+        log.record_at(range(1, 1024), SliceStatus::Received, at(1));
+        log.record_at(range(1, 1024), SliceStatus::Verified, at(2));
+        log.record_saved(BlockNumber(2048));
 
-        let took = Instant::now();
-        let mut headers = {
-            let mut graph = fork_choice_graph.lock();
-            let tail = if let Some(v) = graph.chain_head() {
-                v
-            } else {
-                info!("Difficulty graph failure, will unwind");
-                return Ok(None);
-            };
-            graph.backtrack(&tail)
+        let statuses = |history: Vec<SliceTransition>| {
+            history
+                .into_iter()
+                .map(|transition| transition.status)
+                .collect::<Vec<_>>()
         };
+        assert_eq!(
+            statuses(log.transition_history(range(1000, 1000))),
+            [
+                SliceStatus::Requested,
+                SliceStatus::Received,
+                SliceStatus::Verified,
+                SliceStatus::Saved
+            ]
+        );
+        // The second slice is stuck waiting for an answer.
+        let stuck = log.transition_history(range(2000, 3000));
+        assert_eq!(stuck.len(), 1);
+        assert_eq!(stuck[0].status, SliceStatus::Requested);
+        assert_eq!(stuck[0].at, at(0));
+        assert!(log.transition_history(range(3000, 4000)).is_empty());
 
-        if let Some(first) = headers.first() {
-            if prev_progress_header.hash() != first.1.parent_hash {
-                return Ok(None);
-            }
-        }
-
-        info!(
-            "Built canonical chain with={} headers, elapsed={:?}",
-            headers.len(),
-            took.elapsed()
+        // Saved slices are not recorded again, and the oldest transitions make room.
+        log.record_saved(BlockNumber(2048));
+        log.record_at(range(1025, 2048), SliceStatus::Requested, at(10));
+        assert_eq!(
+            statuses(log.transition_history(range(1, 2048))),
+            [
+                SliceStatus::Requested,
+                SliceStatus::Received,
+                SliceStatus::Verified,
+                SliceStatus::Saved,
+                SliceStatus::Requested
+            ]
         );
+    }
 
-        let cur_size = headers.len();
-        let took = Instant::now();
+    #[test]
+    fn slice_status_counts() {
+        let range = |start, end| BlockNumberRange::new(BlockNumber(start), BlockNumber(end));
+        let log = SliceAuditLog::new(6);
+        assert!(log.status_counts().is_empty());
 
-        if let Err((last_valid, invalid_hash)) =
-            self.validate_sequentially(prev_progress_header, &headers)
+        let t0 = Instant::now();
+        for (i, (start, end)) in [(1, 1024), (1025, 2048), (2049, 3072)]
+            .into_iter()
+            .enumerate()
         {
-            headers.truncate(last_valid);
-
-            if let Some(peer_id) = peer_map.get(&invalid_hash).map(|e| *e) {
-                self.node.penalize_peer(peer_id).await;
+            log.record_at(range(start, end), SliceStatus::Requested, t0);
+            if i < 2 {
+                log.record_at(range(start, end), SliceStatus::Received, t0);
             }
         }
+        log.record_at(range(1, 1024), SliceStatus::Verified, t0);
+        assert_eq!(
+            log.status_counts(),
+            HashMap::from([
+                (SliceStatus::Requested, 1),
+                (SliceStatus::Received, 1),
+                (SliceStatus::Verified, 1),
+            ])
+        );
 
-        if self.consensus.needs_parallel_validation() {
-            if let Err((last_valid, invalid_hash)) = self.validate_parallel(&headers) {
-                headers.truncate(last_valid);
+        // The first slice's earliest transitions make room, its latest one still counts.
+        log.record_saved(BlockNumber(1024));
+        assert_eq!(
+            log.status_counts(),
+            HashMap::from([
+                (SliceStatus::Requested, 1),
+                (SliceStatus::Received, 1),
+                (SliceStatus::Saved, 1),
+            ])
+        );
+    }
 
-                if let Some(peer_id) = peer_map.get(&invalid_hash).map(|e| *e) {
-                    self.node.penalize_peer(peer_id).await;
-                }
-            }
-        }
+    #[tokio::test]
+    async fn requeues_mismatched_slices() {
+        let peers = [H512::repeat_byte(1), H512::repeat_byte(2)];
+        let sentry = MockSentry::new(peers);
+        let stage = mainnet_stage(&sentry).await;
 
-        if cur_size == headers.len() {
-            info!(
-                "Seal verification took={:?} all headers are valid.",
-                took.elapsed()
-            );
-        } else {
-            info!(
-                "Seal verification took={:?} {} headers are invalidated.",
-                took.elapsed(),
-                cur_size - headers.len()
+        let chain = header_chain(0..=8);
+        // Does not attach to #4 of the chain.
+        let fork = header_chain(5..=8);
+
+        let requests = DashMap::new();
+        for start in [1, 5] {
+            requests.insert(
+                BlockNumber(start),
+                HeaderRequest {
+                    start: BlockId::Number(BlockNumber(start)),
+                    limit: 4,
+                    ..Default::default()
+                },
             );
         }
+        let session = SessionState::new(&stage, requests, Default::default());
+        let respond = |peer_id, headers: &[BlockHeader]| {
+            HeaderDownload::handle_response(
+                &session,
+                peer_id,
+                BlockHeaders {
+                    request_id: 0,
+                    headers: headers.to_vec(),
+                },
+            )
+        };
+        let pending = || {
+            let mut pending = session
+                .requests
+                .iter()
+                .map(|e| e.key().0)
+                .collect::<Vec<_>>();
+            pending.sort_unstable();
+            pending
+        };
 
-        Ok(Some(headers))
-    }
-
-    async fn handle_response(
-        node: Arc<Node>,
-        requests: Arc<DashMap<BlockNumber, HeaderRequest>>,
-        graph: Arc<Mutex<ForkChoiceGraph>>,
-        peer_map: Arc<DashMap<H256, H512>>,
-        peer_id: H512,
-        response: BlockHeaders,
-    ) {
-        let cur_size = response.headers.len();
-        debug!("Handling response from {peer_id} with {cur_size} headers");
-
-        match Self::check_contiguous(response.headers) {
-            Ok(headers) => {
-                let key = headers[0].1.number;
-                let last_hash = headers[headers.len() - 1].0;
-
-                let mut graph = graph.lock();
+        respond(peers[0], &chain[1..=4]).await.unwrap();
+        assert_eq!(pending(), vec![5]);
 
-                if let dashmap::mapref::entry::Entry::Occupied(entry) = requests.entry(key) {
-                    let limit = entry.get().limit as usize;
+        // Either side may be wrong, so both are requested again.
+        respond(peers[1], &fork).await.unwrap();
+        assert_eq!(pending(), vec![1, 5]);
+        assert!(sentry.penalties_issued().is_empty());
 
-                    if headers.len() == limit {
-                        entry.remove();
+        // A peer contradicting itself is penalized.
+        respond(peers[1], &chain[1..=4]).await.unwrap();
+        respond(peers[1], &fork).await.unwrap();
+        assert_eq!(pending(), vec![1, 5]);
+        assert_eq!(sentry.penalties_issued(), vec![peers[1]]);
 
-                        for (hash, header) in headers {
-                            graph.insert_with_hash(hash, header);
-                            peer_map.insert(hash, peer_id);
-                        }
-                    }
-                } else if !graph.contains(last_hash) {
-                    for (hash, header) in headers {
-                        graph.insert_with_hash(hash, header);
-                        peer_map.insert(hash, peer_id);
-                    }
-                }
-            }
-            Err(()) => {
-                warn!("Rejected discontiguous header segment from {peer_id}");
-                node.penalize_peer(peer_id).await
-            }
-        }
+        respond(peers[0], &chain[1..=4]).await.unwrap();
+        respond(peers[0], &chain[5..=8]).await.unwrap();
+        assert!(session.requests.is_empty());
     }
 
-    async fn update_head<'tx, E: EnvironmentKind>(
-        &self,
-        txn: &'tx mut MdbxTransaction<'_, RW, E>,
-        height: BlockNumber,
-    ) -> anyhow::Result<()> {
-        let hash = txn.get(tables::CanonicalHeader, height)?.unwrap();
-        let td = txn
-            .get(tables::HeadersTotalDifficulty, (height, hash))?
-            .unwrap();
-        let status = Status::new(height, hash, td);
-        self.node.update_chain_head(Some(status)).await;
-        Ok(())
-    }
+    #[tokio::test]
+    async fn stalled_download() {
+        let mut stage = mainnet_stage(&MockSentry::new([])).await;
+        stage.options.stall_timeout = Some(Duration::from_millis(200));
 
-    fn forward_set_target_block(
-        prev_progress: BlockNumber,
-        increment: Option<BlockNumber>,
-        chain_tip: BlockNumber,
-    ) -> (BlockNumber, bool) {
-        let max_increment = std::cmp::max(
+        // Nobody answers, so the session gives up instead of waiting forever.
+        tokio::time::pause();
+        let mut rejections = Vec::new();
+        let session = stage.download_session(
+            Default::default(),
+            &BlockHeader::default(),
             BlockNumber(1),
-            increment
-                .map(|v| std::cmp::min(v, STAGE_UPPER_BOUND))
-                .unwrap_or(STAGE_UPPER_BOUND),
+            BlockNumber(2048),
+            &mut rejections,
+        );
+        tokio::pin!(session);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(199), &mut session)
+                .await
+                .is_err()
+        );
+        tokio::time::advance(Duration::from_millis(1)).await;
+        let e = session.await.unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<StalledNoProgress>(),
+            Some(&StalledNoProgress {
+                block: BlockNumber(1),
+                peers: 0,
+                stalled_for: Duration::from_millis(200),
+            })
         );
-        let max_incremented_from_start = BlockNumber(prev_progress.0 + max_increment.0);
-
-        if max_incremented_from_start > chain_tip {
-            (chain_tip, true)
-        } else {
-            (max_incremented_from_start, false)
-        }
     }
 
-    fn prepare_requests(
-        starting_block: BlockNumber,
-        target: BlockNumber,
-    ) -> DashMap<BlockNumber, HeaderRequest> {
-        (starting_block..=target)
-            .step_by(HEADERS_UPPER_BOUND)
-            .map(|start| {
-                let limit = std::cmp::min(*target - *start + 1, HEADERS_UPPER_BOUND as u64);
+    #[tokio::test]
+    async fn reports_pending_requests() {
+        let chain = header_chain(0..=3072);
+        let sentry = MockSentry::new([H512::repeat_byte(1)]);
+        sentry.serve_chain(chain.clone());
+        let mut stage = mainnet_stage(&sentry).await;
+        stage
+            .options
+            .trusted_checkpoints
+            .insert(BlockNumber(3072), chain[3072].hash());
+        stage.options.max_in_flight_requests = 1;
+        stage.options.retry_backoff = RetryBackoff {
+            base: Duration::from_millis(50),
+            max: Duration::from_millis(50),
+            multiplier: 1,
+            jitter: 0.0,
+            max_attempts: None,
+        };
+        let (progress_sender, mut progress) = broadcast::channel(64);
+        stage.options.progress_sender = Some(progress_sender);
 
-                let request = HeaderRequest {
-                    start: BlockId::Number(start),
-                    limit,
-                    ..Default::default()
-                };
+        stage
+            .download_session(
+                Default::default(),
+                &chain[0],
+                BlockNumber(1),
+                BlockNumber(3072),
+                &mut Vec::new(),
+            )
+            .await
+            .unwrap();
 
-                (start, request)
-            })
-            .collect()
+        let mut pending = Vec::new();
+        let (mut bytes_downloaded, mut elapsed) = (0, Duration::ZERO);
+        while let Ok(event) = progress.try_recv() {
+            assert_eq!(event.total_requests, 3);
+            assert!(event.bytes_downloaded >= bytes_downloaded);
+            assert!(event.elapsed >= elapsed);
+            pending.push(event.pending_requests);
+            (bytes_downloaded, elapsed) = (event.bytes_downloaded, event.elapsed);
+        }
+        // Every header was received at least once.
+        let chain_bytes = chain[1..=3072]
+            .iter()
+            .map(|header| fastrlp::Encodable::length(header) as u64)
+            .sum::<u64>();
+        assert!(
+            bytes_downloaded >= chain_bytes,
+            "{bytes_downloaded} < {chain_bytes}"
+        );
+        // Each slice is reported as soon as its answer is handled. Re-sent requests may be
+        // answered twice.
+        pending.dedup();
+        assert_eq!(pending, [2, 1, 0]);
     }
 
-    #[inline]
-    fn check_contiguous(headers: Vec<BlockHeader>) -> Result<Vec<(H256, BlockHeader)>, ()> {
-        let headers = headers
-            .into_iter()
-            .map(|h| (h.hash(), h))
-            .collect::<Vec<_>>();
-        if headers.iter().skip(1).enumerate().any(|(i, (_, header))| {
-            header.parent_hash != headers[i].0 || header.number != headers[i].1.number + 1u8
-        }) {
-            return Err(());
+    #[tokio::test]
+    async fn dry_run_reports_rejections() {
+        let chain = header_chain(0..=2048);
+        let peer = H512::repeat_byte(1);
+        let sentry = MockSentry::new([peer]);
+        sentry.serve_chain(chain.clone());
+
+        let db = crate::kv::new_mem_chaindata().unwrap();
+        let tx = db.begin_mutable().unwrap();
+        for header in &chain {
+            let hash = header.hash();
+            tx.set(tables::CanonicalHeader, header.number, hash)
+                .unwrap();
+            tx.set(tables::Header, (header.number, hash), header.clone())
+                .unwrap();
         }
 
-        Ok(headers)
-    }
+        // Headers past the checkpoint are checked, and rejected, by the consensus engine.
+        let mut stage = mainnet_stage(&sentry).await;
+        stage
+            .options
+            .trusted_checkpoints
+            .insert(BlockNumber(1024), chain[1024].hash());
+        stage.options.upper_bound = BlockNumber(1024);
 
-    fn validate_sequentially<'a>(
-        &self,
-        mut parent_header: &'a BlockHeader,
-        headers: &'a [(H256, BlockHeader)],
-    ) -> Result<(), (usize, H256)> {
-        for (i, (hash, header)) in headers.iter().enumerate() {
-            let parent_hash = parent_header.hash();
-            if header.parent_hash != parent_hash || header.number != parent_header.number + 1_u8 {
-                warn!("Rejected bad block header ({hash:?}) because it doesn't attach to parent ({parent_hash:?}): {header:?} => {parent_header:?}");
-                return Err((i.saturating_sub(1), *hash));
+        let range = BlockNumberRange::new(BlockNumber(1), BlockNumber(2048));
+        let report = stage.dry_run(&tx, range).await.unwrap();
+        let rejection = HeaderRejection {
+            number: BlockNumber(1025),
+            hash: chain[1025].hash(),
+            class: FaultClass::InvalidHeader,
+            peer: Some(peer),
+        };
+        assert_eq!(
+            report,
+            DryRunReport {
+                range,
+                verified: 1024,
+                rejections: vec![rejection],
+                unattached: vec![],
             }
+        );
+        assert!(!report.is_clean());
+        assert_eq!(
+            report.to_string(),
+            format!(
+                "Dry run of #1..=#2048 verified 1024 headers, rejected 1: #1025/{:?} (InvalidHeader) from {peer:?}",
+                rejection.hash
+            )
+        );
 
-            if let Err(e) = self
-                .consensus
-                .validate_block_header(header, parent_header, false)
-            {
-                warn!("Rejected bad block header ({hash:?}) for reason {e:?}: {header:?}");
-                return Err((i.saturating_sub(1), *hash));
-            }
-            parent_header = header;
-        }
+        // Nothing is saved below the range.
+        assert!(stage
+            .dry_run(
+                &tx,
+                BlockNumberRange::new(BlockNumber(2050), BlockNumber(2060))
+            )
+            .await
+            .is_err());
+    }
 
-        Ok(())
+    #[tokio::test]
+    async fn fork_policy() {
+        let mut stage = mainnet_stage(&MockSentry::new([])).await;
+        let hash = H256::repeat_byte(0xab);
+
+        stage.options.fork_policy = ForkPolicy::FailFast;
+        let Err(StageError::Internal(e)) = stage.on_fork(BlockNumber(100), hash) else {
+            panic!("fork did not fail the stage");
+        };
+        assert_eq!(
+            e.downcast_ref::<ForkDetected>(),
+            Some(&ForkDetected {
+                number: BlockNumber(100),
+                hash
+            })
+        );
+
+        stage.options.fork_policy = ForkPolicy::ReAnchor { max_rewind: 10 };
+        assert_eq!(
+            stage.on_fork(BlockNumber(100), hash).unwrap(),
+            ExecOutput::Unwind {
+                unwind_to: BlockNumber(90)
+            }
+        );
     }
 
-    fn validate_parallel(&self, headers: &[(H256, BlockHeader)]) -> Result<(), (usize, H256)> {
-        let valid_till = AtomicUsize::new(0);
-
-        headers.par_iter().enumerate().for_each(|(i, (_, header))| {
-            if self.consensus.validate_header_parallel(header).is_err() {
-                let mut value = valid_till.load(Ordering::SeqCst);
-                while i < value {
-                    if valid_till.compare_exchange(value, i, Ordering::SeqCst, Ordering::SeqCst)
-                        == Ok(value)
-                    {
-                        break;
-                    } else {
-                        value = valid_till.load(Ordering::SeqCst);
-                    }
+    #[tokio::test]
+    async fn pauses_and_resumes_download() {
+        let chain = header_chain(0..=3072);
+        let sentry = MockSentry::new([H512::repeat_byte(1)]);
+        let mut stage = mainnet_stage(&sentry).await;
+        stage
+            .options
+            .trusted_checkpoints
+            .insert(BlockNumber(3072), chain[3072].hash());
+        stage.options.max_in_flight_requests = 1;
+        stage.options.retry_backoff = RetryBackoff {
+            base: Duration::from_millis(50),
+            max: Duration::from_millis(50),
+            multiplier: 1,
+            jitter: 0.0,
+            max_attempts: None,
+        };
+
+        // Pause when answering the first request.
+        let pause = stage.options.pause.clone();
+        sentry.set_header_responder({
+            let chain = chain.clone();
+            let pause = pause.clone();
+            move |_, request| {
+                let BlockId::Number(start) = request.params.start else {
+                    return None;
+                };
+                if start == BlockNumber(1) {
+                    pause.pause();
                 }
+                Some(
+                    chain
+                        .iter()
+                        .skip(start.0 as usize)
+                        .take(request.params.limit as usize)
+                        .cloned()
+                        .collect(),
+                )
             }
         });
 
-        let valid_till = valid_till.load(Ordering::SeqCst);
-        if valid_till != 0 {
-            Err((valid_till - 1, headers[valid_till].0))
-        } else {
-            Ok(())
+        let download = tokio::spawn({
+            let stage = Arc::new(stage);
+            let genesis = chain[0].clone();
+            async move {
+                stage
+                    .download_headers(
+                        Default::default(),
+                        &genesis,
+                        BlockNumber(1),
+                        BlockNumber(3072),
+                    )
+                    .await
+            }
+        });
+
+        let mut paused = pause.0.subscribe();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !*paused.borrow_and_update() {
+                paused.changed().await.unwrap();
+            }
+        })
+        .await
+        .unwrap();
+
+        // Unanswered requests would have been re-sent several times by now.
+        tokio::time::pause();
+        let sent = sentry.requests_received().len();
+        tokio::time::advance(Duration::from_millis(300)).await;
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
         }
+        assert_eq!(sentry.requests_received().len(), sent);
+        assert!(!download.is_finished());
+
+        tokio::time::resume();
+        pause.resume();
+        let headers = tokio::time::timeout(Duration::from_secs(10), download)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            headers
+                .into_iter()
+                .map(|(_, header)| header)
+                .collect::<Vec<_>>(),
+            chain[1..].to_vec()
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[tokio::test]
+    async fn accepts_short_final_slice() {
+        let chain = header_chain(0..=1500);
+        let sentry = MockSentry::new([H512::repeat_byte(1)]);
 
-    #[test]
-    fn prepare_requests() {
-        for (from, to, requests) in [
-            (1000, 1000, vec![(1000, 1)]),
-            (1000, 3047, vec![(1000, 1024), (2024, 1024)]),
-            (1000, 3048, vec![(1000, 1024), (2024, 1024), (3048, 1)]),
-            (
-                1000,
-                5000,
-                vec![(1000, 1024), (2024, 1024), (3048, 1024), (4072, 929)],
+        let mut stage = mainnet_stage(&sentry).await;
+        stage
+            .options
+            .trusted_checkpoints
+            .insert(BlockNumber(1500), chain[1500].hash());
+        stage.options.partial_final_slice = true;
+        stage.options.retry_backoff = RetryBackoff {
+            base: Duration::from_millis(50),
+            max: Duration::from_millis(50),
+            multiplier: 1,
+            jitter: 0.0,
+            max_attempts: None,
+        };
+
+        sentry.set_header_responder({
+            let chain = chain.clone();
+            move |_, request| {
+                let BlockId::Number(start) = request.params.start else {
+                    return None;
+                };
+                Some(
+                    chain
+                        .iter()
+                        .skip(start.0 as usize)
+                        .take(request.params.limit as usize)
+                        .cloned()
+                        .collect(),
+                )
+            }
+        });
+
+        // The last slice, #1025 to #2048, is answered with headers up to #1500 only.
+        let (headers, chain_end) = tokio::time::timeout(
+            Duration::from_secs(10),
+            stage.download_session(
+                Default::default(),
+                &chain[0],
+                BlockNumber(1),
+                BlockNumber(2048),
+                &mut Vec::new(),
             ),
-        ] {
-            assert_eq!(
-                HeaderDownload::prepare_requests(from.into(), to.into())
-                    .into_iter()
-                    .collect::<BTreeMap<_, _>>(),
-                requests
-                    .into_iter()
-                    .map(|(start, limit)| {
-                        let start = BlockNumber(start);
-                        (
-                            start,
-                            HeaderRequest {
-                                start: BlockId::Number(start),
-                                limit,
-                                ..Default::default()
-                            },
-                        )
-                    })
-                    .collect()
-            )
-        }
-    }
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(chain_end, Some(BlockNumber(1500)));
+        assert_eq!(
+            headers
+                .unwrap()
+                .into_iter()
+                .map(|(_, header)| header)
+                .collect::<Vec<_>>(),
+            chain[1..].to_vec()
+        );
 
-    #[test]
-    fn forward_set_target_block() {
-        for ((prev_progress, increment, chain_tip), (expected_target, expected_reached_tip)) in [
-            ((10_000, Some(1_000_000), 2_000_000), (100_000, false)),
-            ((10_000, Some(10_000), 2_000_000), (20_000, false)),
-            ((10_000, Some(10_000), 15_000), (15_000, true)),
-            ((10_000, None, 2_000_000), (100_000, false)),
-            ((10_000, None, 30_000), (30_000, true)),
-        ] {
-            assert_eq!(
-                HeaderDownload::forward_set_target_block(
-                    BlockNumber(prev_progress),
-                    increment.map(BlockNumber),
-                    BlockNumber(chain_tip)
-                ),
-                (BlockNumber(expected_target), expected_reached_tip)
-            );
-        }
+        // Below the known tip the same answer is a peer holding back headers.
+        stage
+            .node
+            .chain_tip_sender
+            .send((BlockNumber(3000), H256::repeat_byte(0xff)))
+            .unwrap();
+        assert!(tokio::time::timeout(
+            Duration::from_millis(500),
+            stage.download_session(
+                Default::default(),
+                &chain[0],
+                BlockNumber(1),
+                BlockNumber(2048),
+                &mut Vec::new(),
+            ),
+        )
+        .await
+        .is_err());
     }
 }
@@ -1,3 +1,6 @@
+//! Staged sync stages. Each stage logs under its module path as the tracing target, e.g.
+//! `akula::stages::bodies`, and larger stages nest targets for their parts under it.
+
 mod block_hashes;
 mod bodies;
 mod call_trace_index;
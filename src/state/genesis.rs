@@ -194,6 +194,10 @@ mod tests {
                 let genesis_header = genesis.header(&genesis.initial_state());
                 assert_eq!(genesis_header.hash(), hash);
             }
+            assert_eq!(
+                crate::models::ChainConfig::from((*chainspec).clone()).genesis_hash,
+                hash
+            );
 
             let db = new_mem_chaindata().unwrap();
             let tx = db.begin_mutable().unwrap();